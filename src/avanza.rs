@@ -1,11 +1,13 @@
 use rust_decimal::{Decimal, dec};
 use serde::Deserialize;
-use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
 
 use crate::pp;
+use crate::table::Table;
 use crate::types::{Currency, dec_from_swe_num_opt};
-use crate::{ProgressSender, yahoo_symbol};
+use crate::{
+    ProgressSender,
+    symbols::{self, ResolverChain},
+};
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -35,6 +37,11 @@ struct AvanzaTransaction {
     resultat: Option<Decimal>,
 }
 
+pub const DELIMITER: u8 = b';';
+
+/// Columns that identify the header row of an Avanza export.
+pub const HEADER: &[&str] = &["Datum", "Konto", "Typ av transaktion"];
+
 #[derive(Debug, Deserialize, PartialEq)]
 enum AvanzaType {
     Köp,
@@ -47,44 +54,55 @@ enum AvanzaType {
 }
 
 pub async fn convert(
-    input: &std::path::Path,
-    writer: &mut pp::CsvWriter,
+    table: Table,
+    symbols: &ResolverChain<'_>,
+    writer: &mut pp::Output,
     mut progress: ProgressSender,
 ) -> anyhow::Result<()> {
-    let yahoo = yahoo_symbol::Yahoo::new_with_progress(progress.clone());
-    let mut read = File::open(&input).await.map(BufReader::new)?;
-    let mut line_buf = String::new();
-    let mut num_lines = 0;
-    while read.read_line(&mut line_buf).await? != 0 {
-        line_buf.clear();
-        num_lines += 1;
-    }
-    drop(line_buf);
-    // Assuming one header line
-    num_lines -= 1;
-    progress.total(num_lines).await;
+    let isins = table
+        .deserialize::<AvanzaTransaction>()
+        .filter_map(|line| line.ok()?.isin);
+    symbols.prefetch(isins, &mut progress).await;
+    progress.total(table.len()).await;
     progress.count(0).await;
-    let mut reader = csv::ReaderBuilder::new().delimiter(b';').from_path(input)?;
     let mut read_records = 0;
-    for line in reader.deserialize() {
+    for (i, line) in table.deserialize().enumerate() {
         let line: AvanzaTransaction = line?;
+        writer.set_source_line(table.line(i));
 
+        let avanza_account = prefix_account(&line.konto);
         let mut security_name = line.vardepapper_beskrivning.clone();
         let mut y_symbol = None;
-        if let Some(isin) = &line.isin {
-            let y_securities = yahoo.isin_to_symbols(isin).await?;
-            let security = y_securities
-                .iter()
-                .find(|s| s.exchange == "STO")
-                .or(y_securities.first());
-            if let Some(security) = security {
-                y_symbol = Some(security.symbol.clone());
-                if security_name.is_none() {
-                    security_name = Some(security.name.clone());
+        let mut wkn = None;
+        let holds_security = match line.typ_av_transaktion {
+            AvanzaType::Köp | AvanzaType::Sälj | AvanzaType::Värdepappersöverföring => true,
+            AvanzaType::Övrigt => line.antal.is_some(),
+            _ => false,
+        };
+        match (&line.isin, &line.vardepapper_beskrivning) {
+            (Some(isin), _) => {
+                if let Some(security) = symbols
+                    .resolve(isin, &avanza_account, Some(line.instrumentvaluta.as_str()))
+                    .await?
+                {
+                    y_symbol = Some(security.symbol);
+                    if security_name.is_none() {
+                        security_name = Some(security.name);
+                    }
                 }
             }
+            // Delisted stocks, certificates and old funds can lack ISIN
+            (None, Some(name)) if holds_security => {
+                // The WKN is given whether a ticker is found or not, so that the security is the same
+                // in conversions made offline and online
+                wkn = Some(symbols::synthetic_id(name));
+                y_symbol = symbols
+                    .resolve_name(name, &avanza_account, Some(line.instrumentvaluta.as_str()))
+                    .await
+                    .map(|security| security.symbol);
+            }
+            _ => (),
         }
-        let avanza_account = prefix_account(&line.konto);
         let transaction = match line.typ_av_transaktion {
             AvanzaType::Köp | AvanzaType::Sälj => {
                 let exch: Option<Decimal> = line.valutakurs.map(|v| (dec!(1.0) / v).round_dp(4));
@@ -106,7 +124,7 @@ pub async fn convert(
                     taxes: None,
                     shares: line.antal.as_ref().map(Decimal::abs),
                     isin: line.isin,
-                    wkn: None,
+                    wkn,
                     ticker_symbol: y_symbol,
                     security_name,
                     note: None,
@@ -143,7 +161,7 @@ pub async fn convert(
                     taxes: None,
                     shares: line.antal,
                     isin: line.isin,
-                    wkn: None,
+                    wkn,
                     ticker_symbol: y_symbol,
                     security_name,
                     note: line.vardepapper_beskrivning,
@@ -181,7 +199,7 @@ pub async fn convert(
                     taxes: None,
                     shares: line.antal,
                     isin: line.isin,
-                    wkn: None,
+                    wkn,
                     ticker_symbol: y_symbol,
                     security_name,
                     note: line.vardepapper_beskrivning,
@@ -200,6 +218,16 @@ pub async fn convert(
                     },
                     value: line.belopp.unwrap(),
                     transaction_currency: line.transaktionsvaluta,
+                    gross_amount: None,
+                    currency_gross_amount: None,
+                    exchange_rate: None,
+                    fees: None,
+                    taxes: None,
+                    shares: None,
+                    isin: None,
+                    wkn: None,
+                    ticker_symbol: None,
+                    security_name: None,
                     note: line.vardepapper_beskrivning,
                 }))
             }
@@ -218,6 +246,16 @@ pub async fn convert(
                     },
                     value: line.belopp.unwrap(),
                     transaction_currency: line.transaktionsvaluta,
+                    gross_amount: None,
+                    currency_gross_amount: None,
+                    exchange_rate: None,
+                    fees: None,
+                    taxes: None,
+                    shares: None,
+                    isin: None,
+                    wkn: None,
+                    ticker_symbol: None,
+                    security_name: None,
                     note: line.vardepapper_beskrivning,
                 }))
             }
@@ -232,6 +270,16 @@ pub async fn convert(
                 },
                 value: line.belopp.unwrap(),
                 transaction_currency: line.transaktionsvaluta,
+                gross_amount: None,
+                currency_gross_amount: None,
+                exchange_rate: None,
+                fees: None,
+                taxes: None,
+                shares: None,
+                isin: None,
+                wkn: None,
+                ticker_symbol: None,
+                security_name: None,
                 note: line.vardepapper_beskrivning,
             })),
         };
@@ -243,11 +291,6 @@ pub async fn convert(
         progress.count(read_records).await;
     }
 
-    // Correct the total, now that we now the number of records, in case the original
-    // guess was wrong.
-    progress.total(read_records).await;
-
-    yahoo.save_cache().await?;
     Ok(())
 }
 
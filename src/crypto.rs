@@ -0,0 +1,712 @@
+//! Ledgers from the crypto exchanges Safello, Coinbase and Kraken.
+//!
+//! Crypto assets have no ISIN. They are given Yahoo crypto tickers such as `BTC-EUR`, quoted in the
+//! fiat currency the asset was traded in.
+//!
+//! Rewards, sends and receives are often listed without a fiat value. Their value is then estimated
+//! from the price of the nearest trade of the same asset in the file, and they are skipped if there
+//! is none.
+
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::ProgressSender;
+use crate::pp;
+use crate::table::Table;
+use crate::types::{Currency, dec_from_num_opt, dec_from_swe_num_opt};
+
+pub const SAFELLO_DELIMITER: u8 = b';';
+pub const COINBASE_DELIMITER: u8 = b',';
+pub const KRAKEN_DELIMITER: u8 = b',';
+
+/// Columns that identify the header row of a Safello export.
+pub const SAFELLO_HEADER: &[&str] = &["Datum", "Typ", "Kryptovaluta", "Antal"];
+/// Columns that identify the header row of a Coinbase transaction history.
+pub const COINBASE_HEADER: &[&str] = &["Timestamp", "Transaction Type", "Asset"];
+/// Columns that identify the header row of a Kraken ledger.
+pub const KRAKEN_HEADER: &[&str] = &["txid", "refid", "time", "type", "asset", "amount"];
+
+/// Fiat currency used for Kraken tickers until a trade shows which currency the account uses.
+const DEFAULT_FIAT: &str = "EUR";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Exchange {
+    Safello,
+    Coinbase,
+    Kraken,
+}
+
+impl Exchange {
+    fn account(self) -> &'static str {
+        match self {
+            Exchange::Safello => "Safello",
+            Exchange::Coinbase => "Coinbase",
+            Exchange::Kraken => "Kraken",
+        }
+    }
+}
+
+/// How staking rewards are represented in Portfolio Performance.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StakingAs {
+    /// Inbound delivery of the rewarded coins
+    #[default]
+    Delivery,
+    /// Dividend, followed by a buy of the rewarded coins for the same amount
+    Dividend,
+}
+
+#[derive(Debug, Deserialize)]
+struct SafelloTransaction {
+    #[serde(rename = "Datum")]
+    date: String,
+    #[serde(rename = "Typ")]
+    kind: String,
+    #[serde(rename = "Kryptovaluta")]
+    asset: String,
+    #[serde(rename = "Antal", deserialize_with = "dec_from_swe_num_opt")]
+    quantity: Option<Decimal>,
+    #[serde(rename = "Belopp", deserialize_with = "dec_from_swe_num_opt")]
+    amount: Option<Decimal>,
+    #[serde(rename = "Avgift", deserialize_with = "dec_from_swe_num_opt", default)]
+    fee: Option<Decimal>,
+    #[serde(rename = "Valuta")]
+    currency: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoinbaseTransaction {
+    #[serde(rename = "Timestamp")]
+    timestamp: String,
+    #[serde(rename = "Transaction Type")]
+    kind: String,
+    #[serde(rename = "Asset")]
+    asset: String,
+    #[serde(rename = "Quantity Transacted", deserialize_with = "dec_from_num_opt")]
+    quantity: Option<Decimal>,
+    #[serde(rename = "Spot Price Currency")]
+    currency: String,
+    #[serde(rename = "Subtotal", deserialize_with = "dec_from_num_opt")]
+    subtotal: Option<Decimal>,
+    #[serde(
+        rename = "Total (inclusive of fees and/or spread)",
+        deserialize_with = "dec_from_num_opt"
+    )]
+    total: Option<Decimal>,
+    #[serde(rename = "Fees and/or Spread", deserialize_with = "dec_from_num_opt")]
+    fees: Option<Decimal>,
+    #[serde(rename = "Notes", default)]
+    notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KrakenEntry {
+    refid: String,
+    time: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    subtype: Option<String>,
+    asset: String,
+    #[serde(deserialize_with = "dec_from_num_opt")]
+    amount: Option<Decimal>,
+    #[serde(deserialize_with = "dec_from_num_opt")]
+    fee: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Buy,
+    Sell,
+    Reward,
+    /// Sent to a wallet outside the exchange
+    Send,
+    /// Received from a wallet outside the exchange
+    Receive,
+    /// Network fee paid in the crypto asset
+    NetworkFee,
+    Deposit,
+    Withdrawal,
+}
+
+impl Kind {
+    /// The kind in the log.
+    fn label(self) -> &'static str {
+        match self {
+            Kind::Buy => "köp",
+            Kind::Sell => "försäljning",
+            Kind::Reward => "belöning",
+            Kind::Send => "skickat",
+            Kind::Receive => "mottaget",
+            Kind::NetworkFee => "nätverksavgift",
+            Kind::Deposit => "insättning",
+            Kind::Withdrawal => "uttag",
+        }
+    }
+}
+
+/// A ledger event in any of the exchange formats.
+struct CryptoEvent {
+    /// Line in the file
+    line: u64,
+    date: String,
+    kind: Kind,
+    asset: String,
+    quantity: Decimal,
+    /// Value in fiat currency, including fees
+    value: Option<Decimal>,
+    /// Fees in fiat currency
+    fees: Option<Decimal>,
+    currency: String,
+    note: Option<String>,
+}
+
+pub async fn convert(
+    exchange: Exchange,
+    table: Table,
+    staking_as: StakingAs,
+    writer: &mut pp::Output,
+    mut progress: ProgressSender,
+) -> anyhow::Result<()> {
+    progress.total(table.len()).await;
+    progress.count(0).await;
+    let events = match exchange {
+        Exchange::Safello => safello_events(&table, &mut progress).await?,
+        Exchange::Coinbase => coinbase_events(&table, &mut progress).await?,
+        Exchange::Kraken => kraken_events(&table, &mut progress).await?,
+    };
+    let account = exchange.account();
+    for (i, event) in events.iter().enumerate() {
+        let value = match event.kind {
+            // Fiat is valued in itself, unless it is another currency than the account's
+            Kind::Deposit | Kind::Withdrawal if event.asset == event.currency => event.quantity,
+            Kind::Deposit | Kind::Withdrawal => event.value.unwrap_or(event.quantity),
+            _ => match event.value {
+                Some(value) => value,
+                None => match estimate_value(&events, i) {
+                    Some((value, trade_date)) => {
+                        progress
+                            .log(format!(
+                                "Uppskattar värdet av {} {} {} {} till {value} {} från kursen {trade_date}",
+                                event.date,
+                                event.kind.label(),
+                                event.quantity, event.asset, event.currency
+                            ))
+                            .await;
+                        value
+                    }
+                    None => {
+                        progress
+                            .log(format!(
+                                "Hoppar över {} {} {} {}, som saknar värde och inte har någon kurs i filen",
+                                event.date,
+                                event.kind.label(),
+                                event.quantity, event.asset
+                            ))
+                            .await;
+                        continue;
+                    }
+                },
+            },
+        };
+        writer.set_source_line(event.line);
+        for t in to_transactions(event, value, account, staking_as) {
+            writer.write(&t)?;
+        }
+    }
+    progress.count(table.len()).await;
+    Ok(())
+}
+
+async fn safello_events(
+    table: &Table,
+    progress: &mut ProgressSender,
+) -> anyhow::Result<Vec<CryptoEvent>> {
+    let mut events = Vec::new();
+    for (i, line) in table.deserialize().enumerate() {
+        let line: SafelloTransaction = line?;
+        let source_line = table.line(i);
+        let kind = match line.kind.to_lowercase().as_str() {
+            "köp" => Kind::Buy,
+            "sälj" | "försäljning" => Kind::Sell,
+            "staking" | "belöning" => Kind::Reward,
+            "uttag" => Kind::Send,
+            "insättning" => Kind::Receive,
+            "nätverksavgift" => Kind::NetworkFee,
+            _ => {
+                log_skipped(progress, &line.date, &line.kind, &line.asset).await;
+                continue;
+            }
+        };
+        events.push(CryptoEvent {
+            line: source_line,
+            date: iso_date(&line.date),
+            kind,
+            asset: line.asset,
+            quantity: line.quantity.unwrap_or_default().abs(),
+            value: line.amount.map(|a| a.abs()),
+            fees: line.fee.map(|f| f.abs()),
+            currency: line.currency,
+            note: Some(line.kind),
+        });
+    }
+    Ok(events)
+}
+
+async fn coinbase_events(
+    table: &Table,
+    progress: &mut ProgressSender,
+) -> anyhow::Result<Vec<CryptoEvent>> {
+    let mut events = Vec::new();
+    for (i, line) in table.deserialize().enumerate() {
+        let line: CoinbaseTransaction = line?;
+        let source_line = table.line(i);
+        let kind = match line.kind.as_str() {
+            "Buy" | "Advanced Trade Buy" => Kind::Buy,
+            "Sell" | "Advanced Trade Sell" => Kind::Sell,
+            "Staking Income" | "Rewards Income" | "Inflation Reward" | "Learning Reward" => {
+                Kind::Reward
+            }
+            "Send" => Kind::Send,
+            "Receive" => Kind::Receive,
+            "Deposit" => Kind::Deposit,
+            "Withdrawal" => Kind::Withdrawal,
+            _ => {
+                log_skipped(progress, &line.timestamp, &line.kind, &line.asset).await;
+                continue;
+            }
+        };
+        // The total is what was paid for buys, and what was paid out for sales
+        let value = line.total.or(line.subtotal);
+        events.push(CryptoEvent {
+            line: source_line,
+            date: iso_date(&line.timestamp),
+            kind,
+            asset: line.asset,
+            quantity: line.quantity.unwrap_or_default().abs(),
+            value: value.map(|v| v.abs()),
+            fees: line.fees.map(|f| f.abs()).filter(|f| !f.is_zero()),
+            currency: line.currency,
+            note: line.notes.filter(|n| !n.is_empty()),
+        });
+    }
+    Ok(events)
+}
+
+/// Kraken lists each side of a trade as a separate ledger entry, linked by the `refid`.
+async fn kraken_events(
+    table: &Table,
+    progress: &mut ProgressSender,
+) -> anyhow::Result<Vec<CryptoEvent>> {
+    let mut events = Vec::new();
+    let mut open_trades: HashMap<String, KrakenEntry> = HashMap::new();
+    let mut fiat = DEFAULT_FIAT.to_owned();
+    for (i, line) in table.deserialize().enumerate() {
+        let line: KrakenEntry = line?;
+        let source_line = table.line(i);
+        let asset = kraken_asset(&line.asset);
+        let amount = line.amount.unwrap_or_default();
+        let fee = line.fee.unwrap_or_default();
+        match line.kind.as_str() {
+            "trade" | "spend" | "receive" => {
+                let Some(other) = open_trades.remove(&line.refid) else {
+                    open_trades.insert(line.refid.clone(), line);
+                    continue;
+                };
+                let (fiat_leg, crypto_leg) = if is_fiat(&kraken_asset(&other.asset)) {
+                    (other, line)
+                } else if is_fiat(&asset) {
+                    (line, other)
+                } else {
+                    progress
+                        .log(format!(
+                            "Byte mellan kryptovalutor stöds inte, hoppar över {} {} -> {}",
+                            line.time,
+                            kraken_asset(&other.asset),
+                            asset
+                        ))
+                        .await;
+                    continue;
+                };
+                fiat = kraken_asset(&fiat_leg.asset);
+                let fiat_amount = fiat_leg.amount.unwrap_or_default();
+                let fiat_fee = fiat_leg.fee.unwrap_or_default();
+                let quantity = crypto_leg.amount.unwrap_or_default();
+                let crypto_fee = crypto_leg.fee.unwrap_or_default();
+                let kind = if quantity.is_sign_positive() {
+                    Kind::Buy
+                } else {
+                    Kind::Sell
+                };
+                events.push(CryptoEvent {
+                    line: source_line,
+                    date: iso_date(&crypto_leg.time),
+                    kind,
+                    asset: kraken_asset(&crypto_leg.asset),
+                    // Fees in crypto are deducted from the bought coins, and paid on top of the sold
+                    quantity: if kind == Kind::Buy {
+                        quantity.abs() - crypto_fee
+                    } else {
+                        quantity.abs() + crypto_fee
+                    },
+                    value: Some(if fiat_amount.is_sign_negative() {
+                        fiat_amount.abs() + fiat_fee
+                    } else {
+                        fiat_amount - fiat_fee
+                    }),
+                    fees: Some(fiat_fee).filter(|f| !f.is_zero()),
+                    currency: fiat.clone(),
+                    note: Some(format!("Kraken {}", crypto_leg.refid)),
+                });
+            }
+            "staking" | "earn"
+                if line.kind == "staking" || line.subtype.as_deref() == Some("reward") =>
+            {
+                events.push(CryptoEvent {
+                    line: source_line,
+                    date: iso_date(&line.time),
+                    kind: Kind::Reward,
+                    asset,
+                    quantity: amount - fee,
+                    value: None,
+                    fees: None,
+                    currency: fiat.clone(),
+                    note: Some(line.kind),
+                });
+            }
+            "deposit" | "withdrawal" => {
+                let deposit = line.kind == "deposit";
+                let fiat_asset = is_fiat(&asset);
+                let kind = match (fiat_asset, deposit) {
+                    (true, true) => Kind::Deposit,
+                    (true, false) => Kind::Withdrawal,
+                    (false, true) => Kind::Receive,
+                    (false, false) => Kind::Send,
+                };
+                if !fee.is_zero() && !fiat_asset {
+                    events.push(CryptoEvent {
+                        line: source_line,
+                        date: iso_date(&line.time),
+                        kind: Kind::NetworkFee,
+                        asset: asset.clone(),
+                        quantity: fee,
+                        value: None,
+                        fees: None,
+                        currency: fiat.clone(),
+                        note: Some(format!("Kraken {}", line.refid)),
+                    });
+                }
+                events.push(CryptoEvent {
+                    line: source_line,
+                    date: iso_date(&line.time),
+                    kind,
+                    currency: if fiat_asset {
+                        asset.clone()
+                    } else {
+                        fiat.clone()
+                    },
+                    asset,
+                    quantity: amount.abs(),
+                    value: None,
+                    fees: Some(fee).filter(|f| !f.is_zero() && fiat_asset),
+                    note: Some(format!("Kraken {}", line.refid)),
+                });
+            }
+            // Moves between spot and staking wallets stay within the account
+            "transfer" => (),
+            _ => log_skipped(progress, &line.time, &line.kind, &asset).await,
+        }
+    }
+    for (_, line) in open_trades {
+        log_skipped(progress, &line.time, &line.kind, &line.asset).await;
+    }
+    Ok(events)
+}
+
+/// Estimates the value of the event at `index` from the price of the nearest earlier trade of the same
+/// asset, or else the nearest later one. Returns the value and the date of the trade.
+fn estimate_value<'a>(events: &'a [CryptoEvent], index: usize) -> Option<(Decimal, &'a str)> {
+    let event = &events[index];
+    let price = |trade: &'a CryptoEvent| {
+        let value = trade.value?;
+        (matches!(trade.kind, Kind::Buy | Kind::Sell)
+            && trade.asset == event.asset
+            && trade.currency == event.currency
+            && !trade.quantity.is_zero())
+        .then(|| (value / trade.quantity, trade.date.as_str()))
+    };
+    let (price, date) = events[..index]
+        .iter()
+        .rev()
+        .find_map(price)
+        .or_else(|| events[index + 1..].iter().find_map(price))?;
+    Some(((event.quantity * price).round_dp(2), date))
+}
+
+fn to_transactions(
+    event: &CryptoEvent,
+    value: Decimal,
+    account: &str,
+    staking_as: StakingAs,
+) -> Vec<pp::Transaction> {
+    let currency = Currency::new(event.currency.clone());
+    let ticker = format!("{}-{}", event.asset, event.currency);
+    let portfolio = |type_, cash_account: Option<&str>, fees, note: Option<String>| {
+        pp::Transaction::Portfolio(pp::PortfolioTransaction {
+            date: event.date.clone(),
+            securities_account: Some(account.to_owned()),
+            cash_account: cash_account.map(str::to_owned),
+            type_,
+            value,
+            transaction_currency: currency.clone(),
+            gross_amount: None,
+            currency_gross_amount: Some(currency.clone()),
+            exchange_rate: None,
+            fees,
+            taxes: None,
+            shares: Some(event.quantity),
+            isin: None,
+            wkn: None,
+            ticker_symbol: Some(ticker.clone()),
+            security_name: Some(event.asset.clone()),
+            note,
+        })
+    };
+    match event.kind {
+        Kind::Deposit => vec![account_transaction(
+            event,
+            account,
+            pp::AccountType::Deposit,
+            value,
+            event.fees,
+        )],
+        Kind::Withdrawal => vec![account_transaction(
+            event,
+            account,
+            pp::AccountType::Removal,
+            value,
+            event.fees,
+        )],
+        Kind::Buy => vec![portfolio(
+            pp::PortfolioType::Buy,
+            Some(account),
+            event.fees,
+            event.note.clone(),
+        )],
+        Kind::Sell => vec![portfolio(
+            pp::PortfolioType::Sell,
+            Some(account),
+            event.fees,
+            event.note.clone(),
+        )],
+        Kind::Reward if staking_as == StakingAs::Dividend => {
+            let mut dividend =
+                account_transaction(event, account, pp::AccountType::Dividend, value, None);
+            if let pp::Transaction::Account(t) = &mut dividend {
+                t.shares = Some(event.quantity);
+                t.ticker_symbol = Some(ticker.clone());
+                t.security_name = Some(event.asset.clone());
+            }
+            vec![
+                dividend,
+                portfolio(
+                    pp::PortfolioType::Buy,
+                    Some(account),
+                    None,
+                    event.note.clone(),
+                ),
+            ]
+        }
+        Kind::Reward | Kind::Receive => vec![portfolio(
+            pp::PortfolioType::DeliveryInbound,
+            None,
+            event.fees,
+            event.note.clone(),
+        )],
+        Kind::Send => vec![portfolio(
+            pp::PortfolioType::DeliveryOutbound,
+            None,
+            event.fees,
+            event.note.clone(),
+        )],
+        Kind::NetworkFee => vec![portfolio(
+            pp::PortfolioType::DeliveryOutbound,
+            None,
+            None,
+            Some("Nätverksavgift".to_owned()),
+        )],
+    }
+}
+
+fn account_transaction(
+    event: &CryptoEvent,
+    account: &str,
+    type_: pp::AccountType,
+    value: Decimal,
+    fees: Option<Decimal>,
+) -> pp::Transaction {
+    pp::Transaction::Account(pp::AccountTransaction {
+        date: event.date.clone(),
+        cash_account: account.to_owned(),
+        securities_account: matches!(type_, pp::AccountType::Dividend).then(|| account.to_owned()),
+        type_,
+        value,
+        transaction_currency: Currency::new(event.currency.clone()),
+        gross_amount: None,
+        currency_gross_amount: None,
+        exchange_rate: None,
+        fees,
+        taxes: None,
+        shares: None,
+        isin: None,
+        wkn: None,
+        ticker_symbol: None,
+        security_name: None,
+        note: event.note.clone(),
+    })
+}
+
+async fn log_skipped(progress: &mut ProgressSender, date: &str, kind: &str, asset: &str) {
+    progress
+        .log(format!("Hoppar över {date} {kind} {asset}"))
+        .await;
+}
+
+/// Kraken prefixes legacy asset codes with X (crypto) or Z (fiat), uses XBT for bitcoin and suffixes
+/// staked assets, e.g. `XXBT`, `ZEUR` and `DOT.S`.
+fn kraken_asset(asset: &str) -> String {
+    let asset = asset.split('.').next().unwrap_or(asset);
+    let asset = if asset.len() == 4 && (asset.starts_with('X') || asset.starts_with('Z')) {
+        &asset[1..]
+    } else {
+        asset
+    };
+    match asset {
+        "XBT" => "BTC".to_owned(),
+        "ETH2" => "ETH".to_owned(),
+        "XDG" => "DOGE".to_owned(),
+        _ => asset.to_owned(),
+    }
+}
+
+fn is_fiat(asset: &str) -> bool {
+    matches!(
+        asset,
+        "EUR" | "USD" | "SEK" | "GBP" | "CHF" | "NOK" | "DKK" | "CAD" | "AUD" | "JPY"
+    )
+}
+
+/// Cuts a timestamp such as `2024-01-31T12:00:00Z` down to the date.
+fn iso_date(timestamp: &str) -> String {
+    timestamp.get(..10).unwrap_or(timestamp).to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    async fn convert_rows(
+        exchange: Exchange,
+        delimiter: u8,
+        header: &[&str],
+        contents: &str,
+    ) -> Vec<String> {
+        let table = test_util::table(contents, delimiter, header);
+        let (mut output, transactions) = test_util::output();
+        convert(
+            exchange,
+            table,
+            StakingAs::Delivery,
+            &mut output,
+            test_util::progress(),
+        )
+        .await
+        .unwrap();
+        let transactions = transactions.lock().unwrap();
+        transactions.iter().map(test_util::describe).collect()
+    }
+
+    #[tokio::test]
+    async fn classifies_safello_rows() {
+        let rows = convert_rows(
+            Exchange::Safello,
+            SAFELLO_DELIMITER,
+            SAFELLO_HEADER,
+            "Datum;Typ;Kryptovaluta;Antal;Belopp;Avgift;Valuta\n\
+             2024-01-02;Köp;BTC;0,01;-4000;20;SEK\n\
+             2024-01-03;Staking;BTC;0,001;;;SEK\n\
+             2024-01-04;Uttag;BTC;-0,005;;;SEK\n\
+             2024-01-05;Sälj;BTC;-0,006;2500;10;SEK\n\
+             2024-01-06;Bonus;BTC;0,001;;;SEK\n",
+        )
+        .await;
+        assert_eq!(
+            rows,
+            [
+                "2024-01-02 Buy 4000 0.01 BTC",
+                "2024-01-03 DeliveryInbound 400.00 0.001 BTC",
+                "2024-01-04 DeliveryOutbound 2000.00 0.005 BTC",
+                "2024-01-05 Sell 2500 0.006 BTC",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn classifies_coinbase_rows() {
+        let rows = convert_rows(
+            Exchange::Coinbase,
+            COINBASE_DELIMITER,
+            COINBASE_HEADER,
+            "Timestamp,Transaction Type,Asset,Quantity Transacted,Spot Price Currency,Subtotal,Total (inclusive of fees and/or spread),Fees and/or Spread,Notes\n\
+             2024-01-01T10:00:00Z,Deposit,EUR,1000,EUR,€1000.00,€1000.00,€0.00,\n\
+             2024-01-02T10:00:00Z,Buy,ETH,0.5,EUR,€990.00,\"€1,000.00\",€10.00,Bought ETH\n\
+             2024-01-03T10:00:00Z,Staking Income,ETH,1.5e-3,EUR,€3.00,€3.00,€0.00,\n\
+             2024-01-04T10:00:00Z,Send,ETH,-0.1,EUR,,,,To wallet\n\
+             2024-01-05T10:00:00Z,Convert,ETH,-0.1,EUR,€200.00,€200.00,€0.00,\n",
+        )
+        .await;
+        assert_eq!(
+            rows,
+            [
+                "2024-01-01 Deposit 1000 ",
+                "2024-01-02 Buy 1000.00 0.5 ETH",
+                "2024-01-03 DeliveryInbound 3.00 0.0015 ETH",
+                "2024-01-04 DeliveryOutbound 200.00 0.1 ETH",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn classifies_kraken_rows() {
+        let rows = convert_rows(
+            Exchange::Kraken,
+            KRAKEN_DELIMITER,
+            KRAKEN_HEADER,
+            "txid,refid,time,type,subtype,aclass,asset,amount,fee,balance\n\
+             L1,D1,2024-01-01 09:00:00,deposit,,currency,ZEUR,2000,0,2000\n\
+             L2,T1,2024-01-02 10:00:00,trade,,currency,ZEUR,-1000,2,998\n\
+             L3,T1,2024-01-02 10:00:00,trade,,currency,XXBT,0.05,0.0001,0.0499\n\
+             L4,T2,2024-02-02 10:00:00,trade,,currency,XXBT,-0.02,0.0001,0.0298\n\
+             L5,T2,2024-02-02 10:00:00,trade,,currency,ZEUR,900,1.5,1896.5\n\
+             L6,R1,2024-02-03 00:00:00,staking,,currency,XBT.S,1.5e-3,0,0.0015\n\
+             L7,R2,2024-02-04 00:00:00,staking,,currency,DOT.S,0.5,0,0.5\n\
+             L8,W1,2024-02-05 00:00:00,withdrawal,,currency,XXBT,-0.01,0.0002,0.0196\n",
+        )
+        .await;
+        assert_eq!(
+            rows,
+            [
+                "2024-01-01 Deposit 2000 ",
+                "2024-01-02 Buy 1002 0.0499 BTC",
+                // The fee in coins is paid on top of the sold coins
+                "2024-02-02 Sell 898.5 0.0201 BTC",
+                "2024-02-03 DeliveryInbound 67.05 0.0015 BTC",
+                "2024-02-05 DeliveryOutbound 8.94 0.0002 BTC",
+                "2024-02-05 DeliveryOutbound 447.01 0.01 BTC",
+            ]
+        );
+    }
+}
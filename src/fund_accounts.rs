@@ -0,0 +1,469 @@
+//! Fund account exports from SEB, Swedbank and Handelsbanken.
+//!
+//! The exports list fund trades by fund name and NAV. The ISIN is often missing, so fund names are
+//! resolved through the [`FundNames`] table.
+//!
+//! A fund account holds no cash: the money for a buy comes from a bank account and the money from a
+//! sale goes back to it. Each buy is therefore preceded by a deposit of the same amount into the
+//! account's cash account, and each sale followed by a removal, so that the cash account stays at zero.
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::fund_names::FundNames;
+use crate::pp;
+use crate::table::Table;
+use crate::types::{Currency, dec_from_swe_num_opt, iso_date};
+use crate::{ProgressSender, symbols::ResolverChain};
+
+pub const DELIMITER: u8 = b';';
+
+/// Columns that identify the header row of an SEB fund account export.
+pub const SEB_HEADER: &[&str] = &["Affärsdag", "Fond", "Transaktionstyp"];
+/// Columns that identify the header row of a Swedbank fund account export.
+pub const SWEDBANK_HEADER: &[&str] = &["Datum", "Fondnamn", "Händelse"];
+/// Columns that identify the header row of a Handelsbanken fund account export.
+pub const HANDELSBANKEN_HEADER: &[&str] = &["Transaktionsdag", "Fond", "Transaktion"];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Bank {
+    Seb,
+    Swedbank,
+    Handelsbanken,
+}
+
+impl Bank {
+    fn account(self) -> &'static str {
+        match self {
+            Bank::Seb => "SEB Fondkonto",
+            Bank::Swedbank => "Swedbank Fondkonto",
+            Bank::Handelsbanken => "Handelsbanken Fondkonto",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SebTransaction {
+    #[serde(rename = "Affärsdag")]
+    date: String,
+    #[serde(rename = "Fond")]
+    fund: String,
+    #[serde(rename = "Transaktionstyp")]
+    kind: String,
+    #[serde(rename = "Antal andelar", deserialize_with = "dec_from_swe_num_opt")]
+    shares: Option<Decimal>,
+    #[serde(rename = "Kurs", deserialize_with = "dec_from_swe_num_opt")]
+    nav: Option<Decimal>,
+    #[serde(rename = "Belopp", deserialize_with = "dec_from_swe_num_opt")]
+    amount: Option<Decimal>,
+    #[serde(rename = "Valuta")]
+    currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SwedbankTransaction {
+    #[serde(rename = "Datum")]
+    date: String,
+    #[serde(rename = "Fondnamn")]
+    fund: String,
+    #[serde(rename = "Händelse")]
+    kind: String,
+    #[serde(rename = "Andelar", deserialize_with = "dec_from_swe_num_opt")]
+    shares: Option<Decimal>,
+    #[serde(rename = "Andelskurs", deserialize_with = "dec_from_swe_num_opt")]
+    nav: Option<Decimal>,
+    #[serde(rename = "Belopp", deserialize_with = "dec_from_swe_num_opt")]
+    amount: Option<Decimal>,
+    #[serde(rename = "Valuta")]
+    currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HandelsbankenTransaction {
+    #[serde(rename = "Transaktionsdag")]
+    date: String,
+    #[serde(rename = "Fond")]
+    fund: String,
+    #[serde(rename = "ISIN", default)]
+    isin: Option<String>,
+    #[serde(rename = "Transaktion")]
+    kind: String,
+    #[serde(rename = "Antal andelar", deserialize_with = "dec_from_swe_num_opt")]
+    shares: Option<Decimal>,
+    #[serde(rename = "NAV-kurs", deserialize_with = "dec_from_swe_num_opt")]
+    nav: Option<Decimal>,
+    #[serde(rename = "Belopp", deserialize_with = "dec_from_swe_num_opt")]
+    amount: Option<Decimal>,
+}
+
+/// A row in any of the bank formats.
+struct FundTransaction {
+    date: String,
+    fund: String,
+    isin: Option<String>,
+    kind: String,
+    shares: Option<Decimal>,
+    nav: Option<Decimal>,
+    amount: Option<Decimal>,
+    currency: Option<String>,
+}
+
+impl From<SebTransaction> for FundTransaction {
+    fn from(t: SebTransaction) -> Self {
+        FundTransaction {
+            date: t.date,
+            fund: t.fund,
+            isin: None,
+            kind: t.kind,
+            shares: t.shares,
+            nav: t.nav,
+            amount: t.amount,
+            currency: t.currency,
+        }
+    }
+}
+
+impl From<SwedbankTransaction> for FundTransaction {
+    fn from(t: SwedbankTransaction) -> Self {
+        FundTransaction {
+            date: t.date,
+            fund: t.fund,
+            isin: None,
+            kind: t.kind,
+            shares: t.shares,
+            nav: t.nav,
+            amount: t.amount,
+            currency: t.currency,
+        }
+    }
+}
+
+impl From<HandelsbankenTransaction> for FundTransaction {
+    fn from(t: HandelsbankenTransaction) -> Self {
+        FundTransaction {
+            date: t.date,
+            fund: t.fund,
+            isin: t.isin,
+            kind: t.kind,
+            shares: t.shares,
+            nav: t.nav,
+            amount: t.amount,
+            // Handelsbanken only exports SEK amounts
+            currency: None,
+        }
+    }
+}
+
+/// The ISIN from the export, or else from the fund name table.
+fn fund_isin(line: &FundTransaction, fund_names: &FundNames) -> Option<String> {
+    line.isin
+        .clone()
+        .filter(|isin| !isin.is_empty())
+        .or_else(|| fund_names.isin(&line.fund).map(str::to_owned))
+}
+
+pub async fn convert(
+    bank: Bank,
+    table: Table,
+    fund_names: &FundNames,
+    symbols: &ResolverChain<'_>,
+    writer: &mut pp::Output,
+    mut progress: ProgressSender,
+) -> anyhow::Result<()> {
+    let lines: Vec<FundTransaction> = match bank {
+        Bank::Seb => table
+            .deserialize::<SebTransaction>()
+            .map(|r| r.map(Into::into))
+            .collect::<Result<_, _>>()?,
+        Bank::Swedbank => table
+            .deserialize::<SwedbankTransaction>()
+            .map(|r| r.map(Into::into))
+            .collect::<Result<_, _>>()?,
+        Bank::Handelsbanken => table
+            .deserialize::<HandelsbankenTransaction>()
+            .map(|r| r.map(Into::into))
+            .collect::<Result<_, _>>()?,
+    };
+    let isins = lines.iter().filter_map(|line| fund_isin(line, fund_names));
+    symbols.prefetch(isins, &mut progress).await;
+    progress.total(lines.len()).await;
+    progress.count(0).await;
+    let account = bank.account();
+    let mut unknown_funds = Vec::new();
+    let mut read_records = 0;
+    for (i, line) in lines.into_iter().enumerate() {
+        writer.set_source_line(table.line(i));
+        read_records += 1;
+        progress.count(read_records).await;
+
+        let kind = line.kind.to_lowercase();
+        // Sales are checked first, as "återköp" is a redemption
+        let type_ = if ["sälj", "inlösen", "återköp", "uttag", "byte från"]
+            .iter()
+            .any(|k| kind.contains(k))
+        {
+            pp::PortfolioType::Sell
+        } else if [
+            "köp",
+            "insättning",
+            "månadssparande",
+            "återinvest",
+            "byte till",
+        ]
+        .iter()
+        .any(|k| kind.contains(k))
+        {
+            pp::PortfolioType::Buy
+        } else {
+            progress
+                .log(format!(
+                    "Hoppar över {} {} {}",
+                    line.date, line.kind, line.fund
+                ))
+                .await;
+            continue;
+        };
+
+        let isin = fund_isin(&line, fund_names);
+        if isin.is_none() && !unknown_funds.contains(&line.fund) {
+            unknown_funds.push(line.fund.clone());
+        }
+        let mut y_symbol = None;
+        if let Some(isin) = &isin
+            && let Some(security) = symbols
+                .resolve(
+                    isin,
+                    account,
+                    Some(line.currency.as_deref().unwrap_or("SEK")),
+                )
+                .await?
+        {
+            y_symbol = Some(security.symbol);
+        }
+
+        let shares = line.shares.map(|s| s.abs());
+        let value = match (line.amount, shares, line.nav) {
+            (Some(amount), _, _) => amount.abs(),
+            (None, Some(shares), Some(nav)) => (shares * nav).round_dp(2),
+            _ => anyhow::bail!("Belopp saknas för {} {}", line.date, line.fund),
+        };
+        let Some(date) = iso_date(&line.date) else {
+            anyhow::bail!("Okänt datum {} för {}", line.date, line.fund);
+        };
+        let currency = Currency::new(line.currency.unwrap_or_else(|| "SEK".to_owned()));
+        let is_buy = matches!(type_, pp::PortfolioType::Buy);
+        let cash_flow = pp::Transaction::Account(pp::AccountTransaction {
+            date: date.clone(),
+            cash_account: account.to_owned(),
+            securities_account: None,
+            type_: if is_buy {
+                pp::AccountType::Deposit
+            } else {
+                pp::AccountType::Removal
+            },
+            value,
+            transaction_currency: currency.clone(),
+            gross_amount: None,
+            currency_gross_amount: None,
+            exchange_rate: None,
+            fees: None,
+            taxes: None,
+            shares: None,
+            isin: None,
+            wkn: None,
+            ticker_symbol: None,
+            security_name: None,
+            note: Some(format!("{} {}", line.kind, line.fund)),
+        });
+        if is_buy {
+            writer.write(&cash_flow)?;
+        }
+        writer.write(&pp::Transaction::Portfolio(pp::PortfolioTransaction {
+            date,
+            securities_account: Some(account.to_owned()),
+            cash_account: Some(account.to_owned()),
+            type_,
+            value,
+            transaction_currency: currency.clone(),
+            gross_amount: None,
+            currency_gross_amount: Some(currency),
+            exchange_rate: None,
+            fees: None,
+            taxes: None,
+            shares,
+            isin,
+            wkn: None,
+            ticker_symbol: y_symbol,
+            security_name: Some(line.fund),
+            note: Some(line.kind),
+        }))?;
+        if !is_buy {
+            writer.write(&cash_flow)?;
+        }
+    }
+
+    fund_names.log_unknown(&unknown_funds, &mut progress).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    async fn convert_with_names(
+        bank: Bank,
+        header: &[&str],
+        contents: &str,
+        fund_names: &FundNames,
+    ) -> Vec<pp::Transaction> {
+        let table = test_util::table(contents, DELIMITER, header);
+        let (mut output, transactions) = test_util::output();
+        convert(
+            bank,
+            table,
+            fund_names,
+            &test_util::no_symbols(),
+            &mut output,
+            test_util::progress(),
+        )
+        .await
+        .unwrap();
+        transactions.lock().unwrap().clone()
+    }
+
+    async fn convert_rows(bank: Bank, header: &[&str], contents: &str) -> Vec<String> {
+        convert_with_names(bank, header, contents, &FundNames::default())
+            .await
+            .iter()
+            .map(test_util::describe)
+            .collect()
+    }
+
+    /// The ISINs of the portfolio transactions.
+    fn isins(transactions: &[pp::Transaction]) -> Vec<Option<&str>> {
+        transactions
+            .iter()
+            .filter_map(|t| match t {
+                pp::Transaction::Portfolio(t) => Some(t.isin.as_deref()),
+                pp::Transaction::Account(_) => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn converts_seb() {
+        let rows = convert_rows(
+            Bank::Seb,
+            SEB_HEADER,
+            "Affärsdag;Fond;Transaktionstyp;Antal andelar;Kurs;Belopp;Valuta\n\
+             2024-01-15;SEB Sverigefond;Köp;2,5;400;1000;SEK\n\
+             2024-02-15;SEB Sverigefond;Försäljning (sälj);1;410;410;SEK\n\
+             2024-03-15;SEB Sverigefond;Utdelning;;;5;SEK\n",
+        )
+        .await;
+        assert_eq!(
+            rows,
+            [
+                "2024-01-15 Deposit 1000 ",
+                "2024-01-15 Buy 1000 2.5 SEB Sverigefond",
+                "2024-02-15 Sell 410 1 SEB Sverigefond",
+                "2024-02-15 Removal 410 ",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn converts_swedbank() {
+        let rows = convert_rows(
+            Bank::Swedbank,
+            SWEDBANK_HEADER,
+            "Datum;Fondnamn;Händelse;Andelar;Andelskurs;Belopp;Valuta\n\
+             2024-01-25;Swedbank Robur Technology A;Månadssparande;0,5;;500;SEK\n\
+             2024-02-01;Swedbank Robur Technology A;Byte från;0,5;1100;;SEK\n",
+        )
+        .await;
+        assert_eq!(
+            rows,
+            [
+                "2024-01-25 Deposit 500 ",
+                "2024-01-25 Buy 500 0.5 Swedbank Robur Technology A",
+                "2024-02-01 Sell 550.0 0.5 Swedbank Robur Technology A",
+                "2024-02-01 Removal 550.0 ",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn converts_handelsbanken() {
+        let rows = convert_rows(
+            Bank::Handelsbanken,
+            HANDELSBANKEN_HEADER,
+            "Transaktionsdag;Fond;ISIN;Transaktion;Antal andelar;NAV-kurs;Belopp\n\
+             20240110;Handelsbanken Sverigefond;SE0000357014;Köp;3;100;-300\n\
+             31.01.2024;Handelsbanken Sverigefond;SE0000357014;Återköp;1;110;110\n",
+        )
+        .await;
+        assert_eq!(
+            rows,
+            [
+                "2024-01-10 Deposit 300 ",
+                "2024-01-10 Buy 300 3 Handelsbanken Sverigefond",
+                "2024-01-31 Sell 110 1 Handelsbanken Sverigefond",
+                "2024-01-31 Removal 110 ",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_fund_names_to_isins() {
+        let path = test_util::temp_path("fund_isins.csv");
+        std::fs::write(
+            &path,
+            "Fond;ISIN\n\
+             SEB Sverigefond;SE0000984189\n\
+             Swedbank Robur Technology A;SE0000709123\n\
+             Handelsbanken Sverigefond;SE0000357014\n",
+        )
+        .unwrap();
+        let fund_names = FundNames::load(Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Names that differ in case and whitespace, and a fund that is not in the table
+        let seb = convert_with_names(
+            Bank::Seb,
+            SEB_HEADER,
+            "Affärsdag;Fond;Transaktionstyp;Antal andelar;Kurs;Belopp;Valuta\n\
+             2024-01-15;seb  SVERIGEFOND;Köp;2,5;400;1000;SEK\n\
+             2024-01-15;SEB Japanfond;Köp;1;100;100;SEK\n",
+            &fund_names,
+        )
+        .await;
+        assert_eq!(isins(&seb), [Some("SE0000984189"), None]);
+
+        let swedbank = convert_with_names(
+            Bank::Swedbank,
+            SWEDBANK_HEADER,
+            "Datum;Fondnamn;Händelse;Andelar;Andelskurs;Belopp;Valuta\n\
+             2024-01-25; Swedbank Robur  Technology a ;Månadssparande;0,5;;500;SEK\n",
+            &fund_names,
+        )
+        .await;
+        assert_eq!(isins(&swedbank), [Some("SE0000709123")]);
+
+        // The ISIN in the export is used when there is one
+        let handelsbanken = convert_with_names(
+            Bank::Handelsbanken,
+            HANDELSBANKEN_HEADER,
+            "Transaktionsdag;Fond;ISIN;Transaktion;Antal andelar;NAV-kurs;Belopp\n\
+             20240110;HANDELSBANKEN SVERIGEFOND;;Köp;3;100;-300\n\
+             20240111;Handelsbanken Sverigefond;SE0000000001;Köp;1;100;-100\n",
+            &fund_names,
+        )
+        .await;
+        assert_eq!(
+            isins(&handelsbanken),
+            [Some("SE0000357014"), Some("SE0000000001")]
+        );
+    }
+}
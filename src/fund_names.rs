@@ -0,0 +1,100 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::ProgressSender;
+
+static DEFAULT_FILENAME: LazyLock<PathBuf> = LazyLock::new(|| {
+    dirs::config_dir()
+        .unwrap()
+        .join("portfolio-performance-conv")
+        .join("fund_isins.csv")
+});
+
+/// A user-maintained table that maps fund names to ISINs.
+///
+/// Bank fund accounts often list funds by name only. The table lets the same fund resolve to the
+/// same ISIN, and thereby to the same security in Portfolio Performance, regardless of bank.
+///
+/// The file is a CSV file with the columns `Fond` and `ISIN`, separated by `;` or `,`.
+/// Names are matched ignoring case, punctuation and extra whitespace.
+#[derive(Debug, Default)]
+pub struct FundNames {
+    path: PathBuf,
+    isins: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FundNameRow {
+    #[serde(rename = "Fond")]
+    name: String,
+    #[serde(rename = "ISIN")]
+    isin: String,
+}
+
+impl FundNames {
+    /// Loads the table from the given file, or from the default location in the config directory.
+    /// A missing file gives an empty table.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = path.unwrap_or(&DEFAULT_FILENAME).to_owned();
+        if !path.exists() {
+            return Ok(FundNames {
+                path,
+                isins: HashMap::new(),
+            });
+        }
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read fund name table {}", path.display()))?;
+        let delimiter = if contents.lines().next().unwrap_or_default().contains(';') {
+            b';'
+        } else {
+            b','
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .trim(csv::Trim::All)
+            .from_reader(contents.as_bytes());
+        let mut isins = HashMap::new();
+        for row in reader.deserialize() {
+            let row: FundNameRow =
+                row.with_context(|| format!("Bad fund name table {}", path.display()))?;
+            isins.insert(normalize_name(&row.name), row.isin);
+        }
+        Ok(FundNames { path, isins })
+    }
+
+    pub fn isin(&self, fund_name: &str) -> Option<&str> {
+        self.isins
+            .get(&normalize_name(fund_name))
+            .map(String::as_str)
+    }
+
+    /// Lists funds that were not found in the table, so that the user can add them.
+    pub async fn log_unknown(&self, unknown_funds: &[String], progress: &mut ProgressSender) {
+        if unknown_funds.is_empty() {
+            return;
+        }
+        progress
+            .log(format!(
+                "\nHittade inget ISIN för följande fonder. Lägg till dem i {} för att de ska matcha rätt värdepapper:",
+                self.path.display()
+            ))
+            .await;
+        for fund in unknown_funds {
+            progress.log(format!("* {fund}")).await;
+        }
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,330 @@
+//! Detects the format of an input file and runs the matching importer.
+
+use std::{collections::HashSet, path::Path};
+
+use anyhow::anyhow;
+
+use crate::crypto::{self, Exchange};
+use crate::fund_accounts::{self, Bank};
+use crate::fund_names::FundNames;
+use crate::table::{Table, TableBuilder, TableError};
+use crate::{
+    ConvertOptions, ProgressSender, avanza, candidate_label, lookup,
+    openfigi::OpenFigi,
+    pp, ppm, revolut,
+    symbols::{ExchangePreference, ResolverChain, SymbolOverrides},
+    trading212,
+    types::is_isin,
+    yahoo_symbol,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Avanza,
+    Seb,
+    Swedbank,
+    Handelsbanken,
+    Ppm,
+    Safello,
+    Coinbase,
+    Kraken,
+    Trading212,
+    Revolut,
+}
+
+impl Format {
+    const ALL: &[Format] = &[
+        Format::Avanza,
+        Format::Seb,
+        Format::Swedbank,
+        Format::Handelsbanken,
+        Format::Ppm,
+        Format::Safello,
+        Format::Coinbase,
+        Format::Kraken,
+        Format::Trading212,
+        Format::Revolut,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Avanza => "Avanza",
+            Format::Seb => "SEB",
+            Format::Swedbank => "Swedbank",
+            Format::Handelsbanken => "Handelsbanken",
+            Format::Ppm => "Pensionsmyndigheten (PPM)",
+            Format::Safello => "Safello",
+            Format::Coinbase => "Coinbase",
+            Format::Kraken => "Kraken",
+            Format::Trading212 => "Trading 212",
+            Format::Revolut => "Revolut",
+        }
+    }
+
+    fn read_table(self, input: &Path, sheet: Option<&str>) -> Result<Table, TableError> {
+        let (delimiter, header) = match self {
+            Format::Avanza => (avanza::DELIMITER, avanza::HEADER),
+            Format::Seb => (fund_accounts::DELIMITER, fund_accounts::SEB_HEADER),
+            Format::Swedbank => (fund_accounts::DELIMITER, fund_accounts::SWEDBANK_HEADER),
+            Format::Handelsbanken => (
+                fund_accounts::DELIMITER,
+                fund_accounts::HANDELSBANKEN_HEADER,
+            ),
+            Format::Ppm => (ppm::DELIMITER, ppm::HEADER),
+            Format::Safello => (crypto::SAFELLO_DELIMITER, crypto::SAFELLO_HEADER),
+            Format::Coinbase => (crypto::COINBASE_DELIMITER, crypto::COINBASE_HEADER),
+            Format::Kraken => (crypto::KRAKEN_DELIMITER, crypto::KRAKEN_HEADER),
+            Format::Trading212 => (trading212::DELIMITER, trading212::HEADER),
+            Format::Revolut => (revolut::DELIMITER, revolut::HEADER),
+        };
+        TableBuilder::new()
+            .delimiter(delimiter)
+            .sheet(sheet)
+            .header(header)
+            .read(input)
+    }
+}
+
+/// Reads the input file in the given format, or in the first format whose header row is found in the file.
+pub fn read_input(
+    input: &Path,
+    format: Option<Format>,
+    sheet: Option<&str>,
+) -> anyhow::Result<(Format, Table)> {
+    if let Some(format) = format {
+        return Ok((format, format.read_table(input, sheet)?));
+    }
+    for &format in Format::ALL {
+        match format.read_table(input, sheet) {
+            Ok(table) => return Ok((format, table)),
+            Err(TableError::NoHeader(_)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(anyhow!(
+        "Känner inte igen formatet på {}. Kända format: {}",
+        input.display(),
+        Format::ALL
+            .iter()
+            .map(|f| f.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))
+}
+
+/// The services that ticker symbols are looked up in, after the user's own symbols.
+struct Resolvers {
+    overrides: SymbolOverrides,
+    yahoo: yahoo_symbol::Yahoo,
+    openfigi: Option<OpenFigi>,
+    exchanges: ExchangePreference,
+}
+
+impl Resolvers {
+    fn new(options: &ConvertOptions, progress: &ProgressSender) -> anyhow::Result<Self> {
+        Ok(Resolvers {
+            overrides: SymbolOverrides::load(options.symbols.as_deref())?
+                .with_progress(progress.clone()),
+            yahoo: yahoo_symbol::Yahoo::new_with_progress(&options.cache, progress.clone())?
+                .with_offline(options.offline),
+            openfigi: match options.openfigi_url.as_deref() {
+                Some(url) => Some(
+                    OpenFigi::new(url, &options.cache)?
+                        .with_progress(progress.clone())
+                        .with_offline(options.offline),
+                ),
+                None => None,
+            },
+            exchanges: ExchangePreference::load(options.exchanges.as_deref())?,
+        })
+    }
+
+    fn chain(&self, progress: ProgressSender) -> ResolverChain<'_> {
+        let mut chain = ResolverChain::new(self.exchanges.clone())
+            .with_progress(progress)
+            .with_picker(&self.overrides);
+        chain.push(&self.overrides);
+        chain.push(&self.yahoo);
+        if let Some(openfigi) = &self.openfigi {
+            chain.push(openfigi);
+        }
+        chain
+    }
+
+    /// Tells about cache files that could not be read, and were replaced by empty caches.
+    async fn report_recovered(&self, progress: &mut ProgressSender) {
+        let caches =
+            (self.yahoo.caches().into_iter()).chain(self.openfigi.iter().map(|o| o.cache()));
+        for (path, aside, reason) in caches.filter_map(|cache| cache.recovered()) {
+            progress
+                .log(lookup::recovered_warning(path, aside, reason))
+                .await;
+        }
+    }
+
+    /// Saves what was looked up.
+    async fn save(&self) -> anyhow::Result<()> {
+        self.yahoo.save_cache().await?;
+        if let Some(openfigi) = &self.openfigi {
+            openfigi.save_cache().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts the table, and returns the ISINs that could not be looked up, e.g. as the lookups were
+/// offline.
+pub async fn convert(
+    format: Format,
+    table: Table,
+    options: &ConvertOptions,
+    fund_names: &FundNames,
+    writer: &mut pp::Output,
+    mut progress: ProgressSender,
+) -> anyhow::Result<Vec<String>> {
+    let resolvers = Resolvers::new(options, &progress)?;
+    resolvers.report_recovered(&mut progress).await;
+    let symbols = resolvers
+        .chain(progress.clone())
+        .with_resolved(writer.resolved_securities());
+    let result = match format {
+        Format::Avanza => avanza::convert(table, &symbols, writer, progress).await,
+        Format::Seb => {
+            fund_accounts::convert(Bank::Seb, table, fund_names, &symbols, writer, progress).await
+        }
+        Format::Swedbank => {
+            fund_accounts::convert(
+                Bank::Swedbank,
+                table,
+                fund_names,
+                &symbols,
+                writer,
+                progress,
+            )
+            .await
+        }
+        Format::Handelsbanken => {
+            fund_accounts::convert(
+                Bank::Handelsbanken,
+                table,
+                fund_names,
+                &symbols,
+                writer,
+                progress,
+            )
+            .await
+        }
+        Format::Ppm => ppm::convert(table, fund_names, &symbols, writer, progress).await,
+        Format::Safello => {
+            crypto::convert(
+                Exchange::Safello,
+                table,
+                options.staking_as,
+                writer,
+                progress,
+            )
+            .await
+        }
+        Format::Coinbase => {
+            crypto::convert(
+                Exchange::Coinbase,
+                table,
+                options.staking_as,
+                writer,
+                progress,
+            )
+            .await
+        }
+        Format::Kraken => {
+            crypto::convert(
+                Exchange::Kraken,
+                table,
+                options.staking_as,
+                writer,
+                progress,
+            )
+            .await
+        }
+        Format::Trading212 => trading212::convert(table, &symbols, writer, progress).await,
+        Format::Revolut => revolut::convert(table, writer, progress).await,
+    };
+    let unresolved = symbols.unresolved().await;
+    // Save what was looked up, even if the conversion failed
+    resolvers.save().await?;
+    result.map(|()| unresolved)
+}
+
+/// Looks up the given ISINs, and the ISINs without ticker in the given CSV files from a conversion. The
+/// lookups are cached, so that the next conversion finds them.
+pub async fn resolve(
+    items: &[String],
+    options: &ConvertOptions,
+    mut progress: ProgressSender,
+) -> anyhow::Result<()> {
+    let mut isins = Vec::new();
+    let mut bad = Vec::new();
+    for item in items {
+        let path = Path::new(item);
+        if !path.is_file() {
+            if is_isin(item) {
+                isins.push(item.clone());
+            } else {
+                bad.push(item.as_str());
+            }
+            continue;
+        }
+        for transaction in pp::CsvReader::open(path)? {
+            let (isin, ticker_symbol) = match transaction? {
+                pp::Transaction::Portfolio(t) => (t.isin, t.ticker_symbol),
+                pp::Transaction::Account(t) => (t.isin, t.ticker_symbol),
+            };
+            if let Some(isin) = isin
+                && ticker_symbol.is_none()
+            {
+                isins.push(isin);
+            }
+        }
+    }
+    anyhow::ensure!(
+        bad.is_empty(),
+        "Varken ett giltigt ISIN eller en fil: {}",
+        bad.join(", ")
+    );
+    let mut seen = HashSet::new();
+    isins.retain(|isin| seen.insert(isin.clone()));
+    if isins.is_empty() {
+        progress.log("Inga värdepapper att slå upp").await;
+        return Ok(());
+    }
+
+    let resolvers = Resolvers::new(options, &progress)?;
+    resolvers.report_recovered(&mut progress).await;
+    let symbols = resolvers.chain(progress.clone());
+    let mut result = Ok(());
+    for isin in &isins {
+        match symbols.resolve(isin, "", None).await {
+            Ok(Some(security)) => {
+                progress
+                    .log(format!("{isin}: {}", candidate_label(&security)))
+                    .await;
+            }
+            // Failed lookups have been warned about
+            Ok(None) if symbols.unresolved().await.contains(isin) => (),
+            Ok(None) => progress.log(format!("{isin}: Hittades inte")).await,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    resolvers.save().await?;
+    let unresolved = symbols.unresolved().await;
+    if result.is_ok() && !unresolved.is_empty() {
+        anyhow::bail!(
+            "{} värdepapper kunde inte slås upp: {}",
+            unresolved.len(),
+            unresolved.join(", ")
+        );
+    }
+    result
+}
@@ -0,0 +1,878 @@
+//! Infrastructure shared by the services that securities are looked up in: a cache of the lookups by
+//! ISIN, saved in the cache directory, a rate limiter for the requests and retrying of failed requests.
+//! Also the management of the caches: listing, pruning, exporting and importing them.
+
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufReader, BufWriter},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, LazyLock,
+        atomic::{self, AtomicBool},
+    },
+};
+use tokio::{
+    sync::{Mutex, RwLock, RwLockReadGuard},
+    time::{Duration, Instant, sleep},
+};
+
+use crate::{ProgressSender, yahoo_prices::PriceHistory};
+
+/// Attempts per request, including the first one.
+const MAX_ATTEMPTS: u32 = 4;
+/// The wait before the first retry. It is doubled for each retry.
+const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+
+// TODO: Store dir path in main
+pub(crate) static CACHE_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    dirs::cache_dir()
+        .unwrap()
+        .join("portfolio-performance-conv")
+});
+
+/// The caches, by the name used in exports, and their files in the cache directory.
+pub(crate) const CACHE_FILES: &[(&str, &str)] = &[
+    ("yahoo", "yahoo_cache.json"),
+    // Searches by name, for securities without ISIN
+    ("yahoo_names", "yahoo_names_cache.json"),
+    ("openfigi", "openfigi_cache.json"),
+    (PRICE_CACHE, "yahoo_prices_cache.json"),
+];
+
+/// The cache in [`CACHE_FILES`] whose entries are price histories rather than lookups.
+pub(crate) const PRICE_CACHE: &str = "yahoo_prices";
+
+/// The version of the cache files. Files without a version are from before the version was added, and
+/// have the same layout as version 1.
+const CACHE_VERSION: u32 = 1;
+
+/// Where the lookup caches are stored, and for how long lookups are reused.
+#[derive(Debug, Clone)]
+pub(crate) struct CacheSettings {
+    pub dir: PathBuf,
+    pub ttl: TimeDelta,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            dir: CACHE_DIR.clone(),
+            ttl: TimeDelta::days(5),
+        }
+    }
+}
+
+impl CacheSettings {
+    /// The path of the cache with the given name in [`CACHE_FILES`].
+    pub fn path(&self, name: &str) -> PathBuf {
+        let (_, file_name) = CACHE_FILES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .expect("Unknown cache");
+        self.dir.join(file_name)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub(crate) struct Security {
+    pub(crate) symbol: String,
+    pub(crate) exchange: String,
+    /// The name is often messy. Only use it as a fallback.
+    pub(crate) name: String,
+    /// The kind of security, as named by the service, e.g. `Common Stock`
+    #[serde(default)]
+    pub(crate) security_type: Option<String>,
+    /// The currency of the listing, if the service tells it
+    #[serde(default)]
+    pub(crate) currency: Option<String>,
+    /// Yahoo's kind of security: `EQUITY`, `ETF`, `MUTUALFUND`, ...
+    #[serde(default)]
+    pub(crate) quote_type: Option<String>,
+    /// The full name, as given by the service
+    #[serde(default)]
+    pub(crate) long_name: Option<String>,
+    /// The kind of security for display, e.g. `Fund`
+    #[serde(default)]
+    pub(crate) type_display: Option<String>,
+    /// The exchange for display, e.g. `Stockholm`
+    #[serde(default)]
+    pub(crate) exchange_display: Option<String>,
+}
+
+/// Why a lookup failed.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LookupError {
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Got HTTP status {0}")]
+    Status(reqwest::StatusCode),
+    #[error("Got a web page instead of data, probably a cookie consent page")]
+    WebPage,
+    #[error("Unexpected response: {0}")]
+    BadResponse(#[from] serde_json::Error),
+    #[error("{0}")]
+    Service(String),
+    #[error("Not in the cache, and the lookups are offline")]
+    Offline,
+}
+
+/// Sends the request and returns the body of the response. Requests that are rate limited (429), fail
+/// on the server (5xx) or cannot connect are retried with exponential backoff.
+pub(crate) async fn fetch_with_retry(
+    request: reqwest::RequestBuilder,
+) -> Result<String, LookupError> {
+    let mut backoff = FIRST_BACKOFF;
+    let mut attempt = 1;
+    let response = loop {
+        // unwrap: Only requests with streaming bodies cannot be cloned
+        let result = request.try_clone().unwrap().send().await;
+        let retry_after = match &result {
+            Ok(response)
+                if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    || response.status().is_server_error() =>
+            {
+                // The service may say how long to wait
+                let seconds = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok()?.parse().ok());
+                Some(seconds.map_or(backoff, |s| backoff.max(Duration::from_secs(s))))
+            }
+            Err(e) if e.is_connect() || e.is_timeout() => Some(backoff),
+            _ => None,
+        };
+        match retry_after {
+            Some(wait) if attempt < MAX_ATTEMPTS => {
+                sleep(wait).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            // The URL is left out of the error, as it makes the message long
+            _ => break result.map_err(reqwest::Error::without_url)?,
+        }
+    };
+    let status = response.status();
+    if !status.is_success() {
+        return Err(LookupError::Status(status));
+    }
+    let is_web_page = response
+        .url()
+        .host_str()
+        .is_some_and(|host| host.starts_with("consent."))
+        || response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("html"));
+    let body = response.text().await.map_err(reqwest::Error::without_url)?;
+    if is_web_page || body.trim_start().starts_with('<') {
+        return Err(LookupError::WebPage);
+    }
+    Ok(body)
+}
+
+/// Lookups by ISIN, which are reused for a number of days.
+///
+/// [`Self::save()`] **must be called** before dropping the cache, to save it.
+pub(crate) struct LookupCache {
+    path: PathBuf,
+    ttl: TimeDelta,
+    cache: RwLock<Cache>,
+    is_dirty: AtomicBool,
+    recovered: Option<Recovered>,
+}
+
+/// Where an unreadable cache file was moved, and why it could not be read.
+pub(crate) type Recovered = (PathBuf, String);
+
+/// The contents of a cache file.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Cache<E = IsinLookup> {
+    #[serde(default)]
+    version: u32,
+    pub(crate) entries: HashMap<String, E>,
+}
+
+impl<E> Default for Cache<E> {
+    fn default() -> Self {
+        Cache {
+            version: CACHE_VERSION,
+            entries: HashMap::new(),
+        }
+    }
+}
+
+/// An entry of a cache file, which the cache commands handle alike for all caches.
+pub(crate) trait CacheEntry: Serialize + DeserializeOwned {
+    /// When the entry was stored
+    fn updated_at(&self) -> DateTime<Utc>;
+    /// What the entry holds, for `cache list`
+    fn describe(&self) -> String;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IsinLookup {
+    securities: Vec<Security>,
+    updated_at: DateTime<Utc>,
+}
+
+impl CacheEntry for IsinLookup {
+    fn updated_at(&self) -> DateTime<Utc> {
+        self.updated_at
+    }
+
+    fn describe(&self) -> String {
+        if self.securities.is_empty() {
+            return "Ingen träff".to_owned();
+        }
+        self.securities
+            .iter()
+            .map(|s| s.symbol.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// The securities of a cached lookup.
+#[derive(Debug)]
+pub(crate) struct SecurityEntry<'c> {
+    isin: &'c str,
+    rcache: RwLockReadGuard<'c, Cache>,
+}
+
+impl<'c> Deref for SecurityEntry<'c> {
+    type Target = Vec<Security>;
+
+    fn deref(&self) -> &Self::Target {
+        // unwrap: Value was found to exist in the cache and cannot be removed as Self is holding a lock.
+        &self.rcache.entries.get(self.isin).unwrap().securities
+    }
+}
+
+impl LookupCache {
+    /// Reads the cache from the given file, reusing lookups for `ttl`. A missing file gives an empty
+    /// cache. A file that is not a valid cache file is moved aside, see [`Self::recovered()`], and
+    /// replaced by an empty cache. Other errors, such as a file from a newer version of the program or
+    /// one that may not be read, are returned.
+    pub fn load(path: PathBuf, ttl: TimeDelta) -> anyhow::Result<Self> {
+        let (cache, recovered) = load_cache(&path)?;
+        Ok(LookupCache {
+            path,
+            ttl,
+            cache: RwLock::new(cache),
+            is_dirty: AtomicBool::new(false),
+            recovered,
+        })
+    }
+
+    /// Where the cache file was moved, and why, if it could not be read when loading.
+    pub fn recovered(&self) -> Option<(&Path, &Path, &str)> {
+        self.recovered
+            .as_ref()
+            .map(|(aside, reason)| (self.path.as_path(), aside.as_path(), reason.as_str()))
+    }
+
+    /// Returns the securities for the ISIN, if they were looked up recently.
+    pub async fn get<'c>(&'c self, isin: &'c str) -> Option<SecurityEntry<'c>> {
+        let rcache = self.cache.read().await;
+        let lookup = rcache.entries.get(isin)?;
+        (Utc::now() - lookup.updated_at < self.ttl).then_some(SecurityEntry { isin, rcache })
+    }
+
+    /// Returns the securities for the ISIN, however long ago they were looked up.
+    pub async fn get_including_stale<'c>(&'c self, isin: &'c str) -> Option<SecurityEntry<'c>> {
+        let rcache = self.cache.read().await;
+        rcache
+            .entries
+            .contains_key(isin)
+            .then_some(SecurityEntry { isin, rcache })
+    }
+
+    /// Stores the securities that were looked up for the ISIN.
+    pub async fn insert<'c>(
+        &'c self,
+        isin: &'c str,
+        securities: Vec<Security>,
+    ) -> SecurityEntry<'c> {
+        let mut wcache = self.cache.write().await;
+        wcache.entries.insert(
+            isin.to_owned(),
+            IsinLookup {
+                securities,
+                updated_at: Utc::now(),
+            },
+        );
+        self.is_dirty.store(true, atomic::Ordering::Relaxed);
+        SecurityEntry {
+            isin,
+            rcache: wcache.downgrade(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn clear(&mut self) {
+        self.cache.get_mut().entries.clear();
+    }
+
+    /// Saves the cache.
+    ///
+    /// A separate function is required since async drop is not supported.
+    pub async fn save(&self) -> anyhow::Result<()> {
+        let rcache = self.cache.read().await;
+        write_cache(&self.path, &rcache)?;
+        self.is_dirty.store(false, atomic::Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// The cache, rate limiter and log of a lookup service, which do what each lookup has in common.
+///
+/// [`Self::save_cache()`] **must be called** before dropping the lookups, to save the cache.
+pub(crate) struct CachedLookups {
+    /// The name of the service, for the log
+    service: &'static str,
+    cache: LookupCache,
+    /// Shared by the lookups of a service that are cached separately
+    rate_limiter: Arc<RateLimiter>,
+    progress: Option<Mutex<ProgressSender>>,
+    /// Only use the cache
+    offline: bool,
+}
+
+impl CachedLookups {
+    pub fn new(service: &'static str, cache: LookupCache, rate_limiter: Arc<RateLimiter>) -> Self {
+        CachedLookups {
+            service,
+            cache,
+            rate_limiter,
+            progress: None,
+            offline: false,
+        }
+    }
+
+    pub fn set_progress(&mut self, progress: ProgressSender) {
+        self.progress = Some(Mutex::new(progress));
+    }
+
+    /// Only looks up in the cache, including lookups that would otherwise be redone.
+    pub fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    pub fn cache(&self) -> &LookupCache {
+        &self.cache
+    }
+
+    #[cfg(test)]
+    pub fn cache_mut(&mut self) -> &mut LookupCache {
+        &mut self.cache
+    }
+
+    /// Returns the cached lookup of `key`, or else fetches it with `fetch` when the rate limiter allows,
+    /// and caches it. `query` is what is looked up, for the log. Offline, stale lookups are used too.
+    pub async fn get<'c, F>(
+        &'c self,
+        key: &'c str,
+        query: &str,
+        fetch: F,
+    ) -> Result<SecurityEntry<'c>, LookupError>
+    where
+        F: Future<Output = Result<Vec<Security>, LookupError>>,
+    {
+        if let Some(securities) = self.cache.get(key).await {
+            return Ok(securities);
+        }
+        if self.offline {
+            return self
+                .cache
+                .get_including_stale(key)
+                .await
+                .ok_or(LookupError::Offline);
+        }
+        self.wait().await;
+        let securities = fetch.await?;
+        Ok(self.insert(key, query, securities).await)
+    }
+
+    /// Waits until the next request may be made.
+    pub async fn wait(&self) {
+        self.rate_limiter.wait().await;
+    }
+
+    /// Logs and caches a lookup that has been fetched.
+    pub async fn insert<'c>(
+        &'c self,
+        key: &'c str,
+        query: &str,
+        securities: Vec<Security>,
+    ) -> SecurityEntry<'c> {
+        // One line per lookup, as lookups can run concurrently
+        let found = if securities.is_empty() {
+            "Ingen träff".to_owned()
+        } else {
+            securities
+                .iter()
+                .map(
+                    |s| match s.type_display.as_ref().or(s.security_type.as_ref()) {
+                        Some(security_type) => format!("{} ({security_type})", s.symbol),
+                        None => s.symbol.clone(),
+                    },
+                )
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if let Some(progress) = &self.progress {
+            progress
+                .lock()
+                .await
+                .log(format!(
+                    "Hämtade symbol för {query} från {}: {found}",
+                    self.service
+                ))
+                .await;
+        }
+        self.cache.insert(key, securities).await
+    }
+
+    /// Saves the cache.
+    ///
+    /// A separate function is required since async drop is not supported.
+    pub async fn save_cache(&self) -> anyhow::Result<()> {
+        self.cache.save().await
+    }
+}
+
+/// Reads a cache file like [`read_cache()`], but a file that is not a valid cache file is moved aside
+/// and replaced by an empty cache. Returns where such a file was moved, and why it could not be read.
+pub(crate) fn load_cache<E: CacheEntry>(
+    path: &Path,
+) -> anyhow::Result<(Cache<E>, Option<Recovered>)> {
+    match read_cache(path) {
+        Ok(cache) => Ok((cache, None)),
+        Err(e)
+            if e.downcast_ref::<serde_json::Error>()
+                .is_some_and(|e| !e.is_io()) =>
+        {
+            let aside = corrupt_path(path);
+            std::fs::rename(path, &aside).with_context(|| {
+                format!("{e:#}, and it could not be moved to {}", aside.display())
+            })?;
+            Ok((Cache::default(), Some((aside, format!("{e:#}")))))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The warning about a cache file that could not be read, see [`load_cache()`].
+pub(crate) fn recovered_warning(path: &Path, aside: &Path, reason: &str) -> String {
+    format!(
+        "Varning: Cachen {} kunde inte läsas ({reason}) och har flyttats till {}",
+        path.display(),
+        aside.display()
+    )
+}
+
+/// Reads a cache file. A missing file gives an empty cache.
+fn read_cache<E: CacheEntry>(path: &Path) -> anyhow::Result<Cache<E>> {
+    let f = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Cache::default()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {}", path.display())),
+    };
+    let cache: Cache<E> = serde_json::from_reader(BufReader::new(f))
+        .with_context(|| format!("Bad cache file {}", path.display()))?;
+    anyhow::ensure!(
+        cache.version <= CACHE_VERSION,
+        "{} is from a newer version of the program",
+        path.display()
+    );
+    Ok(cache)
+}
+
+/// The first free `<file>.corrupt`, `<file>.corrupt.2` and so on, so that earlier corrupt files are kept.
+fn corrupt_path(path: &Path) -> PathBuf {
+    (1..)
+        .map(|n| match n {
+            1 => path.with_extension("json.corrupt"),
+            n => path.with_extension(format!("json.corrupt.{n}")),
+        })
+        .find(|aside| !aside.exists())
+        .unwrap()
+}
+
+/// Writes a cache file in the current version. The file is replaced only when it has been written
+/// completely, so that an interrupted save does not corrupt it.
+pub(crate) fn write_cache<E: CacheEntry>(path: &Path, cache: &Cache<E>) -> anyhow::Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap()).context("Failed to create cache directory")?;
+    let temp_path = path.with_extension("json.tmp");
+    let f = File::create(&temp_path)
+        .with_context(|| format!("Failed to create cache file {}", temp_path.display()))?;
+    let mut writer = BufWriter::new(f);
+    serde_json::to_writer_pretty(
+        &mut writer,
+        &CacheRef {
+            version: CACHE_VERSION,
+            entries: &cache.entries,
+        },
+    )?;
+    writer.into_inner()?.sync_all()?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to replace cache file {}", path.display()))
+}
+
+/// A [`Cache`] to be written, without copying the entries.
+#[derive(Serialize)]
+struct CacheRef<'c, E> {
+    version: u32,
+    entries: &'c HashMap<String, E>,
+}
+
+/// A file with the entries of all caches, to share with others. The entries are kept as JSON, as their
+/// type depends on the cache.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheExport {
+    version: u32,
+    caches: BTreeMap<String, HashMap<String, serde_json::Value>>,
+}
+
+/// Describes the entries of the caches, one line per ISIN or ticker, with the entries older than the
+/// TTL marked.
+pub(crate) fn list_caches(settings: &CacheSettings) -> anyhow::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for (name, _) in CACHE_FILES {
+        if *name == PRICE_CACHE {
+            list_cache::<PriceHistory>(settings, name, &mut lines)?;
+        } else {
+            list_cache::<IsinLookup>(settings, name, &mut lines)?;
+        }
+    }
+    Ok(lines)
+}
+
+fn list_cache<E: CacheEntry>(
+    settings: &CacheSettings,
+    name: &str,
+    lines: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let path = settings.path(name);
+    let cache: Cache<E> = read_cache(&path)?;
+    if cache.entries.is_empty() {
+        return Ok(());
+    }
+    lines.push(format!("{}:", path.display()));
+    let mut entries: Vec<_> = cache.entries.iter().collect();
+    entries.sort_by_key(|(key, _)| *key);
+    for (key, entry) in entries {
+        let stale = if Utc::now() - entry.updated_at() < settings.ttl {
+            ""
+        } else {
+            " (gammal)"
+        };
+        lines.push(format!(
+            "  {key} {} {}{stale}",
+            entry.updated_at().format("%Y-%m-%d"),
+            entry.describe()
+        ));
+    }
+    Ok(())
+}
+
+/// Removes the entries that are older than the TTL, or all entries. Returns the number of removed
+/// entries.
+pub(crate) fn prune_caches(settings: &CacheSettings, all: bool) -> anyhow::Result<usize> {
+    let mut removed = 0;
+    for (name, _) in CACHE_FILES {
+        let path = settings.path(name);
+        removed += if *name == PRICE_CACHE {
+            prune_cache::<PriceHistory>(&path, settings.ttl, all)?
+        } else {
+            prune_cache::<IsinLookup>(&path, settings.ttl, all)?
+        };
+    }
+    Ok(removed)
+}
+
+fn prune_cache<E: CacheEntry>(path: &Path, ttl: TimeDelta, all: bool) -> anyhow::Result<usize> {
+    let mut cache: Cache<E> = read_cache(path)?;
+    let before = cache.entries.len();
+    cache
+        .entries
+        .retain(|_, entry| !all && Utc::now() - entry.updated_at() < ttl);
+    let removed = before - cache.entries.len();
+    if removed > 0 {
+        write_cache(path, &cache)?;
+    }
+    Ok(removed)
+}
+
+/// Writes the entries of all caches to a file. Returns the number of entries.
+pub(crate) fn export_caches(settings: &CacheSettings, path: &Path) -> anyhow::Result<usize> {
+    let mut caches = BTreeMap::new();
+    for (name, _) in CACHE_FILES {
+        let cache_path = settings.path(name);
+        let entries = if *name == PRICE_CACHE {
+            export_cache::<PriceHistory>(&cache_path)?
+        } else {
+            export_cache::<IsinLookup>(&cache_path)?
+        };
+        caches.insert(name.to_string(), entries);
+    }
+    let count = caches.values().map(HashMap::len).sum();
+    let f = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    serde_json::to_writer_pretty(
+        BufWriter::new(f),
+        &CacheExport {
+            version: CACHE_VERSION,
+            caches,
+        },
+    )?;
+    Ok(count)
+}
+
+fn export_cache<E: CacheEntry>(path: &Path) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    let cache: Cache<E> = read_cache(path)?;
+    cache
+        .entries
+        .into_iter()
+        .map(|(key, entry)| Ok((key, serde_json::to_value(entry)?)))
+        .collect()
+}
+
+/// Adds the entries in an exported file to the caches. Entries that are already cached are replaced if
+/// the exported entry is newer. Returns the number of added or replaced entries.
+pub(crate) fn import_caches(settings: &CacheSettings, path: &Path) -> anyhow::Result<usize> {
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let export: CacheExport = serde_json::from_reader(BufReader::new(f))
+        .with_context(|| format!("{} is not an exported cache", path.display()))?;
+    anyhow::ensure!(
+        export.version <= CACHE_VERSION,
+        "{} is from a newer version of the program",
+        path.display()
+    );
+    let mut imported = 0;
+    for (name, entries) in export.caches {
+        // Caches from newer versions of the program are skipped
+        if !CACHE_FILES.iter().any(|(n, _)| *n == name) {
+            continue;
+        }
+        let cache_path = settings.path(&name);
+        imported += if name == PRICE_CACHE {
+            import_cache::<PriceHistory>(&cache_path, entries)
+        } else {
+            import_cache::<IsinLookup>(&cache_path, entries)
+        }
+        .with_context(|| format!("Bad cache {name} in {}", path.display()))?;
+    }
+    Ok(imported)
+}
+
+fn import_cache<E: CacheEntry>(
+    path: &Path,
+    entries: HashMap<String, serde_json::Value>,
+) -> anyhow::Result<usize> {
+    let mut cache: Cache<E> = read_cache(path)?;
+    let mut imported = 0;
+    for (key, entry) in entries {
+        let entry: E = serde_json::from_value(entry)?;
+        if cache
+            .entries
+            .get(&key)
+            .is_none_or(|cached| cached.updated_at() < entry.updated_at())
+        {
+            cache.entries.insert(key, entry);
+            imported += 1;
+        }
+    }
+    if imported > 0 {
+        write_cache(path, &cache)?;
+    }
+    Ok(imported)
+}
+
+impl Drop for LookupCache {
+    fn drop(&mut self) {
+        if self.is_dirty.load(atomic::Ordering::Relaxed) {
+            println!(
+                "Implementation Error: {} was not saved! Call save().",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Limits the requests to a service with a token bucket: up to `burst` requests can be made at once, and
+/// then one per `interval`.
+pub(crate) struct RateLimiter {
+    burst: f64,
+    interval: Duration,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, interval: Duration) -> Self {
+        RateLimiter {
+            burst: burst.into(),
+            interval,
+            bucket: Mutex::new(Bucket {
+                tokens: burst.into(),
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until the next request may be made. Concurrent callers are let through in turn.
+    pub async fn wait(&self) {
+        // The lock is held while sleeping, so that the waiting callers queue up
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        let refilled = (now - bucket.updated_at).as_secs_f64() / self.interval.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refilled).min(self.burst);
+        bucket.updated_at = now;
+        if bucket.tokens < 1.0 {
+            sleep(self.interval.mul_f64(1.0 - bucket.tokens)).await;
+            bucket.tokens = 0.0;
+            bucket.updated_at = Instant::now();
+        } else {
+            bucket.tokens -= 1.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    /// Serves the responses to GET requests, and returns the URL.
+    fn stub_server(responses: &[&str]) -> String {
+        let responses = responses.iter().map(|r| r.to_string()).collect();
+        let base_url = test_util::stub_server(responses, |request| {
+            assert!(request.starts_with("GET /search "))
+        });
+        format!("{base_url}/search")
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let url = stub_server(&[
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 13\r\nConnection: close\r\n\r\n{\"quotes\":[]}",
+        ]);
+        let body = fetch_with_retry(reqwest::Client::new().get(url))
+            .await
+            .unwrap();
+        assert_eq!(body, r#"{"quotes":[]}"#);
+    }
+
+    #[tokio::test]
+    async fn detects_web_pages() {
+        let url = stub_server(&[
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 15\r\nConnection: close\r\n\r\n<html>ok</html>",
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ]);
+        let client = reqwest::Client::new();
+        assert!(matches!(
+            fetch_with_retry(client.get(&url)).await,
+            Err(LookupError::WebPage)
+        ));
+        assert!(matches!(
+            fetch_with_retry(client.get(&url)).await,
+            Err(LookupError::Status(reqwest::StatusCode::NOT_FOUND))
+        ));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pp-cache-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn corrupt_cache_is_moved_aside() {
+        let dir = temp_dir("corrupt");
+        let path = dir.join("yahoo_cache.json");
+        for aside in ["yahoo_cache.json.corrupt", "yahoo_cache.json.corrupt.2"] {
+            std::fs::write(&path, "{not json").unwrap();
+            let cache = LookupCache::load(path.clone(), TimeDelta::days(5)).unwrap();
+            let (_, moved_to, reason) = cache.recovered().unwrap();
+            assert_eq!(moved_to, dir.join(aside));
+            assert!(reason.starts_with("Bad cache file"));
+            assert!(cache.get("SE0000108656").await.is_none());
+        }
+
+        // A file from a newer version is left as it is
+        std::fs::write(&path, r#"{"version":99,"entries":{}}"#).unwrap();
+        let error = LookupCache::load(path.clone(), TimeDelta::days(5))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("newer version"));
+        assert!(path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn exported_caches_are_imported() {
+        let dir = temp_dir("export");
+        let from = CacheSettings {
+            dir: dir.join("from"),
+            ttl: TimeDelta::days(5),
+        };
+        let to = CacheSettings {
+            dir: dir.join("to"),
+            ..from.clone()
+        };
+        // An unversioned file, from before the version was added
+        std::fs::create_dir_all(&from.dir).unwrap();
+        std::fs::write(
+            from.path("yahoo"),
+            r#"{"entries":{"SE0000108656":{"securities":[{"symbol":"ERIC-B.ST","exchange":"STO","name":"Ericsson"}],"updated_at":"2020-01-01T00:00:00Z"}}}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            from.path(PRICE_CACHE),
+            r#"{"entries":{"ERIC-B.ST":{"quotes":[{"Date":"2020-01-02","Close":"88.5","High":null,"Low":null,"Volume":null}],"updated_at":"2020-01-02T00:00:00Z"}}}"#,
+        )
+        .unwrap();
+        let export_path = dir.join("export.json");
+        assert_eq!(export_caches(&from, &export_path).unwrap(), 2);
+        assert_eq!(import_caches(&to, &export_path).unwrap(), 2);
+        assert_eq!(import_caches(&to, &export_path).unwrap(), 0);
+        let lines = list_caches(&to).unwrap();
+        assert!(lines.contains(&"  ERIC-B.ST 2020-01-02 1 kurser (gammal)".to_owned()));
+
+        let cache = LookupCache::load(to.path("yahoo"), TimeDelta::MAX).unwrap();
+        assert_eq!(
+            cache.get("SE0000108656").await.unwrap()[0].symbol,
+            "ERIC-B.ST"
+        );
+        assert_eq!(cache.cache.read().await.version, CACHE_VERSION);
+        // The entries are older than the TTL
+        assert_eq!(prune_caches(&to, false).unwrap(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_allows_a_burst() {
+        let limiter = RateLimiter::new(2, Duration::from_millis(100));
+        let start = Instant::now();
+        limiter.wait().await;
+        limiter.wait().await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        limiter.wait().await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+}
@@ -2,22 +2,40 @@
 #![windows_subsystem = "windows"]
 
 use std::{
+    io::{IsTerminal, Write},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use iced::{
     Element, Subscription,
-    futures::{SinkExt, Stream, StreamExt, channel::mpsc::Sender},
+    futures::{
+        SinkExt, Stream, StreamExt,
+        channel::{mpsc::Sender, oneshot},
+    },
     stream::{channel, try_channel},
     widget,
 };
 use tokio::pin;
 
 mod avanza;
+mod crypto;
+mod fund_accounts;
+mod fund_names;
+mod import;
+mod lookup;
+mod openfigi;
 mod pp;
+mod ppm;
+mod revolut;
+mod symbols;
+mod table;
+#[cfg(test)]
+mod test_util;
+mod trading212;
 mod types;
+mod yahoo_prices;
 mod yahoo_symbol;
 
 struct Settings {
@@ -28,6 +46,8 @@ struct Settings {
     status: String,
     conv_count: Option<usize>,
     conv_total: Option<usize>,
+    /// Listings that the user is asked to choose among
+    choice: Option<TickerChoice>,
 }
 
 #[derive(Debug, Clone)]
@@ -37,6 +57,7 @@ enum Message {
     FileSelected(Option<PathBuf>),
     Convert,
     Progress(Result<ConversionProgress, String>),
+    TickerChosen(usize),
     EditLog(widget::text_editor::Action),
 }
 
@@ -50,6 +71,7 @@ impl Default for Settings {
             status: "Välj en fil att konvertera".to_string(),
             conv_count: None,
             conv_total: None,
+            choice: None,
         }
     }
 }
@@ -64,7 +86,7 @@ impl Settings {
                 self.selecting_file = true;
             }
             Message::Convert => {
-                self.status = format!("Konverterar...");
+                self.status = "Konverterar...".to_owned();
                 self.log = widget::text_editor::Content::new();
                 self.running = true;
             }
@@ -79,6 +101,15 @@ impl Settings {
                     ConversionProgress::Total(total) => {
                         self.conv_total = Some(total);
                     }
+                    ConversionProgress::Phase(status) => {
+                        self.status = status;
+                        self.conv_count = None;
+                        self.conv_total = None;
+                    }
+                    ConversionProgress::Choose(choice) => {
+                        self.status = format!("Välj ticker för {}", choice.isin);
+                        self.choice = Some(choice);
+                    }
                     ConversionProgress::Done => {
                         self.running = false;
                         self.status = "Klar!".to_owned();
@@ -86,11 +117,18 @@ impl Settings {
                 },
                 Err(error) => {
                     self.running = false;
+                    self.choice = None;
                     self.status = "Fel vid konvertering".to_owned();
                     self.log_line("Fel:");
-                    self.log_line(&format!("{error}"));
+                    self.log_line(&error);
                 }
             },
+            Message::TickerChosen(index) => {
+                if let Some(choice) = self.choice.take() {
+                    choice.answer(index);
+                    self.status = "Konverterar...".to_owned();
+                }
+            }
             Message::EditLog(action) => {
                 // Make the text box read-only
                 if !action.is_edit() {
@@ -121,7 +159,10 @@ impl Settings {
             // created in convert() only gets called once.
             let convert_id = 1;
             let path = Path::new(&self.path).to_owned();
-            Subscription::run_with_id(convert_id, convert(path).map(Message::Progress))
+            Subscription::run_with_id(
+                convert_id,
+                convert(path, ConvertOptions::default()).map(Message::Progress),
+            )
         } else if self.selecting_file {
             let select_file_id = 2;
             Subscription::run_with_id(select_file_id, select_file())
@@ -130,7 +171,7 @@ impl Settings {
         }
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         use iced::widget::*;
         let mut convert_btn = button("Konvertera");
         if !self.running {
@@ -145,22 +186,36 @@ impl Settings {
         } else {
             String::new()
         };
+        let picker = self.choice.as_ref().map(|choice| {
+            Column::with_children(choice.candidates.iter().enumerate().map(|(i, security)| {
+                button(text(candidate_label(security)))
+                    .on_press(Message::TickerChosen(i))
+                    .into()
+            }))
+            .push(text(
+                "Valet sparas bland de egna tickersymbolerna (symbols.toml).",
+            ))
+            .spacing(5)
+        });
         widget::column![
             row![
                 text("Transaktionsfil:").align_y(iced::alignment::Vertical::Center),
                 text_input("", &self.path)
                     .on_input(Message::PathChanged)
                     .on_submit(Message::Convert),
-                button("Välj CSV...").on_press(Message::SelectFile),
+                button("Välj fil...").on_press(Message::SelectFile),
                 convert_btn,
             ]
             .spacing(5),
             row![text(&self.status), horizontal_space(), text(count_text),],
+        ]
+        .push_maybe(picker)
+        .push(
             text_editor(&self.log)
                 .height(iced::Length::Fill)
                 .size(13)
-                .on_action(Message::EditLog)
-        ]
+                .on_action(Message::EditLog),
+        )
         .spacing(5)
         .padding(5)
         .into()
@@ -172,36 +227,296 @@ enum ConversionProgress {
     Log(String),
     Count(usize),
     Total(usize),
+    /// A new phase of the conversion begins, with its own count and total
+    Phase(String),
+    /// The conversion waits for the user to choose a listing
+    Choose(TickerChoice),
     Done,
 }
 
+/// Listings of a security that the user should choose among, when the exchange preference does not
+/// decide.
+#[derive(Debug, Clone)]
+struct TickerChoice {
+    isin: String,
+    candidates: Vec<lookup::Security>,
+    reply: Arc<Mutex<Option<oneshot::Sender<usize>>>>,
+}
+
+impl TickerChoice {
+    /// Answers with the index of the chosen candidate. Dropping the choice without answering fails the
+    /// lookup.
+    fn answer(&self, index: usize) {
+        if let Some(reply) = self.reply.lock().unwrap().take() {
+            reply.send(index).ok();
+        }
+    }
+}
+
+/// The symbol, exchange, name and kind of a listing. Listings from the override file have no exchange.
+fn candidate_label(security: &lookup::Security) -> String {
+    let exchange = security
+        .exchange_display
+        .as_ref()
+        .unwrap_or(&security.exchange);
+    let mut label = if exchange.is_empty() {
+        format!("{} {}", security.symbol, security.name)
+    } else {
+        format!("{} ({exchange}) {}", security.symbol, security.name)
+    };
+    if let Some(type_display) = &security.type_display {
+        label.push_str(&format!(" [{type_display}]"));
+    }
+    label
+}
+
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Fil att konvertera
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Fil att konvertera (CSV eller Excel)
     file: Option<std::path::PathBuf>,
+    /// Blad att läsa i Excel-filer. Standard är det första bladet med rätt kolumner.
+    #[arg(long)]
+    sheet: Option<String>,
+    /// Filens format. Standard är att känna igen formatet på kolumnerna.
+    #[arg(long, value_enum)]
+    format: Option<import::Format>,
+    /// CSV-fil med fondnamn och ISIN (kolumnerna Fond och ISIN), för fondkonton som saknar ISIN.
+    #[arg(long)]
+    fund_names: Option<PathBuf>,
+    #[command(flatten)]
+    lookup: LookupArgs,
+    /// Slå bara upp tickersymboler i cachen och i filen med egna tickersymboler, utan nätverk.
+    /// Värdepapper som saknas listas efter konverteringen.
+    #[arg(long)]
+    offline: bool,
+    /// Skapa även en Portfolio Performance-fil (.xml) med alla konton, värdepapper och transaktioner
+    #[arg(long)]
+    xml: bool,
+    /// Befintlig Portfolio Performance-fil (.xml) att lägga till nya transaktioner i. Filen ändras inte,
+    /// utan resultatet skrivs till en ny fil och en numrerad säkerhetskopia (.xml.~1~) skapas.
+    #[arg(long, value_name = "XML")]
+    merge: Option<PathBuf>,
+    /// Skapa även en JSON-fil (.pp.ndjson) med en transaktion per rad, för vidare analys i andra verktyg
+    #[arg(long)]
+    json: bool,
+    /// Skapa även en bokföringsfil för Beancount (.beancount) eller hledger (.journal)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    ledger: Option<pp::LedgerFlavor>,
+    /// Format på CSV-filerna: engelska (kommatecken, decimalpunkt) eller tyska (semikolon, decimalkomma)
+    /// kolumnnamn, beroende på språket i Portfolio Performance
+    #[arg(long, value_enum, default_value_t, value_name = "PROFIL")]
+    csv_profile: pp::CsvProfile,
+    /// Avgränsare mellan kolumnerna i CSV-filerna, i stället för profilens
+    #[arg(long, value_name = "TECKEN")]
+    csv_delimiter: Option<char>,
+    /// Decimaltecken i CSV-filerna, i stället för profilens
+    #[arg(long, value_name = "TECKEN")]
+    csv_decimal: Option<char>,
+    /// Skriv klockslaget i en egen kolumn (Time) i CSV-filerna
+    #[arg(long)]
+    csv_time: bool,
+    /// Dela upp CSV-filerna per konto, per kalenderår eller båda
+    #[arg(long, value_enum, value_name = "UPPDELNING")]
+    split: Option<pp::Split>,
+    /// Vad som ska hända med utdatafiler som redan finns: skriv över, avbryt eller spara
+    /// numrerade säkerhetskopior (fil.~1~, fil.~2~ osv.)
+    #[arg(long, value_enum, default_value_t, value_name = "ÅTGÄRD")]
+    existing: pp::Existing,
+    /// Hur staking-belöningar från kryptobörser ska bokföras
+    #[arg(long, value_enum, default_value_t)]
+    staking_as: crypto::StakingAs,
+}
+
+/// How ticker symbols are looked up.
+#[derive(clap::Args, Debug)]
+struct LookupArgs {
+    /// TOML-fil med egna tickersymboler per ISIN, som används före uppslagningen hos Yahoo Finance
+    #[arg(long)]
+    symbols: Option<PathBuf>,
+    /// TOML-fil med i vilken ordning börser föredras när ett värdepapper finns på flera, totalt eller
+    /// per konto eller valuta
+    #[arg(long)]
+    exchanges: Option<PathBuf>,
+    /// Slå även upp ISIN hos OpenFIGI när Yahoo Finance inte hittar värdepapperet. En API-nyckel kan
+    /// anges i miljövariabeln OPENFIGI_API_KEY för snabbare uppslagningar.
+    #[arg(long)]
+    openfigi: bool,
+    /// Adress till OpenFIGI
+    #[arg(long, default_value = openfigi::DEFAULT_BASE_URL)]
+    openfigi_url: String,
+    #[command(flatten)]
+    cache: CacheArgs,
+}
+
+/// Where the lookups are cached.
+#[derive(clap::Args, Debug)]
+struct CacheArgs {
+    /// Mapp för cachen med uppslagna tickersymboler, i stället för programmets cachemapp
+    #[arg(long, value_name = "MAPP")]
+    cache_dir: Option<PathBuf>,
+    /// Antal dagar som uppslagningar i cachen återanvänds innan de görs om
+    #[arg(long, value_name = "DAGAR")]
+    cache_days: Option<u32>,
+}
+
+impl CacheArgs {
+    fn settings(self) -> lookup::CacheSettings {
+        let default = lookup::CacheSettings::default();
+        lookup::CacheSettings {
+            dir: self.cache_dir.unwrap_or(default.dir),
+            ttl: self
+                .cache_days
+                .map_or(default.ttl, |days| chrono::TimeDelta::days(days.into())),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Visa de uppslagna värdepapperen. Uppslagningar som är äldre än --cache-days markeras.
+    List {
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Ta bort uppslagningar som är äldre än --cache-days
+    Prune {
+        /// Ta bort alla uppslagningar
+        #[arg(long)]
+        all: bool,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Skriv alla uppslagningar till en fil, till exempel för att dela dem med andra
+    Export {
+        file: PathBuf,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Lägg till uppslagningarna i en exporterad fil. Nyare uppslagningar ersätter äldre.
+    Import {
+        file: PathBuf,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Hämta historiska kurser från Yahoo Finance för värdepapperen i en .pp-securities.csv-fil,
+    /// och skriv en CSV-fil med kurser per värdepapper
+    Prices {
+        /// Värdepappersfil (.pp-securities.csv) från en konvertering
+        file: PathBuf,
+        /// Adress till Yahoo Finance
+        #[arg(long, default_value = yahoo_prices::DEFAULT_BASE_URL)]
+        yahoo_url: String,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Jämför två CSV-filer med transaktioner (från en konvertering eller exporterade från
+    /// Portfolio Performance) och visa transaktionerna som bara finns i den ena
+    Diff {
+        /// Den gamla filen. Transaktioner som bara finns här visas med -
+        old: PathBuf,
+        /// Den nya filen. Transaktioner som bara finns här visas med +
+        new: PathBuf,
+    },
+    /// Slå upp tickersymboler för ISIN och spara dem i cachen, till exempel för värdepapper som saknades
+    /// vid en konvertering med --offline
+    Resolve {
+        /// ISIN, eller CSV-filer från en konvertering vars värdepapper saknar ticker
+        #[arg(required = true, value_name = "ISIN|FIL")]
+        items: Vec<String>,
+        #[command(flatten)]
+        lookup: LookupArgs,
+    },
+    /// Visa, rensa, exportera eller importera cachen med uppslagna tickersymboler
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+/// Settings for a conversion, given on the command line or in the GUI.
+#[derive(Debug, Clone, Default)]
+struct ConvertOptions {
+    sheet: Option<String>,
+    format: Option<import::Format>,
+    fund_names: Option<PathBuf>,
+    symbols: Option<PathBuf>,
+    exchanges: Option<PathBuf>,
+    /// The address of OpenFIGI, if it is used
+    openfigi_url: Option<String>,
+    /// Only use the cache and the user's own ticker symbols
+    offline: bool,
+    cache: lookup::CacheSettings,
+    staking_as: crypto::StakingAs,
+    xml: bool,
+    merge: Option<PathBuf>,
+    json: bool,
+    ledger: Option<pp::LedgerFlavor>,
+    csv_format: pp::CsvFormat,
+    split: Option<pp::Split>,
+    existing: pp::Existing,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    if let Some(input_path) = args.file {
-        let rt = tokio::runtime::Runtime::new()?;
-        rt.block_on(async move {
-            let s = convert(input_path);
-            pin!(s);
-            while let Some(result) = s.next().await {
-                match result {
-                    Ok(progress) => {
-                        match progress {
-                            ConversionProgress::Log(msg) => println!("{msg}"),
-                            ConversionProgress::Count(_) => (),
-                            ConversionProgress::Total(_) => (),
-                            ConversionProgress::Done => (),
-                        };
-                    }
-                    Err(_) => todo!(),
-                }
-            }
-        });
+    if let Some(Command::Prices {
+        file,
+        yahoo_url,
+        cache,
+    }) = args.command
+    {
+        run_in_terminal(prices(file, yahoo_url, cache.settings()))?;
+    } else if let Some(Command::Diff { old, new }) = args.command {
+        diff(&old, &new)?;
+    } else if let Some(Command::Resolve { items, lookup }) = args.command {
+        let options = ConvertOptions {
+            symbols: lookup.symbols,
+            exchanges: lookup.exchanges,
+            openfigi_url: lookup.openfigi.then_some(lookup.openfigi_url),
+            cache: lookup.cache.settings(),
+            ..Default::default()
+        };
+        run_in_terminal(resolve(items, options))?;
+    } else if let Some(Command::Cache { command }) = args.command {
+        manage_cache(command)?;
+    } else if let Some(input_path) = args.file {
+        let mut csv_format = pp::CsvFormat::from(args.csv_profile);
+        if let Some(delimiter) = args.csv_delimiter {
+            anyhow::ensure!(
+                delimiter.is_ascii(),
+                "Avgränsaren i CSV-filerna måste vara ett ASCII-tecken"
+            );
+            csv_format.delimiter = delimiter as u8;
+        }
+        if let Some(decimal) = args.csv_decimal {
+            csv_format.decimal_separator = decimal;
+        }
+        csv_format.time_column = args.csv_time;
+        let options = ConvertOptions {
+            sheet: args.sheet,
+            format: args.format,
+            fund_names: args.fund_names,
+            symbols: args.lookup.symbols,
+            exchanges: args.lookup.exchanges,
+            openfigi_url: args.lookup.openfigi.then_some(args.lookup.openfigi_url),
+            offline: args.offline,
+            cache: args.lookup.cache.settings(),
+            staking_as: args.staking_as,
+            xml: args.xml,
+            merge: args.merge,
+            json: args.json,
+            ledger: args.ledger,
+            csv_format,
+            split: args.split,
+            existing: args.existing,
+        };
+        run_in_terminal(convert(input_path, options))?;
     } else {
         iced::application(
             "Portfolio Performance Converter",
@@ -215,6 +530,63 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Runs a task without the GUI, printing its log.
+fn run_in_terminal(
+    task: impl Stream<Item = Result<ConversionProgress, String>>,
+) -> anyhow::Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async move {
+        pin!(task);
+        let mut error = None;
+        while let Some(result) = task.next().await {
+            match result {
+                Ok(progress) => {
+                    match progress {
+                        ConversionProgress::Log(msg) => println!("{msg}"),
+                        ConversionProgress::Count(_) => (),
+                        ConversionProgress::Total(_) => (),
+                        ConversionProgress::Phase(_) => (),
+                        ConversionProgress::Choose(choice) => choose_in_terminal(&choice),
+                        ConversionProgress::Done => (),
+                    };
+                }
+                // The error can arrive before the last log messages, so they are printed first
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(e) => anyhow::bail!(e),
+            None => Ok(()),
+        }
+    })
+}
+
+/// Asks on the terminal which listing to use. Without a terminal the choice is not answered, which fails
+/// the lookup.
+fn choose_in_terminal(choice: &TickerChoice) {
+    if !std::io::stdin().is_terminal() {
+        return;
+    }
+    println!("Flera noteringar hittades för {}:", choice.isin);
+    for (i, security) in choice.candidates.iter().enumerate() {
+        println!("  {}. {}", i + 1, candidate_label(security));
+    }
+    loop {
+        print!("Välj 1-{}: ", choice.candidates.len());
+        std::io::stdout().flush().ok();
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        if let Ok(number) = line.trim().parse::<usize>()
+            && (1..=choice.candidates.len()).contains(&number)
+        {
+            choice.answer(number - 1);
+            return;
+        }
+    }
+}
+
 #[derive(Clone)]
 struct ProgressSender {
     sender: Sender<ConversionProgress>,
@@ -242,9 +614,34 @@ impl ProgressSender {
             .await
             .ok();
     }
+
+    async fn phase(&mut self, status: impl Into<String>) {
+        self.sender
+            .send(ConversionProgress::Phase(status.into()))
+            .await
+            .ok();
+    }
+
+    /// Asks the user to choose one of the listings of the ISIN. Returns the index of the chosen listing,
+    /// or `None` if no choice could be made.
+    async fn choose(&mut self, isin: &str, candidates: Vec<lookup::Security>) -> Option<usize> {
+        let (reply, answer) = oneshot::channel();
+        self.sender
+            .send(ConversionProgress::Choose(TickerChoice {
+                isin: isin.to_owned(),
+                candidates,
+                reply: Arc::new(Mutex::new(Some(reply))),
+            }))
+            .await
+            .ok()?;
+        answer.await.ok()
+    }
 }
 
-fn convert(input_path: PathBuf) -> impl Stream<Item = Result<ConversionProgress, String>> {
+fn convert(
+    input_path: PathBuf,
+    options: ConvertOptions,
+) -> impl Stream<Item = Result<ConversionProgress, String>> {
     try_channel(1, async move |mut output| {
         let mut progress = ProgressSender {
             sender: output.clone(),
@@ -252,50 +649,166 @@ fn convert(input_path: PathBuf) -> impl Stream<Item = Result<ConversionProgress,
         progress
             .log(format!("Konverterar {}...", input_path.display()))
             .await;
+        let (format, table) =
+            import::read_input(&input_path, options.format, options.sheet.as_deref())
+                .map_err(|e| e.to_string())?;
+        progress.log(format!("Format: {}", format.name())).await;
+        let fund_names = fund_names::FundNames::load(options.fund_names.as_deref())
+            .map_err(|e| e.to_string())?;
         let portfolio_output = input_path.with_extension("pp-portfolio-transactions.csv");
         let account_output = input_path.with_extension("pp-account-transactions.csv");
-        let mut writer =
-            pp::CsvWriter::new(&portfolio_output, &account_output).map_err(|e| e.to_string())?;
-        avanza::convert(&input_path, &mut writer, progress.clone())
-            .await
-            .map_err(|e| e.to_string())?;
+        let securities_output = input_path.with_extension("pp-securities.csv");
+        let xml_output =
+            (options.xml || options.merge.is_some()).then(|| input_path.with_extension("pp.xml"));
+        let json_output = options.json.then(|| input_path.with_extension("pp.ndjson"));
+        let files = pp::OutputFiles::new(options.existing);
+        let xml_writer = xml_output.as_ref().map(|path| match &options.merge {
+            Some(existing) => pp::XmlWriter::new(&files, path).merge_with(existing),
+            None => pp::XmlWriter::new(&files, path),
+        });
+        let ledger_output = options
+            .ledger
+            .map(|flavor| (flavor, input_path.with_extension(flavor.extension())));
+        let mut writer = pp::Output::new(&files);
+        match options.split {
+            Some(split) => {
+                writer.add(pp::SplitCsvWriter::new(
+                    &files,
+                    &input_path,
+                    split,
+                    options.csv_format,
+                ));
+            }
+            None => writer.add(
+                pp::CsvWriter::new(
+                    &files,
+                    &portfolio_output,
+                    &account_output,
+                    options.csv_format,
+                )
+                .map_err(|e| format!("{:#}", anyhow::Error::from(e)))?,
+            ),
+        }
+        let securities_writer = pp::SecurityCsvWriter::new(
+            &files,
+            &securities_output,
+            options.csv_format,
+            writer.resolved_securities(),
+        );
+        writer.add(securities_writer);
+        if let Some(xml_writer) = xml_writer {
+            writer.add(xml_writer);
+        }
+        if let Some(json_output) = &json_output {
+            writer.add(pp::JsonWriter::new(&files, json_output).map_err(|e| format!("{e:#}"))?);
+        }
+        if let Some((flavor, path)) = &ledger_output {
+            writer.add(pp::LedgerWriter::new(&files, path, *flavor));
+        }
+        let unresolved = import::convert(
+            format,
+            table,
+            &options,
+            &fund_names,
+            &mut writer,
+            progress.clone(),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        let messages = writer.finish().map_err(|e| format!("{e:#}"))?;
 
         let mut deps: Vec<_> = writer.cash_accounts().iter().collect();
         deps.sort();
         let mut secs: Vec<_> = writer.security_accounts().iter().collect();
         secs.sort();
         progress
-            .log(format!(
+            .log(
                 "
 
 
 Lägg till följande konton i Portfolio Performance innan du importerar CSV-filerna.
 Om du inte lägger in alla konton i förväg så kommer transaktioner hamna på fel konton.
-"
-            ))
+",
+            )
             .await;
-        progress.log(format!("Securities accounts:")).await;
+        progress.log("Securities accounts:").await;
         for account in secs {
             progress.log(format!("* {account}")).await;
         }
         progress
-            .log(format!("\nDeposit accounts (Reference accounts):"))
+            .log("\nDeposit accounts (Reference accounts):")
             .await;
         for account in deps {
             progress.log(format!("* {account}")).await;
         }
         progress
             .log(format!(
-                "\nPortfolio transactions: {}",
-                portfolio_output.display()
-            ))
-            .await;
-        progress
-            .log(format!(
-                "Account transactions: {}",
-                account_output.display()
+                "\nSecurities (importera först): {}",
+                securities_output.display()
             ))
             .await;
+        if options.split.is_none() {
+            progress
+                .log(format!(
+                    "Portfolio transactions: {}",
+                    portfolio_output.display()
+                ))
+                .await;
+            progress
+                .log(format!(
+                    "Account transactions: {}",
+                    account_output.display()
+                ))
+                .await;
+        }
+        // The split CSV writer lists its files here
+        for message in messages {
+            progress.log(message).await;
+        }
+        if let Some((_, ledger_output)) = &ledger_output {
+            progress
+                .log(format!("Bokföring: {}", ledger_output.display()))
+                .await;
+        }
+        if let Some(json_output) = &json_output {
+            progress
+                .log(format!("JSON: {}", json_output.display()))
+                .await;
+        }
+        if let Some(xml_output) = &xml_output {
+            progress
+                .log(format!(
+                    "Portfolio Performance-fil: {} (öppna den med Arkiv > Öppna)",
+                    xml_output.display()
+                ))
+                .await;
+        }
+        if !unresolved.is_empty() {
+            progress
+                .log("\nFöljande värdepapper kunde inte slås upp och saknar ticker:")
+                .await;
+            for item in &unresolved {
+                progress.log(format!("* {item}")).await;
+            }
+            // Securities without ISIN are searched for by name in the next conversion
+            let isins: Vec<_> = unresolved
+                .iter()
+                .filter(|item| types::is_isin(item))
+                .map(String::as_str)
+                .collect();
+            if isins.is_empty() {
+                progress
+                    .log("Konvertera igen när nätverket fungerar.")
+                    .await;
+            } else {
+                progress
+                    .log(format!(
+                        "Slå upp dem när nätverket fungerar med\n  portfolio-performance-conv resolve {}\noch konvertera sedan igen.",
+                        isins.join(" ")
+                    ))
+                    .await;
+            }
+        }
 
         #[cfg(target_os = "windows")]
         {
@@ -309,10 +822,87 @@ Om du inte lägger in alla konton i förväg så kommer transaktioner hamna på
     })
 }
 
+fn prices(
+    securities_path: PathBuf,
+    base_url: String,
+    cache: lookup::CacheSettings,
+) -> impl Stream<Item = Result<ConversionProgress, String>> {
+    try_channel(1, async move |mut output| {
+        let progress = ProgressSender {
+            sender: output.clone(),
+        };
+        yahoo_prices::export(&securities_path, &base_url, &cache, progress)
+            .await
+            .map_err(|e| format!("{e:#}"))?;
+        output.send(ConversionProgress::Done).await.unwrap();
+        Ok(())
+    })
+}
+
+fn resolve(
+    items: Vec<String>,
+    options: ConvertOptions,
+) -> impl Stream<Item = Result<ConversionProgress, String>> {
+    try_channel(1, async move |mut output| {
+        let progress = ProgressSender {
+            sender: output.clone(),
+        };
+        import::resolve(&items, &options, progress)
+            .await
+            .map_err(|e| format!("{e:#}"))?;
+        output.send(ConversionProgress::Done).await.unwrap();
+        Ok(())
+    })
+}
+
+fn manage_cache(command: CacheCommand) -> anyhow::Result<()> {
+    match command {
+        CacheCommand::List { cache } => {
+            let settings = &cache.settings();
+            let lines = lookup::list_caches(settings)?;
+            for line in &lines {
+                println!("{line}");
+            }
+            if lines.is_empty() {
+                println!("Cachen i {} är tom", settings.dir.display());
+            }
+        }
+        CacheCommand::Prune { all, cache } => {
+            let removed = lookup::prune_caches(&cache.settings(), all)?;
+            println!("Tog bort {removed} uppslagningar");
+        }
+        CacheCommand::Export { file, cache } => {
+            let count = lookup::export_caches(&cache.settings(), &file)?;
+            println!("Exporterade {count} uppslagningar till {}", file.display());
+        }
+        CacheCommand::Import { file, cache } => {
+            let count = lookup::import_caches(&cache.settings(), &file)?;
+            println!("Importerade {count} uppslagningar från {}", file.display());
+        }
+    }
+    Ok(())
+}
+
+fn diff(old_path: &Path, new_path: &Path) -> anyhow::Result<()> {
+    let old = pp::CsvReader::open(old_path)?.collect::<anyhow::Result<Vec<_>>>()?;
+    let new = pp::CsvReader::open(new_path)?.collect::<anyhow::Result<Vec<_>>>()?;
+    let lines = pp::diff(&old, &new);
+    for line in &lines {
+        println!("{line}");
+    }
+    if lines.is_empty() {
+        println!("Filerna innehåller samma transaktioner");
+    }
+    Ok(())
+}
+
 fn select_file() -> impl Stream<Item = Message> {
     channel(1, async |mut output| {
         if let Some(path) = rfd::AsyncFileDialog::new()
-            .add_filter("CSV", &["csv"])
+            .add_filter(
+                "CSV/Excel",
+                &[&["csv"], table::SPREADSHEET_EXTENSIONS].concat(),
+            )
             .pick_file()
             .await
         {
@@ -0,0 +1,325 @@
+//! Looks up securities by ISIN in the [OpenFIGI](https://www.openfigi.com/api) mapping API, which knows
+//! many Nordic funds and bonds that Yahoo Finance's search does not.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::{
+    ProgressSender,
+    lookup::{
+        CacheSettings, CachedLookups, LookupCache, LookupError, RateLimiter, Security,
+        SecurityEntry, fetch_with_retry,
+    },
+    symbols::{ListingsFuture, PrefetchFuture, SymbolResolver},
+};
+
+pub const DEFAULT_BASE_URL: &str = "https://api.openfigi.com";
+
+/// Environment variable with an OpenFIGI API key, which allows more lookups per minute.
+const API_KEY_VARIABLE: &str = "OPENFIGI_API_KEY";
+
+/// The most ISINs per mapping request, without and with an API key.
+const JOBS_PER_REQUEST: usize = 10;
+const JOBS_PER_REQUEST_WITH_KEY: usize = 100;
+
+/// OpenFIGI exchange codes, with the Yahoo Finance exchange and ticker suffix that they correspond to.
+const EXCHANGES: &[(&str, &str, &str)] = &[
+    ("SS", "STO", ".ST"),
+    ("FH", "HEL", ".HE"),
+    ("DC", "CPH", ".CO"),
+    ("NO", "OSL", ".OL"),
+    ("GY", "GER", ".DE"),
+    ("LN", "LSE", ".L"),
+    ("UN", "NYQ", ""),
+    ("UW", "NMS", ""),
+    ("UQ", "NMS", ""),
+    ("US", "NMS", ""),
+];
+
+/// Performs lookups towards OpenFIGI. Tickers are translated to Yahoo Finance symbols for the exchanges
+/// that Yahoo lists, so that Portfolio Performance can fetch their prices.
+///
+/// Results are cached like Yahoo lookups. [`Self::save_cache()`] **must be called** before dropping
+/// [`OpenFigi`], to save the cache.
+pub(crate) struct OpenFigi {
+    base_url: String,
+    api_key: Option<String>,
+    lookups: CachedLookups,
+}
+
+#[derive(Debug, Serialize)]
+struct MappingJob<'a> {
+    #[serde(rename = "idType")]
+    id_type: &'static str,
+    #[serde(rename = "idValue")]
+    id_value: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct MappingResult {
+    #[serde(default)]
+    data: Vec<FigiInstrument>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FigiInstrument {
+    ticker: Option<String>,
+    exch_code: Option<String>,
+    name: Option<String>,
+    security_type: Option<String>,
+}
+
+impl OpenFigi {
+    pub fn new(base_url: &str, cache: &CacheSettings) -> anyhow::Result<Self> {
+        Ok(Self::with_cache(
+            base_url,
+            std::env::var(API_KEY_VARIABLE).ok(),
+            LookupCache::load(cache.path("openfigi"), cache.ttl)?,
+        ))
+    }
+
+    fn with_cache(base_url: &str, api_key: Option<String>, cache: LookupCache) -> Self {
+        // 25 requests per 6 seconds with a key, and 25 per minute without one. Without a key there is
+        // no burst, as a burst would be followed by a minute's worth of requests within the minute.
+        let rate_limiter = if api_key.is_some() {
+            RateLimiter::new(25, Duration::from_millis(240))
+        } else {
+            RateLimiter::new(1, Duration::from_millis(2400))
+        };
+        OpenFigi {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            api_key,
+            lookups: CachedLookups::new("OpenFIGI", cache, Arc::new(rate_limiter)),
+        }
+    }
+
+    pub fn with_progress(mut self, progress: ProgressSender) -> Self {
+        self.lookups.set_progress(progress);
+        self
+    }
+
+    pub fn cache(&self) -> &LookupCache {
+        self.lookups.cache()
+    }
+
+    /// See [`CachedLookups::set_offline()`].
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.lookups.set_offline(offline);
+        self
+    }
+
+    /// Looks up the listings of the given ISIN. The returned vec is empty if nothing is found.
+    pub async fn isin_to_securities<'c>(
+        &'c self,
+        isin: &'c str,
+    ) -> Result<SecurityEntry<'c>, LookupError> {
+        let fetch = async {
+            self.fetch(&[isin.to_owned()])
+                .await?
+                .pop()
+                .unwrap_or(Ok(Vec::new()))
+        };
+        self.lookups.get(isin, isin, fetch).await
+    }
+
+    /// Looks up the ISINs that are not in the cache, as many per request as OpenFIGI allows.
+    async fn prefetch_isins(&self, isins: &[String]) {
+        if self.lookups.is_offline() {
+            return;
+        }
+        let mut missing = Vec::new();
+        for isin in isins {
+            if self.lookups.cache().get(isin).await.is_none() {
+                missing.push(isin.clone());
+            }
+        }
+        let jobs_per_request = if self.api_key.is_some() {
+            JOBS_PER_REQUEST_WITH_KEY
+        } else {
+            JOBS_PER_REQUEST
+        };
+        for isins in missing.chunks(jobs_per_request) {
+            self.lookups.wait().await;
+            let Ok(results) = self.fetch(isins).await else {
+                continue;
+            };
+            for (isin, result) in isins.iter().zip(results) {
+                if let Ok(securities) = result {
+                    self.lookups.insert(isin, isin, securities).await;
+                }
+            }
+        }
+    }
+
+    /// Maps the ISINs in one request. Returns the listings of each ISIN, in the same order.
+    async fn fetch(
+        &self,
+        isins: &[String],
+    ) -> Result<Vec<Result<Vec<Security>, LookupError>>, LookupError> {
+        let jobs: Vec<MappingJob> = isins
+            .iter()
+            .map(|isin| MappingJob {
+                id_type: "ID_ISIN",
+                id_value: isin,
+            })
+            .collect();
+        let client = reqwest::Client::new();
+        let mut request = client
+            .post(format!("{}/v3/mapping", self.base_url))
+            .json(&jobs);
+        if let Some(api_key) = &self.api_key {
+            request = request.header("X-OPENFIGI-APIKEY", api_key);
+        }
+        let results: Vec<MappingResult> = serde_json::from_str(&fetch_with_retry(request).await?)?;
+        if results.len() != isins.len() {
+            return Err(LookupError::Service(format!(
+                "OpenFIGI answered {} of {} mapping jobs",
+                results.len(),
+                isins.len()
+            )));
+        }
+        Ok(results.into_iter().map(to_securities).collect())
+    }
+
+    /// Saves the internal cache.
+    pub async fn save_cache(&self) -> anyhow::Result<()> {
+        self.lookups.save_cache().await
+    }
+}
+
+impl SymbolResolver for OpenFigi {
+    fn listings<'a>(&'a self, isin: &'a str) -> ListingsFuture<'a> {
+        Box::pin(async move { Ok(self.isin_to_securities(isin).await?.to_vec()) })
+    }
+
+    fn prefetch<'a>(&'a self, isins: &'a [String]) -> PrefetchFuture<'a> {
+        Box::pin(self.prefetch_isins(isins))
+    }
+}
+
+/// The listings of one mapping job.
+fn to_securities(result: MappingResult) -> Result<Vec<Security>, LookupError> {
+    if let Some(error) = result.error {
+        return Err(LookupError::Service(format!("OpenFIGI: {error}")));
+    }
+    // A "No identifier found" warning comes without data
+    let mut securities: Vec<Security> = Vec::new();
+    for instrument in result.data {
+        let (Some(ticker), Some(exch_code)) = (instrument.ticker, instrument.exch_code) else {
+            continue;
+        };
+        let security = to_security(
+            &ticker,
+            &exch_code,
+            instrument.name.unwrap_or_default(),
+            instrument.security_type,
+        );
+        // The same listing is often returned once per share class or trading venue
+        if !securities.iter().any(|s| s.symbol == security.symbol) {
+            securities.push(security);
+        }
+    }
+    Ok(securities)
+}
+
+/// `ERIC B` at `SS` becomes `ERIC-B.ST` at `STO`. Exchanges that Yahoo does not list keep the OpenFIGI
+/// ticker and exchange code.
+fn to_security(
+    ticker: &str,
+    exch_code: &str,
+    name: String,
+    security_type: Option<String>,
+) -> Security {
+    let ticker = ticker.trim().replace([' ', '/'], "-");
+    let (symbol, exchange) = match EXCHANGES.iter().find(|(code, ..)| *code == exch_code) {
+        Some((_, exchange, suffix)) => (format!("{ticker}{suffix}"), exchange.to_string()),
+        None => (ticker, exch_code.to_owned()),
+    };
+    Security {
+        symbol,
+        exchange,
+        name,
+        security_type,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use rand::Rng;
+
+    /// Serves one request for the given mapping jobs with the given JSON body, and returns the base
+    /// URL.
+    fn stub_server(jobs: &'static str, body: &str) -> String {
+        test_util::stub_server(vec![test_util::json_response(body)], move |request| {
+            assert!(request.starts_with("POST /v3/mapping "));
+            assert!(request.ends_with(jobs));
+        })
+    }
+
+    #[tokio::test]
+    async fn maps_isin_to_yahoo_symbol() {
+        let base_url = stub_server(
+            r#"[{"idType":"ID_ISIN","idValue":"SE0000108656"}]"#,
+            r#"[{"data":[
+            {"figi":"BBG000BQ0Y51","name":"ERICSSON LM-B SHS","ticker":"ERICB","exchCode":"GY","securityType":"Common Stock"},
+            {"figi":"BBG000BQ0KY2","name":"ERICSSON LM-B SHS","ticker":"ERIC B","exchCode":"SS","securityType":"Common Stock"},
+            {"figi":"BBG000BQ0L00","name":"ERICSSON LM-B SHS","ticker":"ERIC B","exchCode":"SS","securityType":"Common Stock"}]}]"#,
+        );
+        let cache_path = std::env::temp_dir().join(format!(
+            "pp-conv-test-openfigi-{}.json",
+            rand::rng().random::<u32>()
+        ));
+        let figi = OpenFigi::with_cache(
+            &base_url,
+            None,
+            LookupCache::load(cache_path.clone(), chrono::TimeDelta::days(5)).unwrap(),
+        );
+        let securities = figi.isin_to_securities("SE0000108656").await.unwrap();
+        assert_eq!(securities.len(), 2);
+        assert_eq!(securities[1].symbol, "ERIC-B.ST");
+        assert_eq!(securities[1].exchange, "STO");
+        assert_eq!(securities[1].security_type.as_deref(), Some("Common Stock"));
+        drop(securities);
+        figi.save_cache().await.unwrap();
+        std::fs::remove_file(cache_path).ok();
+    }
+
+    #[tokio::test]
+    async fn prefetches_several_isins_per_request() {
+        let base_url = stub_server(
+            r#"[{"idType":"ID_ISIN","idValue":"SE0000108656"},{"idType":"ID_ISIN","idValue":"SE0000000000"}]"#,
+            r#"[{"data":[{"name":"ERICSSON LM-B SHS","ticker":"ERIC B","exchCode":"SS"}]},
+            {"warning":"No identifier found."}]"#,
+        );
+        let cache_path = std::env::temp_dir().join(format!(
+            "pp-conv-test-openfigi-{}.json",
+            rand::rng().random::<u32>()
+        ));
+        let figi = OpenFigi::with_cache(
+            &base_url,
+            None,
+            LookupCache::load(cache_path.clone(), chrono::TimeDelta::days(5)).unwrap(),
+        );
+        figi.prefetch(&["SE0000108656".to_owned(), "SE0000000000".to_owned()])
+            .await;
+        // Both are answered from the cache, as the stub server only serves one request
+        let securities = figi.isin_to_securities("SE0000108656").await.unwrap();
+        assert_eq!(securities[0].symbol, "ERIC-B.ST");
+        drop(securities);
+        assert!(
+            figi.isin_to_securities("SE0000000000")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        figi.save_cache().await.unwrap();
+        std::fs::remove_file(cache_path).ok();
+    }
+}
@@ -1,185 +1,608 @@
-use std::{collections::HashSet, fs::File, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
 use crate::types::Currency;
+use anyhow::Context;
 use rust_decimal::Decimal;
-use serde::Serialize;
+
+mod files;
+mod json;
+mod ledger;
+mod profile;
+mod reader;
+mod xml;
+mod xstream;
+
+pub use files::{Existing, OutputFiles};
+pub use json::JsonWriter;
+pub use ledger::{LedgerFlavor, LedgerWriter};
+use profile::{ACCOUNT_COLUMNS, Column, PORTFOLIO_COLUMNS};
+pub use profile::{CsvFormat, CsvProfile};
+pub use reader::{CsvReader, diff, read_security_tickers};
+pub use xml::XmlWriter;
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Transaction {
     Portfolio(PortfolioTransaction),
     Account(AccountTransaction),
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone)]
 pub struct PortfolioTransaction {
     pub date: String,
-    #[serde(rename = "Securities Account")]
     pub securities_account: Option<String>,
-    #[serde(rename = "Cash Account")]
     pub cash_account: Option<String>,
-    #[serde(rename = "Type")]
     pub type_: PortfolioType,
     pub value: Decimal,
-    #[serde(rename = "Transaction Currency")]
     pub transaction_currency: Currency,
-    #[serde(rename = "Gross Amount")]
     pub gross_amount: Option<Decimal>,
-    // This is the currency of the gross amount
-    #[serde(rename = "Currency Gross Amount")]
+    /// This is the currency of the gross amount
     pub currency_gross_amount: Option<Currency>,
-    #[serde(rename = "Exchange Rate")]
     pub exchange_rate: Option<Decimal>,
     pub fees: Option<Decimal>,
     pub taxes: Option<Decimal>,
     pub shares: Option<Decimal>,
-    #[serde(rename = "ISIN")]
     pub isin: Option<String>,
-    #[serde(rename = "WKN")]
     pub wkn: Option<String>,
-    #[serde(rename = "Ticker Symbol")]
     pub ticker_symbol: Option<String>,
-    #[serde(rename = "Security Name")]
     pub security_name: Option<String>,
     pub note: Option<String>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone)]
 pub enum PortfolioType {
     Buy,
     Sell,
-    #[serde(rename = "Delivery (Inbound)")]
     DeliveryInbound,
-    #[serde(rename = "Delivery (Outbound)")]
     DeliveryOutbound,
-    #[serde(rename = "Transfer (Inbound)")]
     TransferInbound,
-    #[serde(rename = "Transfer (Outbound)")]
     TransferOutbound,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone)]
 pub struct AccountTransaction {
     pub date: String,
-    #[serde(rename = "Cash Account")]
     pub cash_account: String,
-    #[serde(rename = "Securities Account")]
     pub securities_account: Option<String>,
-    #[serde(rename = "Type")]
     pub type_: AccountType,
     pub value: Decimal,
-    #[serde(rename = "Transaction Currency")]
     pub transaction_currency: Currency,
-    // #[serde(rename = "Gross Amount")]
-    // pub gross_amount: Option<Decimal>,
-    // This is the currency of the gross amount
-    // #[serde(rename = "Currency Gross Amount")]
-    // pub currency_gross_amount: Option<Currency>,
-    // #[serde(rename = "Exchange Rate")]
-    // pub exchange_rate: Option<Decimal>,
-    // pub fees: Option<Decimal>,
-    // pub taxes: Option<Decimal>,
-    // pub shares: Option<Decimal>,
-    // #[serde(rename = "ISIN")]
-    // pub isin: Option<String>,
-    // #[serde(rename = "WKN")]
-    // pub wkn: Option<String>,
-    // #[serde(rename = "Ticker Symbol")]
-    // pub ticker_symbol: Option<String>,
-    // #[serde(rename = "Security Name")]
-    // pub security_name: Option<String>,
+    pub gross_amount: Option<Decimal>,
+    /// This is the currency of the gross amount
+    pub currency_gross_amount: Option<Currency>,
+    pub exchange_rate: Option<Decimal>,
+    pub fees: Option<Decimal>,
+    pub taxes: Option<Decimal>,
+    pub shares: Option<Decimal>,
+    pub isin: Option<String>,
+    pub wkn: Option<String>,
+    pub ticker_symbol: Option<String>,
+    pub security_name: Option<String>,
     pub note: Option<String>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone)]
 pub enum AccountType {
     Buy,
     Deposit,
     Dividend,
     Fees,
-    #[serde(rename = "Fees Refund")]
     FeesRefund,
     Interest,
-    #[serde(rename = "Interest Charge")]
     InterestCharge,
     Removal,
     Sell,
-    #[serde(rename = "Tax Refund")]
     TaxRefund,
     Taxes,
-    #[serde(rename = "Transfer (Inbound)")]
     TransferInbound,
-    #[serde(rename = "Transfer (Outbound)")]
     TransferOutbound,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum CsvWriterError {
     #[error("Failed to create file")]
-    CreateFileFailed(#[from] csv::Error),
+    CreateFileFailed(#[from] std::io::Error),
     #[error("Failed to write to file")]
     WriteFailed,
 }
 
+/// A backend that the converted transactions are written to.
+pub trait Writer: Send {
+    /// Writes a transaction. `source_line` is the line in the converted file that it comes from.
+    fn write(&mut self, transaction: &Transaction, source_line: Option<u64>) -> anyhow::Result<()>;
+
+    /// Completes the output, once all transactions are written.
+    fn finish(&mut self) -> anyhow::Result<()>;
+
+    /// A message for the log about the completed output, if there is anything to tell.
+    fn summary(&self) -> Option<String> {
+        None
+    }
+}
+
 pub struct CsvWriter {
-    portfolio_trans: csv::Writer<File>,
-    account_trans: csv::Writer<File>,
-    security_accounts: HashSet<String>,
-    cash_accounts: HashSet<String>,
+    portfolio_trans: CsvFile,
+    account_trans: CsvFile,
 }
 
 impl CsvWriter {
     /// Creates a new CSV writer for the given portfolio and account paths.
     /// The portfolio path is for the securities account transactions,
     /// and the account path is for the savings account transactions.
-    /// The CSV files will be created if they do not exist.
-    /// If the files already exist, they will be overwritten.
-    pub fn new<T: AsRef<Path>>(portfolio_path: T, account_path: T) -> Result<Self, CsvWriterError> {
+    /// The files are written to temporary files, which replace the given paths when `files` are committed.
+    pub fn new<T: AsRef<Path>>(
+        files: &OutputFiles,
+        portfolio_path: T,
+        account_path: T,
+        format: CsvFormat,
+    ) -> Result<Self, CsvWriterError> {
         Ok(CsvWriter {
-            portfolio_trans: csv::WriterBuilder::new()
-                .delimiter(b',')
-                .from_path(portfolio_path)
-                .map_err(CsvWriterError::CreateFileFailed)?,
-            account_trans: csv::WriterBuilder::new()
-                .delimiter(b',')
-                .from_path(account_path)
-                .map_err(CsvWriterError::CreateFileFailed)?,
-            security_accounts: HashSet::new(),
-            cash_accounts: HashSet::new(),
+            portfolio_trans: CsvFile::create(files, portfolio_path, format, PORTFOLIO_COLUMNS)?,
+            account_trans: CsvFile::create(files, account_path, format, ACCOUNT_COLUMNS)?,
         })
     }
+}
 
-    pub fn write(&mut self, transaction: &Transaction) -> Result<(), CsvWriterError> {
+impl Writer for CsvWriter {
+    fn write(
+        &mut self,
+        transaction: &Transaction,
+        _source_line: Option<u64>,
+    ) -> anyhow::Result<()> {
         match transaction {
-            Transaction::Portfolio(t) => {
-                self.portfolio_trans
-                    .serialize(t)
-                    .map_err(|_| CsvWriterError::WriteFailed)?;
-                if let Some(security_account) = &t.securities_account {
-                    self.security_accounts.insert(security_account.clone());
+            Transaction::Portfolio(_) => self.portfolio_trans.write(transaction)?,
+            Transaction::Account(_) => self.account_trans.write(transaction)?,
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.portfolio_trans.flush()?;
+        self.account_trans.flush()?;
+        Ok(())
+    }
+}
+
+/// A portfolio or account transactions CSV file.
+struct CsvFile {
+    writer: csv::Writer<File>,
+    format: CsvFormat,
+    columns: Vec<Column>,
+}
+
+impl CsvFile {
+    /// Creates the file and writes the header.
+    fn create<T: AsRef<Path>>(
+        files: &OutputFiles,
+        path: T,
+        format: CsvFormat,
+        columns: &[Column],
+    ) -> Result<Self, CsvWriterError> {
+        let columns = format.columns(columns);
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(format.delimiter)
+            .from_writer(files.create(path)?);
+        writer
+            .write_record(columns.iter().map(|c| c.name(format.language)))
+            .map_err(|_| CsvWriterError::WriteFailed)?;
+        Ok(CsvFile {
+            writer,
+            format,
+            columns,
+        })
+    }
+
+    fn write(&mut self, transaction: &Transaction) -> Result<(), CsvWriterError> {
+        self.writer
+            .write_record(self.format.record(transaction, &self.columns))
+            .map_err(|_| CsvWriterError::WriteFailed)
+    }
+
+    fn flush(&mut self) -> Result<(), CsvWriterError> {
+        self.writer.flush().map_err(|_| CsvWriterError::WriteFailed)
+    }
+}
+
+/// How the transactions are split into several CSV files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Split {
+    /// One pair of files per account
+    Account,
+    /// One pair of files per calendar year
+    Year,
+    /// One pair of files per account and calendar year
+    Both,
+}
+
+/// Writes the transactions to separate CSV files per account and/or year, named
+/// `<input>.<account>.<year>.pp-portfolio-transactions.csv` and so on.
+/// The files are created when the first transaction for them is written.
+pub struct SplitCsvWriter {
+    files: OutputFiles,
+    input_path: PathBuf,
+    split: Split,
+    format: CsvFormat,
+    /// The files in the order they were created
+    csv_files: Vec<(PathBuf, CsvFile)>,
+    index: HashMap<PathBuf, usize>,
+}
+
+impl SplitCsvWriter {
+    /// `input_path` is the converted file, which the file names are based on.
+    pub fn new<T: AsRef<Path>>(
+        files: &OutputFiles,
+        input_path: T,
+        split: Split,
+        format: CsvFormat,
+    ) -> Self {
+        SplitCsvWriter {
+            files: files.clone(),
+            input_path: input_path.as_ref().to_owned(),
+            split,
+            format,
+            csv_files: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    fn path(&self, transaction: &Transaction) -> PathBuf {
+        let (date, account, suffix) = match transaction {
+            Transaction::Portfolio(t) => (
+                &t.date,
+                t.securities_account.as_ref().or(t.cash_account.as_ref()),
+                "pp-portfolio-transactions.csv",
+            ),
+            Transaction::Account(t) => (
+                &t.date,
+                Some(&t.cash_account),
+                "pp-account-transactions.csv",
+            ),
+        };
+        let account = account.map_or_else(|| "okänt-konto".to_owned(), |a| file_name_part(a));
+        let year = date.get(..4).unwrap_or(date);
+        let group = match self.split {
+            Split::Account => account,
+            Split::Year => year.to_owned(),
+            Split::Both => format!("{account}.{year}"),
+        };
+        self.input_path.with_extension(format!("{group}.{suffix}"))
+    }
+}
+
+impl Writer for SplitCsvWriter {
+    fn write(
+        &mut self,
+        transaction: &Transaction,
+        _source_line: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let path = self.path(transaction);
+        let index = match self.index.get(&path) {
+            Some(&index) => index,
+            None => {
+                let columns = match transaction {
+                    Transaction::Portfolio(_) => PORTFOLIO_COLUMNS,
+                    Transaction::Account(_) => ACCOUNT_COLUMNS,
+                };
+                let file = CsvFile::create(&self.files, &path, self.format, columns)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                self.csv_files.push((path.clone(), file));
+                self.index.insert(path, self.csv_files.len() - 1);
+                self.csv_files.len() - 1
+            }
+        };
+        self.csv_files[index].1.write(transaction)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        for (_, file) in &mut self.csv_files {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn summary(&self) -> Option<String> {
+        let mut paths: Vec<_> = self.csv_files.iter().map(|(path, _)| path).collect();
+        paths.sort();
+        let list = |suffix: &str| {
+            paths
+                .iter()
+                .filter(|p| p.to_string_lossy().ends_with(suffix))
+                .map(|p| format!("\n* {}", p.display()))
+                .collect::<String>()
+        };
+        Some(format!(
+            "Portfolio transactions:{}\nAccount transactions:{}",
+            list("pp-portfolio-transactions.csv"),
+            list("pp-account-transactions.csv")
+        ))
+    }
+}
+
+/// Makes an account name usable in a file name.
+fn file_name_part(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// What a symbol lookup tells about a security, which the transactions do not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityDetails {
+    pub name: String,
+    /// The kind of security, e.g. `Fund`
+    pub security_type: Option<String>,
+    /// The currency that the listing is traded in
+    pub currency: Option<Currency>,
+}
+
+/// The details of the securities that were given a ticker during a conversion, by ticker symbol. Cloning
+/// gives another handle to the same details.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedSecurities {
+    inner: Arc<Mutex<HashMap<String, SecurityDetails>>>,
+}
+
+impl ResolvedSecurities {
+    pub fn insert(&self, ticker_symbol: &str, details: SecurityDetails) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.insert(ticker_symbol.to_owned(), details);
+    }
+
+    pub fn get(&self, ticker_symbol: &str) -> Option<SecurityDetails> {
+        self.inner.lock().unwrap().get(ticker_symbol).cloned()
+    }
+}
+
+/// A line in the securities CSV file, which Portfolio Performance can import to create the securities
+/// before the transactions are imported.
+#[derive(Debug, Clone)]
+struct Security {
+    isin: Option<String>,
+    wkn: Option<String>,
+    ticker_symbol: Option<String>,
+    security_name: Option<String>,
+    currency: Currency,
+    quote_feed: &'static str,
+}
+
+/// Collects the distinct securities of the transactions, and writes them to a CSV file.
+pub struct SecurityCsvWriter {
+    files: OutputFiles,
+    path: PathBuf,
+    format: CsvFormat,
+    resolved: ResolvedSecurities,
+    securities: Vec<Security>,
+    /// Index in `securities` by ISIN, WKN and ticker
+    index: HashMap<String, usize>,
+}
+
+impl SecurityCsvWriter {
+    /// The name, kind and currency of securities with a ticker are taken from `resolved` when the
+    /// file is written.
+    pub fn new<T: AsRef<Path>>(
+        files: &OutputFiles,
+        path: T,
+        format: CsvFormat,
+        resolved: &ResolvedSecurities,
+    ) -> Self {
+        SecurityCsvWriter {
+            files: files.clone(),
+            path: path.as_ref().to_owned(),
+            format,
+            resolved: resolved.clone(),
+            securities: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+impl Writer for SecurityCsvWriter {
+    fn write(
+        &mut self,
+        transaction: &Transaction,
+        _source_line: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let (isin, wkn, ticker_symbol, security_name, currency, is_trade) = match transaction {
+            Transaction::Portfolio(t) => (
+                &t.isin,
+                &t.wkn,
+                &t.ticker_symbol,
+                &t.security_name,
+                t.currency_gross_amount
+                    .as_ref()
+                    .unwrap_or(&t.transaction_currency),
+                true,
+            ),
+            Transaction::Account(t) => (
+                &t.isin,
+                &t.wkn,
+                &t.ticker_symbol,
+                &t.security_name,
+                &t.transaction_currency,
+                false,
+            ),
+        };
+        let keys: Vec<String> = [
+            isin.as_ref().map(|isin| format!("isin:{isin}")),
+            wkn.as_ref().map(|wkn| format!("wkn:{wkn}")),
+            ticker_symbol
+                .as_ref()
+                .map(|ticker| format!("ticker:{ticker}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let index = match keys.iter().find_map(|key| self.index.get(key)) {
+            Some(&index) => {
+                let security = &mut self.securities[index];
+                // Dividends are often paid in another currency than the security is traded in
+                if is_trade {
+                    security.currency = currency.clone();
                 }
-                if let Some(cash_account) = &t.cash_account {
-                    self.cash_accounts.insert(cash_account.clone());
+                security.isin = security.isin.take().or_else(|| isin.clone());
+                security.wkn = security.wkn.take().or_else(|| wkn.clone());
+                security.security_name = security
+                    .security_name
+                    .take()
+                    .or_else(|| security_name.clone());
+                if security.ticker_symbol.is_none() {
+                    security.ticker_symbol = ticker_symbol.clone();
+                    security.quote_feed = quote_feed(ticker_symbol);
                 }
+                index
             }
-            Transaction::Account(t) => {
-                self.account_trans
-                    .serialize(t)
-                    .map_err(|_| CsvWriterError::WriteFailed)?;
-                {
-                    if let Some(security_account) = &t.securities_account {
-                        self.security_accounts.insert(security_account.clone());
-                    }
-                    self.cash_accounts.insert(t.cash_account.clone());
-                }
+            None => {
+                self.securities.push(Security {
+                    isin: isin.clone(),
+                    wkn: wkn.clone(),
+                    ticker_symbol: ticker_symbol.clone(),
+                    security_name: security_name.clone(),
+                    currency: currency.clone(),
+                    quote_feed: quote_feed(ticker_symbol),
+                });
+                self.securities.len() - 1
             }
         };
+        for key in keys {
+            self.index.entry(key).or_insert(index);
+        }
         Ok(())
     }
 
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.format.delimiter)
+            .from_writer(
+                self.files
+                    .create(&self.path)
+                    .map_err(CsvWriterError::CreateFileFailed)?,
+            );
+        writer
+            .write_record(self.format.security_header())
+            .map_err(|_| CsvWriterError::WriteFailed)?;
+        for security in &self.securities {
+            let details = security
+                .ticker_symbol
+                .as_deref()
+                .and_then(|ticker| self.resolved.get(ticker));
+            let text = |s: Option<&str>| s.unwrap_or_default().to_owned();
+            // The names in the transactions are usually tidier than those of the lookups
+            let name = security
+                .security_name
+                .as_deref()
+                .or(details.as_ref().map(|d| d.name.as_str()));
+            let currency = details
+                .as_ref()
+                .and_then(|d| d.currency.as_ref())
+                .unwrap_or(&security.currency);
+            let security_type = details.as_ref().and_then(|d| d.security_type.as_deref());
+            writer
+                .write_record([
+                    text(security.isin.as_deref()),
+                    text(security.wkn.as_deref()),
+                    text(security.ticker_symbol.as_deref()),
+                    text(name),
+                    currency.as_str().to_owned(),
+                    security.quote_feed.to_owned(),
+                    text(security_type),
+                ])
+                .map_err(|_| CsvWriterError::WriteFailed)?;
+        }
+        writer.flush().context("Failed to write securities file")
+    }
+}
+
+/// Securities with a ticker get their prices from Yahoo Finance.
+fn quote_feed(ticker_symbol: &Option<String>) -> &'static str {
+    if ticker_symbol.is_some() {
+        "YAHOO"
+    } else {
+        "MANUAL"
+    }
+}
+
+/// Writes converted transactions to all the chosen backends, and keeps track of the accounts.
+#[derive(Default)]
+pub struct Output {
+    files: OutputFiles,
+    writers: Vec<Box<dyn Writer>>,
+    source_line: Option<u64>,
+    security_accounts: HashSet<String>,
+    cash_accounts: HashSet<String>,
+    resolved_securities: ResolvedSecurities,
+}
+
+impl Output {
+    /// The writers create their files in `files`, which are moved into place by [`Self::finish()`].
+    pub fn new(files: &OutputFiles) -> Self {
+        Output {
+            files: files.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, writer: impl Writer + 'static) {
+        self.writers.push(Box::new(writer));
+    }
+
+    /// Sets the line in the converted file that the following transactions come from.
+    pub fn set_source_line(&mut self, line: u64) {
+        self.source_line = Some(line);
+    }
+
+    pub fn write(&mut self, transaction: &Transaction) -> anyhow::Result<()> {
+        for writer in &mut self.writers {
+            writer.write(transaction, self.source_line)?;
+        }
+        let (security_account, cash_account) = match transaction {
+            Transaction::Portfolio(t) => (&t.securities_account, t.cash_account.as_ref()),
+            Transaction::Account(t) => (&t.securities_account, Some(&t.cash_account)),
+        };
+        if let Some(security_account) = security_account {
+            self.security_accounts.insert(security_account.clone());
+        }
+        if let Some(cash_account) = cash_account {
+            self.cash_accounts.insert(cash_account.clone());
+        }
+        Ok(())
+    }
+
+    /// Completes all outputs and moves the files into place. Returns the messages that the backends
+    /// have for the log.
+    pub fn finish(&mut self) -> anyhow::Result<Vec<String>> {
+        for writer in &mut self.writers {
+            writer.finish()?;
+        }
+        let mut messages = self
+            .files
+            .commit()
+            .context("Failed to save the output files")?;
+        messages.extend(self.writers.iter().filter_map(|w| w.summary()));
+        Ok(messages)
+    }
+
+    /// The details of the securities that were given a ticker, which the symbol lookups fill in.
+    pub fn resolved_securities(&self) -> &ResolvedSecurities {
+        &self.resolved_securities
+    }
+
     /// Returns an accumulated list of all created accounts.
     pub fn security_accounts(&self) -> &HashSet<String> {
         &self.security_accounts
@@ -190,3 +613,171 @@ impl CsvWriter {
         &self.cash_accounts
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn securities_file_follows_profile() {
+        let path = test_util::temp_path("securities.csv");
+        let resolved = ResolvedSecurities::default();
+        resolved.insert(
+            "VWRL.AS",
+            SecurityDetails {
+                name: "Vanguard FTSE All-World UCITS ETF".to_owned(),
+                security_type: Some("ETF".to_owned()),
+                currency: Some(Currency::new("EUR")),
+            },
+        );
+        let files = OutputFiles::default();
+        let mut writer =
+            SecurityCsvWriter::new(&files, &path, CsvProfile::German.into(), &resolved);
+        for (isin, ticker) in [("IE00B3RBWM25", Some("VWRL.AS")), ("SE0000108656", None)] {
+            let transaction = Transaction::Portfolio(PortfolioTransaction {
+                date: "2024-01-02".to_owned(),
+                securities_account: Some("ISK".to_owned()),
+                cash_account: Some("ISK".to_owned()),
+                type_: PortfolioType::Buy,
+                value: dec!(100),
+                transaction_currency: Currency::new("SEK"),
+                gross_amount: None,
+                currency_gross_amount: None,
+                exchange_rate: None,
+                fees: None,
+                taxes: None,
+                shares: Some(dec!(1)),
+                isin: Some(isin.to_owned()),
+                wkn: None,
+                ticker_symbol: ticker.map(str::to_owned),
+                security_name: None,
+                note: None,
+            });
+            writer.write(&transaction, None).unwrap();
+        }
+        writer.finish().unwrap();
+        files.commit().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "ISIN;WKN;Ticker-Symbol;Wertpapiername;Währung;Kursversorgung;Notiz\n\
+             IE00B3RBWM25;;VWRL.AS;Vanguard FTSE All-World UCITS ETF;EUR;YAHOO;ETF\n\
+             SE0000108656;;;;SEK;MANUAL;\n"
+        );
+        assert_eq!(
+            read_security_tickers(&path).unwrap(),
+            [(
+                "VWRL.AS".to_owned(),
+                Some("Vanguard FTSE All-World UCITS ETF".to_owned())
+            )]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn buy(date: &str, account: &str) -> Transaction {
+        Transaction::Portfolio(PortfolioTransaction {
+            date: date.to_owned(),
+            securities_account: Some(account.to_owned()),
+            cash_account: Some(account.to_owned()),
+            type_: PortfolioType::Buy,
+            value: dec!(100),
+            transaction_currency: Currency::new("SEK"),
+            gross_amount: None,
+            currency_gross_amount: None,
+            exchange_rate: None,
+            fees: None,
+            taxes: None,
+            shares: Some(dec!(1)),
+            isin: Some("SE0000108656".to_owned()),
+            wkn: None,
+            ticker_symbol: None,
+            security_name: None,
+            note: None,
+        })
+    }
+
+    fn deposit(date: &str, account: &str) -> Transaction {
+        Transaction::Account(AccountTransaction {
+            date: date.to_owned(),
+            cash_account: account.to_owned(),
+            securities_account: None,
+            type_: AccountType::Deposit,
+            value: dec!(100),
+            transaction_currency: Currency::new("SEK"),
+            gross_amount: None,
+            currency_gross_amount: None,
+            exchange_rate: None,
+            fees: None,
+            taxes: None,
+            shares: None,
+            isin: None,
+            wkn: None,
+            ticker_symbol: None,
+            security_name: None,
+            note: None,
+        })
+    }
+
+    #[test]
+    fn splits_by_account_and_year() {
+        let input_path = test_util::temp_path("trades.csv");
+        let transactions = [
+            deposit("2023-05-02", "ISK"),
+            buy("2023-05-02", "ISK"),
+            buy("2023-11-20 10:15", "ISK"),
+            deposit("2024-01-10", "KF / Avanza"),
+            buy("2024-01-10", "KF / Avanza"),
+            buy("2024-03-01", "ISK"),
+        ];
+        let files = OutputFiles::default();
+        let mut writer = SplitCsvWriter::new(&files, &input_path, Split::Both, Default::default());
+        for transaction in &transactions {
+            writer.write(transaction, None).unwrap();
+        }
+        writer.finish().unwrap();
+        files.commit().unwrap();
+
+        let path = |group: &str, kind: &str| {
+            input_path.with_extension(format!("{group}.pp-{kind}-transactions.csv"))
+        };
+        let created = [
+            (path("ISK.2023", "account"), 1),
+            (path("ISK.2023", "portfolio"), 2),
+            (path("KF___Avanza.2024", "account"), 1),
+            (path("KF___Avanza.2024", "portfolio"), 1),
+            (path("ISK.2024", "portfolio"), 1),
+        ];
+        for (path, rows) in &created {
+            let contents = std::fs::read_to_string(path).unwrap();
+            assert_eq!(contents.lines().count(), rows + 1, "{}", path.display());
+        }
+        assert_eq!(writer.csv_files.len(), created.len());
+        assert_eq!(
+            writer.summary().unwrap(),
+            format!(
+                "Portfolio transactions:\n* {}\n* {}\n* {}\nAccount transactions:\n* {}\n* {}",
+                path("ISK.2023", "portfolio").display(),
+                path("ISK.2024", "portfolio").display(),
+                path("KF___Avanza.2024", "portfolio").display(),
+                path("ISK.2023", "account").display(),
+                path("KF___Avanza.2024", "account").display(),
+            )
+        );
+        for (path, _) in &created {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        // The other splits only name the files by account or by year
+        let by_account =
+            SplitCsvWriter::new(&files, &input_path, Split::Account, Default::default());
+        assert_eq!(
+            by_account.path(&transactions[4]),
+            path("KF___Avanza", "portfolio")
+        );
+        let by_year = SplitCsvWriter::new(&files, &input_path, Split::Year, Default::default());
+        assert_eq!(by_year.path(&transactions[2]), path("2023", "portfolio"));
+    }
+}
@@ -0,0 +1,279 @@
+//! Output files are first written to temporary files next to them, and only renamed into place when
+//! the whole conversion has succeeded. A failed conversion leaves the previous output untouched, and so
+//! does a failure to move one of the files into place, as the files already moved are rolled back.
+
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// What to do with output files that already exist.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Existing {
+    /// Replace the files
+    #[default]
+    Overwrite,
+    /// Stop without writing anything
+    Refuse,
+    /// Keep the old files as numbered backups, `<file>.~1~`, `<file>.~2~` and so on
+    Backup,
+}
+
+/// The output files of a conversion. Cloning gives another handle to the same files.
+#[derive(Debug, Clone, Default)]
+pub struct OutputFiles {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    existing: Existing,
+    /// Temporary path and final path of each created file
+    files: Vec<(PathBuf, PathBuf)>,
+    /// Input files that are copied to numbered backups on commit
+    backups: Vec<PathBuf>,
+}
+
+impl OutputFiles {
+    pub fn new(existing: Existing) -> Self {
+        OutputFiles {
+            inner: Arc::new(Mutex::new(Inner {
+                existing,
+                files: Vec::new(),
+                backups: Vec::new(),
+            })),
+        }
+    }
+
+    /// Creates a temporary file for `path`, which becomes `path` on [`Self::commit()`].
+    pub fn create<T: AsRef<Path>>(&self, path: T) -> io::Result<File> {
+        let path = path.as_ref();
+        let mut inner = self.inner.lock().unwrap();
+        inner.check(path)?;
+        let temp_path = temp_path(path);
+        let file = File::create(&temp_path)?;
+        inner.files.push((temp_path, path.to_owned()));
+        Ok(file)
+    }
+
+    /// Copies `path` to a numbered backup on [`Self::commit()`], whatever [`Existing`] says. For input
+    /// files that the conversion builds on, such as a client file that is merged with.
+    pub fn backup<T: AsRef<Path>>(&self, path: T) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.backups.push(path.as_ref().to_owned());
+    }
+
+    /// Moves all created files into place. Returns messages about backups for the log.
+    ///
+    /// If a file cannot be moved into place, the files that already were are rolled back, so that
+    /// either all files are replaced or none of them.
+    pub fn commit(&self) -> io::Result<Vec<String>> {
+        let mut inner = self.inner.lock().unwrap();
+        // Check all files first, so that nothing is replaced if one of them is refused
+        for (_, path) in &inner.files {
+            inner.check(path)?;
+        }
+        let mut copies = Vec::new();
+        let mut placed = Vec::new();
+        let result = inner.place_all(&mut copies, &mut placed);
+        if let Err(e) = result {
+            for placed in placed.into_iter().rev() {
+                placed.roll_back();
+            }
+            for (_, backup) in copies {
+                fs::remove_file(backup).ok();
+            }
+            return Err(e);
+        }
+
+        let mut messages = Vec::new();
+        for (path, backup) in &copies {
+            messages.push(format!(
+                "Säkerhetskopia av {}: {}",
+                path.display(),
+                backup.display()
+            ));
+        }
+        for placed in placed {
+            if let Some(message) = placed.finish(inner.existing) {
+                messages.push(message);
+            }
+        }
+        Ok(messages)
+    }
+}
+
+/// A file that has been moved into place, and the previous file, which has been moved aside.
+struct Placed {
+    path: PathBuf,
+    aside: Option<PathBuf>,
+}
+
+impl Placed {
+    /// Puts the previous file back.
+    fn roll_back(self) {
+        fs::remove_file(&self.path).ok();
+        if let Some(aside) = &self.aside {
+            fs::rename(aside, &self.path).ok();
+        }
+    }
+
+    /// Removes the previous file, unless it is kept as a backup. Returns a message about the backup.
+    fn finish(self, existing: Existing) -> Option<String> {
+        let aside = self.aside?;
+        if existing == Existing::Backup {
+            return Some(format!(
+                "Säkerhetskopia av {}: {}",
+                self.path.display(),
+                aside.display()
+            ));
+        }
+        fs::remove_file(&aside).ok();
+        None
+    }
+}
+
+impl Inner {
+    /// Makes the backup copies, and moves the created files into place with the previous files moved
+    /// aside. What is done is added to `copies` and `placed`, so that it can be rolled back.
+    fn place_all(
+        &mut self,
+        copies: &mut Vec<(PathBuf, PathBuf)>,
+        placed: &mut Vec<Placed>,
+    ) -> io::Result<()> {
+        for path in std::mem::take(&mut self.backups) {
+            let backup = backup_path(&path);
+            fs::copy(&path, &backup)?;
+            copies.push((path, backup));
+        }
+        while let Some((temp_path, path)) = self.files.first().cloned() {
+            let aside = if path.exists() {
+                let aside = match self.existing {
+                    Existing::Backup => backup_path(&path),
+                    _ => old_path(&path),
+                };
+                fs::rename(&path, &aside)?;
+                Some(aside)
+            } else {
+                None
+            };
+            if let Err(e) = fs::rename(&temp_path, &path) {
+                if let Some(aside) = &aside {
+                    fs::rename(aside, &path).ok();
+                }
+                return Err(e);
+            }
+            self.files.remove(0);
+            placed.push(Placed { path, aside });
+        }
+        Ok(())
+    }
+
+    fn check(&self, path: &Path) -> io::Result<()> {
+        if self.existing == Existing::Refuse && path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} finns redan", path.display()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // The conversion failed, so the temporary files are not needed
+        for (temp_path, _) in &self.files {
+            fs::remove_file(temp_path).ok();
+        }
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Where the previous file is kept until all files are in place.
+fn old_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".old.tmp");
+    path.with_file_name(name)
+}
+
+/// The first free `<file>.~N~`.
+fn backup_path(path: &Path) -> PathBuf {
+    (1..)
+        .map(|n| {
+            let mut name = path.file_name().unwrap_or_default().to_owned();
+            name.push(format!(".~{n}~"));
+            path.with_file_name(name)
+        })
+        .find(|backup| !backup.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn files_are_replaced_on_commit_only() {
+        let dir = std::env::temp_dir().join(format!("pp-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.csv");
+        fs::write(&path, "old").unwrap();
+
+        let files = OutputFiles::new(Existing::Backup);
+        write!(files.create(&path).unwrap(), "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        files.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("out.csv.~1~")).unwrap(), "old");
+
+        // Dropped without commit
+        let files = OutputFiles::new(Existing::Overwrite);
+        write!(files.create(&path).unwrap(), "failed").unwrap();
+        drop(files);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!temp_path(&path).exists());
+
+        let files = OutputFiles::new(Existing::Refuse);
+        assert!(files.create(&path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_commit_is_rolled_back() {
+        let dir = std::env::temp_dir().join(format!("pp-files-rollback-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("a.csv");
+        let second = dir.join("b.csv");
+        fs::write(&first, "old a").unwrap();
+        fs::write(&second, "old b").unwrap();
+
+        let files = OutputFiles::new(Existing::Overwrite);
+        write!(files.create(&first).unwrap(), "new a").unwrap();
+        write!(files.create(&second).unwrap(), "new b").unwrap();
+        // The second file cannot be moved into place
+        fs::remove_file(temp_path(&second)).unwrap();
+        assert!(files.commit().is_err());
+        assert_eq!(fs::read_to_string(&first).unwrap(), "old a");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "old b");
+        drop(files);
+        let mut left: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["a.csv", "b.csv"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
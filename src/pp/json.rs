@@ -0,0 +1,306 @@
+//! Transactions as newline-delimited JSON, for further analysis in other tools.
+//!
+//! Every line is an object with `record` and `schema_version`. Transactions have `record: "transaction"`,
+//! and the last line is the `record: "summary"` object. Dates are ISO 8601 dates, amounts are decimal
+//! strings and types are fixed names such as `Buy` and `DeliveryInbound`, see [`portfolio_type()`] and
+//! [`account_type()`]. [`SCHEMA_VERSION`] is increased whenever a field is removed or changes meaning.
+
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::{AccountType, OutputFiles, PortfolioType, Transaction, Writer};
+use crate::types::iso_date;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+struct JsonTransaction<'a> {
+    record: &'static str,
+    schema_version: u32,
+    /// Line of the transaction in the converted file
+    source_line: Option<u64>,
+    /// `portfolio` or `account`
+    kind: &'static str,
+    #[serde(rename = "type")]
+    type_: &'static str,
+    date: String,
+    securities_account: Option<&'a str>,
+    cash_account: Option<&'a str>,
+    value: String,
+    currency: &'a str,
+    gross_amount: Option<String>,
+    gross_amount_currency: Option<&'a str>,
+    exchange_rate: Option<String>,
+    fees: Option<String>,
+    taxes: Option<String>,
+    shares: Option<String>,
+    isin: Option<&'a str>,
+    wkn: Option<&'a str>,
+    ticker_symbol: Option<&'a str>,
+    security_name: Option<&'a str>,
+    note: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonSummary {
+    record: &'static str,
+    schema_version: u32,
+    transactions: usize,
+    portfolio_transactions: usize,
+    account_transactions: usize,
+    first_date: Option<String>,
+    last_date: Option<String>,
+    securities_accounts: BTreeSet<String>,
+    cash_accounts: BTreeSet<String>,
+}
+
+/// Writes each transaction as a JSON line, followed by a summary line written by [`Self::finish()`].
+pub struct JsonWriter {
+    writer: BufWriter<File>,
+    summary: JsonSummary,
+}
+
+impl JsonWriter {
+    pub fn new<T: AsRef<Path>>(files: &OutputFiles, path: T) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let f = files
+            .create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(JsonWriter {
+            writer: BufWriter::new(f),
+            summary: JsonSummary {
+                record: "summary",
+                schema_version: SCHEMA_VERSION,
+                transactions: 0,
+                portfolio_transactions: 0,
+                account_transactions: 0,
+                first_date: None,
+                last_date: None,
+                securities_accounts: BTreeSet::new(),
+                cash_accounts: BTreeSet::new(),
+            },
+        })
+    }
+}
+
+impl Writer for JsonWriter {
+    fn write(&mut self, transaction: &Transaction, source_line: Option<u64>) -> anyhow::Result<()> {
+        let json = match transaction {
+            Transaction::Portfolio(t) => JsonTransaction {
+                record: "transaction",
+                schema_version: SCHEMA_VERSION,
+                source_line,
+                kind: "portfolio",
+                type_: portfolio_type(&t.type_),
+                date: iso_date(&t.date).unwrap_or_else(|| t.date.clone()),
+                securities_account: t.securities_account.as_deref(),
+                cash_account: t.cash_account.as_deref(),
+                value: t.value.to_string(),
+                currency: t.transaction_currency.as_str(),
+                gross_amount: decimal(t.gross_amount),
+                gross_amount_currency: t.currency_gross_amount.as_ref().map(|c| c.as_str()),
+                exchange_rate: decimal(t.exchange_rate),
+                fees: decimal(t.fees),
+                taxes: decimal(t.taxes),
+                shares: decimal(t.shares),
+                isin: t.isin.as_deref(),
+                wkn: t.wkn.as_deref(),
+                ticker_symbol: t.ticker_symbol.as_deref(),
+                security_name: t.security_name.as_deref(),
+                note: t.note.as_deref(),
+            },
+            Transaction::Account(t) => JsonTransaction {
+                record: "transaction",
+                schema_version: SCHEMA_VERSION,
+                source_line,
+                kind: "account",
+                type_: account_type(&t.type_),
+                date: iso_date(&t.date).unwrap_or_else(|| t.date.clone()),
+                securities_account: t.securities_account.as_deref(),
+                cash_account: Some(&t.cash_account),
+                value: t.value.to_string(),
+                currency: t.transaction_currency.as_str(),
+                gross_amount: decimal(t.gross_amount),
+                gross_amount_currency: t.currency_gross_amount.as_ref().map(|c| c.as_str()),
+                exchange_rate: decimal(t.exchange_rate),
+                fees: decimal(t.fees),
+                taxes: decimal(t.taxes),
+                shares: decimal(t.shares),
+                isin: t.isin.as_deref(),
+                wkn: t.wkn.as_deref(),
+                ticker_symbol: t.ticker_symbol.as_deref(),
+                security_name: t.security_name.as_deref(),
+                note: t.note.as_deref(),
+            },
+        };
+        serde_json::to_writer(&mut self.writer, &json)?;
+        writeln!(self.writer)?;
+
+        let summary = &mut self.summary;
+        summary.transactions += 1;
+        match transaction {
+            Transaction::Portfolio(_) => summary.portfolio_transactions += 1,
+            Transaction::Account(_) => summary.account_transactions += 1,
+        }
+        if summary.first_date.as_ref().is_none_or(|d| json.date < *d) {
+            summary.first_date = Some(json.date.clone());
+        }
+        if summary.last_date.as_ref().is_none_or(|d| json.date > *d) {
+            summary.last_date = Some(json.date.clone());
+        }
+        if let Some(account) = json.securities_account {
+            summary.securities_accounts.insert(account.to_owned());
+        }
+        if let Some(account) = json.cash_account {
+            summary.cash_accounts.insert(account.to_owned());
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, &self.summary)?;
+        writeln!(self.writer)?;
+        self.writer.flush().context("Failed to write JSON file")
+    }
+}
+
+fn decimal(value: Option<Decimal>) -> Option<String> {
+    value.map(|v| v.to_string())
+}
+
+/// The names of the types in the schema, which must not change with the names of the variants.
+fn portfolio_type(type_: &PortfolioType) -> &'static str {
+    match type_ {
+        PortfolioType::Buy => "Buy",
+        PortfolioType::Sell => "Sell",
+        PortfolioType::DeliveryInbound => "DeliveryInbound",
+        PortfolioType::DeliveryOutbound => "DeliveryOutbound",
+        PortfolioType::TransferInbound => "TransferInbound",
+        PortfolioType::TransferOutbound => "TransferOutbound",
+    }
+}
+
+fn account_type(type_: &AccountType) -> &'static str {
+    match type_ {
+        AccountType::Buy => "Buy",
+        AccountType::Deposit => "Deposit",
+        AccountType::Dividend => "Dividend",
+        AccountType::Fees => "Fees",
+        AccountType::FeesRefund => "FeesRefund",
+        AccountType::Interest => "Interest",
+        AccountType::InterestCharge => "InterestCharge",
+        AccountType::Removal => "Removal",
+        AccountType::Sell => "Sell",
+        AccountType::TaxRefund => "TaxRefund",
+        AccountType::Taxes => "Taxes",
+        AccountType::TransferInbound => "TransferInbound",
+        AccountType::TransferOutbound => "TransferOutbound",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use super::*;
+    use crate::{
+        pp::{AccountTransaction, PortfolioTransaction},
+        test_util,
+        types::Currency,
+    };
+
+    #[test]
+    fn writes_transactions_and_summary() {
+        let path = test_util::temp_path("transactions.jsonl");
+        let files = OutputFiles::default();
+        let mut writer = JsonWriter::new(&files, &path).unwrap();
+        let buy = Transaction::Portfolio(PortfolioTransaction {
+            date: "2024-03-01 09:30".to_owned(),
+            securities_account: Some("ISK".to_owned()),
+            cash_account: Some("ISK SEK".to_owned()),
+            type_: PortfolioType::DeliveryInbound,
+            value: dec!(1234.50),
+            transaction_currency: Currency::new("SEK"),
+            gross_amount: None,
+            currency_gross_amount: None,
+            exchange_rate: None,
+            fees: Some(dec!(1)),
+            taxes: None,
+            shares: Some(dec!(0.5)),
+            isin: Some("SE0000108656".to_owned()),
+            wkn: None,
+            ticker_symbol: Some("ERIC-B.ST".to_owned()),
+            security_name: Some("Ericsson B".to_owned()),
+            note: None,
+        });
+        let dividend = Transaction::Account(AccountTransaction {
+            date: "20240115".to_owned(),
+            cash_account: "ISK SEK".to_owned(),
+            securities_account: None,
+            type_: AccountType::Dividend,
+            value: dec!(12),
+            transaction_currency: Currency::new("SEK"),
+            gross_amount: Some(dec!(1.5)),
+            currency_gross_amount: Some(Currency::new("USD")),
+            exchange_rate: Some(dec!(10.8)),
+            fees: None,
+            taxes: Some(dec!(4.2)),
+            shares: None,
+            isin: None,
+            wkn: None,
+            ticker_symbol: None,
+            security_name: None,
+            note: Some("Utdelning".to_owned()),
+        });
+        writer.write(&buy, Some(2)).unwrap();
+        writer.write(&dividend, None).unwrap();
+        writer.finish().unwrap();
+        files.commit().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines[0],
+            serde_json::json!({
+                "record": "transaction", "schema_version": 1, "source_line": 2,
+                "kind": "portfolio", "type": "DeliveryInbound", "date": "2024-03-01",
+                "securities_account": "ISK", "cash_account": "ISK SEK",
+                "value": "1234.50", "currency": "SEK",
+                "gross_amount": null, "gross_amount_currency": null, "exchange_rate": null,
+                "fees": "1", "taxes": null, "shares": "0.5",
+                "isin": "SE0000108656", "wkn": null, "ticker_symbol": "ERIC-B.ST",
+                "security_name": "Ericsson B", "note": null,
+            })
+        );
+        assert_eq!(lines[1]["type"], "Dividend");
+        assert_eq!(lines[1]["kind"], "account");
+        assert_eq!(lines[1]["date"], "2024-01-15");
+        assert_eq!(lines[1]["source_line"], serde_json::Value::Null);
+        assert_eq!(lines[1]["gross_amount"], "1.5");
+        assert_eq!(lines[1]["gross_amount_currency"], "USD");
+        assert_eq!(lines[1]["exchange_rate"], "10.8");
+        assert_eq!(lines[1]["taxes"], "4.2");
+        assert_eq!(
+            lines[2],
+            serde_json::json!({
+                "record": "summary", "schema_version": 1, "transactions": 2,
+                "portfolio_transactions": 1, "account_transactions": 1,
+                "first_date": "2024-01-15", "last_date": "2024-03-01",
+                "securities_accounts": ["ISK"], "cash_accounts": ["ISK SEK"],
+            })
+        );
+        assert_eq!(lines.len(), 3);
+    }
+}
@@ -0,0 +1,586 @@
+//! Plain-text accounting output, as Beancount or hledger journals.
+//!
+//! Each converted account gets a hierarchy of its own, e.g. `Assets:ISK:Cash` for the money and
+//! `Assets:ISK:Securities` for the holdings, with fees, taxes and income in `Expenses:ISK:*` and
+//! `Income:ISK:*`. Securities become commodities with their ISIN as metadata. Buys are booked at cost,
+//! and sales reduce the holdings at cost with the sale price noted.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write as _,
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use rust_decimal::Decimal;
+
+use super::{AccountType, OutputFiles, PortfolioType, Transaction, Writer, profile::Language};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LedgerFlavor {
+    Beancount,
+    Hledger,
+}
+
+impl LedgerFlavor {
+    pub fn extension(self) -> &'static str {
+        match self {
+            LedgerFlavor::Beancount => "beancount",
+            LedgerFlavor::Hledger => "journal",
+        }
+    }
+}
+
+/// Writes the transactions as a Beancount or hledger journal, when all transactions are known.
+pub struct LedgerWriter {
+    files: OutputFiles,
+    path: PathBuf,
+    flavor: LedgerFlavor,
+    transactions: Vec<Transaction>,
+}
+
+#[derive(Debug, Default)]
+struct Commodity {
+    isin: Option<String>,
+    ticker: Option<String>,
+    name: Option<String>,
+}
+
+struct Entry {
+    date: String,
+    narration: String,
+    postings: Vec<(String, Option<String>)>,
+}
+
+impl LedgerWriter {
+    pub fn new<T: AsRef<Path>>(files: &OutputFiles, path: T, flavor: LedgerFlavor) -> Self {
+        LedgerWriter {
+            files: files.clone(),
+            path: path.as_ref().to_owned(),
+            flavor,
+            transactions: Vec::new(),
+        }
+    }
+}
+
+impl Writer for LedgerWriter {
+    fn write(
+        &mut self,
+        transaction: &Transaction,
+        _source_line: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.transactions.push(transaction.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let mut journal = Journal {
+            flavor: self.flavor,
+            commodities: BTreeMap::new(),
+            commodity_names: HashMap::new(),
+            accounts: BTreeSet::new(),
+            entries: Vec::new(),
+        };
+        for transaction in &self.transactions {
+            journal.add(transaction);
+        }
+        self.files
+            .create(&self.path)
+            .and_then(|mut f| f.write_all(journal.render().as_bytes()))
+            .with_context(|| format!("Failed to create {}", self.path.display()))
+    }
+}
+
+struct Journal {
+    flavor: LedgerFlavor,
+    commodities: BTreeMap<String, Commodity>,
+    /// Commodity names by security key
+    commodity_names: HashMap<String, String>,
+    accounts: BTreeSet<String>,
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    fn add(&mut self, transaction: &Transaction) {
+        let entry = match transaction {
+            Transaction::Portfolio(t) => {
+                let owner = account_component(t.securities_account.as_deref().unwrap_or("Okänt"));
+                let cash = t.cash_account.as_deref().map(account_component);
+                let currency = t.transaction_currency.as_str();
+                let commodity = self.commodity(
+                    t.isin.as_deref(),
+                    t.ticker_symbol.as_deref(),
+                    t.security_name.as_deref(),
+                );
+                let shares = t.shares.unwrap_or_default().abs();
+                let fees = t.fees.unwrap_or_default();
+                let taxes = t.taxes.unwrap_or_default();
+                let holdings = format!("Assets:{owner}:Securities");
+                let mut postings = Vec::new();
+                match t.type_ {
+                    PortfolioType::Buy | PortfolioType::Sell => {
+                        let cash = cash.unwrap_or_else(|| owner.clone());
+                        let is_buy = matches!(t.type_, PortfolioType::Buy);
+                        let gross = if is_buy {
+                            t.value - fees - taxes
+                        } else {
+                            t.value + fees + taxes
+                        };
+                        postings.push((
+                            holdings,
+                            Some(if is_buy {
+                                self.at_cost(shares, &commodity, gross, currency)
+                            } else {
+                                self.sold(shares, &commodity, gross, currency)
+                            }),
+                        ));
+                        let value = if is_buy { -t.value } else { t.value };
+                        postings
+                            .push((format!("Assets:{cash}:Cash"), Some(amount(value, currency))));
+                        self.fee_postings(&mut postings, &owner, fees, taxes, currency);
+                        if !is_buy && self.flavor == LedgerFlavor::Beancount {
+                            postings.push((format!("Income:{owner}:Gains"), None));
+                        }
+                    }
+                    PortfolioType::DeliveryInbound | PortfolioType::TransferInbound => {
+                        postings.push((
+                            holdings,
+                            Some(self.at_cost(shares, &commodity, t.value, currency)),
+                        ));
+                        if !t.value.is_zero() {
+                            postings.push((
+                                "Equity:Transfers".to_owned(),
+                                Some(amount(-t.value, currency)),
+                            ));
+                        }
+                    }
+                    PortfolioType::DeliveryOutbound | PortfolioType::TransferOutbound => {
+                        postings.push((
+                            holdings,
+                            Some(self.sold(shares, &commodity, t.value, currency)),
+                        ));
+                        postings.push(("Equity:Transfers".to_owned(), None));
+                    }
+                }
+                Entry {
+                    date: t.date.chars().take(10).collect(),
+                    narration: t.note.clone().unwrap_or_else(|| {
+                        format!(
+                            "{} {}",
+                            t.type_.name(Language::English),
+                            t.security_name.as_deref().unwrap_or(&commodity)
+                        )
+                    }),
+                    postings,
+                }
+            }
+            Transaction::Account(t) => {
+                let owner = account_component(&t.cash_account);
+                let currency = t.transaction_currency.as_str();
+                let fees = t.fees.unwrap_or_default();
+                let taxes = t.taxes.unwrap_or_default();
+                let value = t.value.abs();
+                let (sign, counter) = match t.type_ {
+                    AccountType::Deposit | AccountType::TransferInbound | AccountType::Sell => {
+                        (1, "Equity:Transfers".to_owned())
+                    }
+                    AccountType::Removal | AccountType::TransferOutbound | AccountType::Buy => {
+                        (-1, "Equity:Transfers".to_owned())
+                    }
+                    AccountType::Dividend => (1, format!("Income:{owner}:Dividends")),
+                    AccountType::Interest => (1, format!("Income:{owner}:Interest")),
+                    AccountType::InterestCharge => (-1, format!("Expenses:{owner}:Interest")),
+                    AccountType::Fees => (-1, format!("Expenses:{owner}:Fees")),
+                    AccountType::FeesRefund => (1, format!("Expenses:{owner}:Fees")),
+                    AccountType::Taxes => (-1, format!("Expenses:{owner}:Taxes")),
+                    AccountType::TaxRefund => (1, format!("Expenses:{owner}:Taxes")),
+                };
+                let mut postings = vec![(
+                    format!("Assets:{owner}:Cash"),
+                    Some(amount(value * Decimal::from(sign), currency)),
+                )];
+                self.fee_postings(&mut postings, &owner, fees, taxes, currency);
+                postings.push((counter, None));
+                let security = t.security_name.as_deref().or(t.ticker_symbol.as_deref());
+                Entry {
+                    date: t.date.chars().take(10).collect(),
+                    narration: t.note.clone().unwrap_or_else(|| {
+                        let type_ = t.type_.name(Language::English);
+                        match security {
+                            Some(security) => format!("{type_} {security}"),
+                            None => type_.to_owned(),
+                        }
+                    }),
+                    postings,
+                }
+            }
+        };
+        for (account, _) in &entry.postings {
+            self.accounts.insert(account.clone());
+        }
+        self.entries.push(entry);
+    }
+
+    fn fee_postings(
+        &self,
+        postings: &mut Vec<(String, Option<String>)>,
+        owner: &str,
+        fees: Decimal,
+        taxes: Decimal,
+        currency: &str,
+    ) {
+        if !fees.is_zero() {
+            postings.push((
+                format!("Expenses:{owner}:Fees"),
+                Some(amount(fees, currency)),
+            ));
+        }
+        if !taxes.is_zero() {
+            postings.push((
+                format!("Expenses:{owner}:Taxes"),
+                Some(amount(taxes, currency)),
+            ));
+        }
+    }
+
+    /// Shares that are added to the holdings, with their total cost.
+    fn at_cost(&self, shares: Decimal, commodity: &str, cost: Decimal, currency: &str) -> String {
+        let commodity = self.commodity_symbol(commodity);
+        let (shares, cost) = (shares.normalize(), cost.normalize());
+        match self.flavor {
+            LedgerFlavor::Beancount => format!("{shares} {commodity} {{{{{cost} {currency}}}}}"),
+            LedgerFlavor::Hledger => format!("{shares} {commodity} @@ {cost} {currency}"),
+        }
+    }
+
+    /// Shares that leave the holdings, at their booked cost, for the given total price.
+    fn sold(&self, shares: Decimal, commodity: &str, price: Decimal, currency: &str) -> String {
+        let commodity = self.commodity_symbol(commodity);
+        let (shares, price) = (shares.normalize(), price.normalize());
+        match self.flavor {
+            LedgerFlavor::Beancount => format!("-{shares} {commodity} {{}} @@ {price} {currency}"),
+            LedgerFlavor::Hledger => format!("-{shares} {commodity} @@ {price} {currency}"),
+        }
+    }
+
+    /// hledger needs quotes around commodities that contain anything but letters.
+    fn commodity_symbol(&self, commodity: &str) -> String {
+        if self.flavor == LedgerFlavor::Hledger && !commodity.chars().all(char::is_alphabetic) {
+            format!("\"{commodity}\"")
+        } else {
+            commodity.to_owned()
+        }
+    }
+
+    /// Returns the commodity of a security, created from its ticker, ISIN or name.
+    fn commodity(
+        &mut self,
+        isin: Option<&str>,
+        ticker: Option<&str>,
+        name: Option<&str>,
+    ) -> String {
+        let keys: Vec<String> = [
+            isin.map(|isin| format!("isin:{isin}")),
+            ticker.map(|ticker| format!("ticker:{ticker}")),
+            name.map(|name| format!("name:{name}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if let Some(commodity) = keys.iter().find_map(|key| self.commodity_names.get(key)) {
+            return commodity.clone();
+        }
+        let base = [ticker, isin, name]
+            .into_iter()
+            .flatten()
+            .map(commodity_name)
+            .find(|c| !c.is_empty())
+            .unwrap_or_else(|| "UNKNOWN".to_owned());
+        let mut commodity = base.clone();
+        let mut n = 1;
+        while self.commodities.contains_key(&commodity) {
+            n += 1;
+            commodity = format!("{}{n}", &base[..base.len().min(22)]);
+        }
+        self.commodities.insert(
+            commodity.clone(),
+            Commodity {
+                isin: isin.map(str::to_owned),
+                ticker: ticker.map(str::to_owned),
+                name: name.map(str::to_owned),
+            },
+        );
+        for key in keys {
+            self.commodity_names.insert(key, commodity.clone());
+        }
+        commodity
+    }
+
+    fn render(mut self) -> String {
+        self.entries.sort_by(|a, b| a.date.cmp(&b.date));
+        let first_date = self
+            .entries
+            .first()
+            .map(|e| e.date.clone())
+            .unwrap_or_else(|| "1970-01-01".to_owned());
+        let mut out = String::new();
+        match self.flavor {
+            LedgerFlavor::Beancount => {
+                out.push_str("option \"booking_method\" \"FIFO\"\n\n");
+                for (commodity, info) in &self.commodities {
+                    writeln!(out, "{first_date} commodity {commodity}").unwrap();
+                    for (key, value) in [
+                        ("isin", &info.isin),
+                        ("ticker", &info.ticker),
+                        ("name", &info.name),
+                    ] {
+                        if let Some(value) = value {
+                            writeln!(out, "  {key}: \"{}\"", escape(value)).unwrap();
+                        }
+                    }
+                }
+                out.push('\n');
+                for account in &self.accounts {
+                    writeln!(out, "{first_date} open {account}").unwrap();
+                }
+            }
+            LedgerFlavor::Hledger => {
+                for (commodity, info) in &self.commodities {
+                    writeln!(out, "commodity {}", self.commodity_symbol(commodity)).unwrap();
+                    for (key, value) in [
+                        ("isin", &info.isin),
+                        ("ticker", &info.ticker),
+                        ("name", &info.name),
+                    ] {
+                        if let Some(value) = value {
+                            writeln!(out, "  ; {key}: {value}").unwrap();
+                        }
+                    }
+                }
+                out.push('\n');
+                for account in &self.accounts {
+                    writeln!(out, "account {account}").unwrap();
+                }
+            }
+        }
+        for entry in &self.entries {
+            out.push('\n');
+            match self.flavor {
+                LedgerFlavor::Beancount => {
+                    writeln!(out, "{} * \"{}\"", entry.date, escape(&entry.narration)).unwrap()
+                }
+                LedgerFlavor::Hledger => {
+                    writeln!(out, "{} {}", entry.date, entry.narration).unwrap()
+                }
+            }
+            for (account, amount) in &entry.postings {
+                match amount {
+                    Some(amount) => writeln!(out, "  {account:<40}  {amount}").unwrap(),
+                    None => writeln!(out, "  {account}").unwrap(),
+                }
+            }
+        }
+        out
+    }
+}
+
+fn amount(value: Decimal, currency: &str) -> String {
+    format!("{} {currency}", value.normalize())
+}
+
+/// Turns an account name into an account name component, e.g. `Trading 212` into `Trading-212`.
+fn account_component(name: &str) -> String {
+    let component = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            // unwrap: Parts are not empty
+            let first = chars.next().unwrap();
+            first.to_uppercase().chain(chars).collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-");
+    if component.is_empty() {
+        "Okänt".to_owned()
+    } else {
+        component
+    }
+}
+
+/// Beancount commodities are upper case, start with a letter and are at most 24 characters long.
+fn commodity_name(text: &str) -> String {
+    let name: String = text
+        .to_uppercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '\'') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let name = name.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+    let name = if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("X{name}")
+    } else {
+        name.to_owned()
+    };
+    name.chars()
+        .take(24)
+        .collect::<String>()
+        .trim_end_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_owned()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pp::{AccountTransaction, PortfolioTransaction};
+    use crate::types::Currency;
+    use rust_decimal::dec;
+
+    fn journal(flavor: LedgerFlavor) -> Journal {
+        Journal {
+            flavor,
+            commodities: BTreeMap::new(),
+            commodity_names: HashMap::new(),
+            accounts: BTreeSet::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    fn trade(date: &str, type_: PortfolioType, value: Decimal, shares: Decimal) -> Transaction {
+        Transaction::Portfolio(PortfolioTransaction {
+            date: date.to_owned(),
+            securities_account: Some("ISK".to_owned()),
+            cash_account: Some("ISK".to_owned()),
+            type_,
+            value,
+            transaction_currency: Currency::new("SEK"),
+            gross_amount: None,
+            currency_gross_amount: None,
+            exchange_rate: None,
+            fees: Some(dec!(5)),
+            taxes: None,
+            shares: Some(shares),
+            isin: Some("SE0000108656".to_owned()),
+            wkn: None,
+            ticker_symbol: Some("ERIC-B.ST".to_owned()),
+            security_name: Some("Ericsson B".to_owned()),
+            note: None,
+        })
+    }
+
+    /// A buy of 10 shares for 1000 SEK, a sale of 4 of them for 500 SEK, both with 5 SEK in fees, and a
+    /// dividend of 100 SEK of which 15 SEK is withheld.
+    fn add_transactions(journal: &mut Journal) {
+        journal.add(&trade(
+            "2024-01-03",
+            PortfolioType::Buy,
+            dec!(1005),
+            dec!(10),
+        ));
+        journal.add(&trade(
+            "2024-02-05",
+            PortfolioType::Sell,
+            dec!(495),
+            dec!(4),
+        ));
+        journal.add(&Transaction::Account(AccountTransaction {
+            date: "2024-04-10".to_owned(),
+            cash_account: "ISK".to_owned(),
+            securities_account: Some("ISK".to_owned()),
+            type_: AccountType::Dividend,
+            value: dec!(85),
+            transaction_currency: Currency::new("SEK"),
+            gross_amount: None,
+            currency_gross_amount: None,
+            exchange_rate: None,
+            fees: None,
+            taxes: Some(dec!(15)),
+            shares: Some(dec!(6)),
+            isin: Some("SE0000108656".to_owned()),
+            wkn: None,
+            ticker_symbol: Some("ERIC-B.ST".to_owned()),
+            security_name: Some("Ericsson B".to_owned()),
+            note: None,
+        }));
+    }
+
+    /// The entries of a rendered journal, without the declarations before them.
+    fn entries(out: &str) -> &str {
+        // The declarations end with those of the accounts
+        let last = out
+            .rfind(" open ")
+            .or_else(|| out.rfind("account "))
+            .unwrap();
+        &out[last + out[last..].find('\n').unwrap() + 1..]
+    }
+
+    #[test]
+    fn beancount_books_buys_sales_and_dividends() {
+        let mut journal = journal(LedgerFlavor::Beancount);
+        add_transactions(&mut journal);
+        let out = journal.render();
+        assert!(out.contains("commodity ERIC-B.ST\n  isin: \"SE0000108656\""));
+        assert!(out.contains("open Assets:ISK:Securities"));
+        assert!(out.contains("open Income:ISK:Gains"));
+        // The buy balances at cost, the sale with the gain and the dividend with the withheld tax
+        assert_eq!(
+            entries(&out),
+            "
+2024-01-03 * \"Buy Ericsson B\"
+  Assets:ISK:Securities                     10 ERIC-B.ST {{1000 SEK}}
+  Assets:ISK:Cash                           -1005 SEK
+  Expenses:ISK:Fees                         5 SEK
+
+2024-02-05 * \"Sell Ericsson B\"
+  Assets:ISK:Securities                     -4 ERIC-B.ST {} @@ 500 SEK
+  Assets:ISK:Cash                           495 SEK
+  Expenses:ISK:Fees                         5 SEK
+  Income:ISK:Gains
+
+2024-04-10 * \"Dividend Ericsson B\"
+  Assets:ISK:Cash                           85 SEK
+  Expenses:ISK:Taxes                        15 SEK
+  Income:ISK:Dividends
+"
+        );
+    }
+
+    #[test]
+    fn hledger_books_buys_sales_and_dividends() {
+        let mut journal = journal(LedgerFlavor::Hledger);
+        add_transactions(&mut journal);
+        let out = journal.render();
+        assert!(out.contains("commodity \"ERIC-B.ST\"\n  ; isin: SE0000108656"));
+        assert!(out.contains("account Assets:ISK:Securities"));
+        // hledger balances the sale by its price, so it has no gains posting
+        assert_eq!(
+            entries(&out),
+            "
+2024-01-03 Buy Ericsson B
+  Assets:ISK:Securities                     10 \"ERIC-B.ST\" @@ 1000 SEK
+  Assets:ISK:Cash                           -1005 SEK
+  Expenses:ISK:Fees                         5 SEK
+
+2024-02-05 Sell Ericsson B
+  Assets:ISK:Securities                     -4 \"ERIC-B.ST\" @@ 500 SEK
+  Assets:ISK:Cash                           495 SEK
+  Expenses:ISK:Fees                         5 SEK
+
+2024-04-10 Dividend Ericsson B
+  Assets:ISK:Cash                           85 SEK
+  Expenses:ISK:Taxes                        15 SEK
+  Income:ISK:Dividends
+"
+        );
+    }
+}
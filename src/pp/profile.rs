@@ -0,0 +1,457 @@
+//! The layout of the CSV files, which depends on the language and version of Portfolio Performance.
+
+use rust_decimal::Decimal;
+
+use super::{AccountType, PortfolioType, Transaction};
+
+/// The language of the column names and transaction types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    German,
+}
+
+/// Predefined layouts for the CSV files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CsvProfile {
+    /// Comma separated, `.` as decimal separator and English column names
+    #[default]
+    English,
+    /// Semicolon separated, `,` as decimal separator and German column names
+    German,
+}
+
+/// How the CSV files are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvFormat {
+    pub delimiter: u8,
+    pub decimal_separator: char,
+    pub language: Language,
+    /// Write the time of day in a column of its own
+    pub time_column: bool,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        CsvProfile::default().into()
+    }
+}
+
+impl From<CsvProfile> for CsvFormat {
+    fn from(profile: CsvProfile) -> Self {
+        match profile {
+            CsvProfile::English => CsvFormat {
+                delimiter: b',',
+                decimal_separator: '.',
+                language: Language::English,
+                time_column: false,
+            },
+            CsvProfile::German => CsvFormat {
+                delimiter: b';',
+                decimal_separator: ',',
+                language: Language::German,
+                time_column: false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Date,
+    Time,
+    SecuritiesAccount,
+    CashAccount,
+    Type,
+    Value,
+    TransactionCurrency,
+    GrossAmount,
+    CurrencyGrossAmount,
+    ExchangeRate,
+    Fees,
+    Taxes,
+    Shares,
+    Isin,
+    Wkn,
+    TickerSymbol,
+    SecurityName,
+    Note,
+}
+
+impl Column {
+    const ALL: [Column; 18] = [
+        Column::Date,
+        Column::Time,
+        Column::SecuritiesAccount,
+        Column::CashAccount,
+        Column::Type,
+        Column::Value,
+        Column::TransactionCurrency,
+        Column::GrossAmount,
+        Column::CurrencyGrossAmount,
+        Column::ExchangeRate,
+        Column::Fees,
+        Column::Taxes,
+        Column::Shares,
+        Column::Isin,
+        Column::Wkn,
+        Column::TickerSymbol,
+        Column::SecurityName,
+        Column::Note,
+    ];
+
+    /// The column with the given English or German name, ignoring case.
+    pub fn from_name(name: &str) -> Option<(Column, Language)> {
+        let name = name.trim();
+        Column::ALL.into_iter().find_map(|column| {
+            [Language::English, Language::German]
+                .into_iter()
+                .find(|language| column.name(*language).eq_ignore_ascii_case(name))
+                .map(|language| (column, language))
+        })
+    }
+
+    pub fn name(self, language: Language) -> &'static str {
+        let (english, german) = match self {
+            Column::Date => ("Date", "Datum"),
+            Column::Time => ("Time", "Uhrzeit"),
+            Column::SecuritiesAccount => ("Securities Account", "Depot"),
+            Column::CashAccount => ("Cash Account", "Konto"),
+            Column::Type => ("Type", "Typ"),
+            Column::Value => ("Value", "Wert"),
+            Column::TransactionCurrency => ("Transaction Currency", "Buchungswährung"),
+            Column::GrossAmount => ("Gross Amount", "Bruttobetrag"),
+            Column::CurrencyGrossAmount => ("Currency Gross Amount", "Währung Bruttobetrag"),
+            Column::ExchangeRate => ("Exchange Rate", "Wechselkurs"),
+            Column::Fees => ("Fees", "Gebühren"),
+            Column::Taxes => ("Taxes", "Steuern"),
+            Column::Shares => ("Shares", "Stück"),
+            Column::Isin => ("ISIN", "ISIN"),
+            Column::Wkn => ("WKN", "WKN"),
+            Column::TickerSymbol => ("Ticker Symbol", "Ticker-Symbol"),
+            Column::SecurityName => ("Security Name", "Wertpapiername"),
+            Column::Note => ("Note", "Notiz"),
+        };
+        match language {
+            Language::English => english,
+            Language::German => german,
+        }
+    }
+}
+
+/// The columns of the portfolio transactions file, without the time column.
+pub const PORTFOLIO_COLUMNS: &[Column] = &[
+    Column::Date,
+    Column::SecuritiesAccount,
+    Column::CashAccount,
+    Column::Type,
+    Column::Value,
+    Column::TransactionCurrency,
+    Column::GrossAmount,
+    Column::CurrencyGrossAmount,
+    Column::ExchangeRate,
+    Column::Fees,
+    Column::Taxes,
+    Column::Shares,
+    Column::Isin,
+    Column::Wkn,
+    Column::TickerSymbol,
+    Column::SecurityName,
+    Column::Note,
+];
+
+/// The columns of the account transactions file, without the time column.
+pub const ACCOUNT_COLUMNS: &[Column] = &[
+    Column::Date,
+    Column::CashAccount,
+    Column::SecuritiesAccount,
+    Column::Type,
+    Column::Value,
+    Column::TransactionCurrency,
+    Column::GrossAmount,
+    Column::CurrencyGrossAmount,
+    Column::ExchangeRate,
+    Column::Fees,
+    Column::Taxes,
+    Column::Shares,
+    Column::Isin,
+    Column::Wkn,
+    Column::TickerSymbol,
+    Column::SecurityName,
+    Column::Note,
+];
+
+impl CsvFormat {
+    /// The columns to write, with the time column after the date if it is enabled.
+    pub fn columns(&self, columns: &[Column]) -> Vec<Column> {
+        let mut columns = columns.to_vec();
+        if self.time_column {
+            columns.insert(1, Column::Time);
+        }
+        columns
+    }
+}
+
+impl CsvFormat {
+    /// The header of the securities file. The kind of security goes in the note column.
+    pub fn security_header(&self) -> [&'static str; 7] {
+        let (currency, quote_feed) = match self.language {
+            Language::English => ("Currency", "Quote Feed"),
+            Language::German => ("Währung", "Kursversorgung"),
+        };
+        [
+            Column::Isin.name(self.language),
+            Column::Wkn.name(self.language),
+            Column::TickerSymbol.name(self.language),
+            Column::SecurityName.name(self.language),
+            currency,
+            quote_feed,
+            Column::Note.name(self.language),
+        ]
+    }
+
+    /// The fields of a transaction, in the order of `columns`.
+    pub fn record(&self, transaction: &Transaction, columns: &[Column]) -> Vec<String> {
+        columns
+            .iter()
+            .map(|column| self.field(transaction, *column))
+            .collect()
+    }
+
+    fn field(&self, transaction: &Transaction, column: Column) -> String {
+        let (date, securities_account, cash_account, type_, t) = match transaction {
+            Transaction::Portfolio(t) => (
+                &t.date,
+                t.securities_account.as_deref(),
+                t.cash_account.as_deref(),
+                t.type_.name(self.language),
+                Common {
+                    value: t.value,
+                    transaction_currency: t.transaction_currency.as_str(),
+                    gross_amount: t.gross_amount,
+                    currency_gross_amount: t.currency_gross_amount.as_ref().map(|c| c.as_str()),
+                    exchange_rate: t.exchange_rate,
+                    fees: t.fees,
+                    taxes: t.taxes,
+                    shares: t.shares,
+                    isin: t.isin.as_deref(),
+                    wkn: t.wkn.as_deref(),
+                    ticker_symbol: t.ticker_symbol.as_deref(),
+                    security_name: t.security_name.as_deref(),
+                    note: t.note.as_deref(),
+                },
+            ),
+            Transaction::Account(t) => (
+                &t.date,
+                t.securities_account.as_deref(),
+                Some(t.cash_account.as_str()),
+                t.type_.name(self.language),
+                Common {
+                    value: t.value,
+                    transaction_currency: t.transaction_currency.as_str(),
+                    gross_amount: t.gross_amount,
+                    currency_gross_amount: t.currency_gross_amount.as_ref().map(|c| c.as_str()),
+                    exchange_rate: t.exchange_rate,
+                    fees: t.fees,
+                    taxes: t.taxes,
+                    shares: t.shares,
+                    isin: t.isin.as_deref(),
+                    wkn: t.wkn.as_deref(),
+                    ticker_symbol: t.ticker_symbol.as_deref(),
+                    security_name: t.security_name.as_deref(),
+                    note: t.note.as_deref(),
+                },
+            ),
+        };
+        let text = |s: Option<&str>| s.unwrap_or_default().to_owned();
+        match column {
+            // Without a time column, the time stays in the date column
+            Column::Date if self.time_column => date.chars().take(10).collect(),
+            Column::Date => date.clone(),
+            Column::Time => date.get(11..).unwrap_or_default().trim().to_owned(),
+            Column::SecuritiesAccount => text(securities_account),
+            Column::CashAccount => text(cash_account),
+            Column::Type => type_.to_owned(),
+            Column::Value => self.decimal(Some(t.value)),
+            Column::TransactionCurrency => t.transaction_currency.to_owned(),
+            Column::GrossAmount => self.decimal(t.gross_amount),
+            Column::CurrencyGrossAmount => text(t.currency_gross_amount),
+            Column::ExchangeRate => self.decimal(t.exchange_rate),
+            Column::Fees => self.decimal(t.fees),
+            Column::Taxes => self.decimal(t.taxes),
+            Column::Shares => self.decimal(t.shares),
+            Column::Isin => text(t.isin),
+            Column::Wkn => text(t.wkn),
+            Column::TickerSymbol => text(t.ticker_symbol),
+            Column::SecurityName => text(t.security_name),
+            Column::Note => text(t.note),
+        }
+    }
+
+    fn decimal(&self, value: Option<Decimal>) -> String {
+        value
+            .map(|v| {
+                v.to_string()
+                    .replace('.', &self.decimal_separator.to_string())
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// The fields that portfolio and account transactions have in common.
+struct Common<'a> {
+    value: Decimal,
+    transaction_currency: &'a str,
+    gross_amount: Option<Decimal>,
+    currency_gross_amount: Option<&'a str>,
+    exchange_rate: Option<Decimal>,
+    fees: Option<Decimal>,
+    taxes: Option<Decimal>,
+    shares: Option<Decimal>,
+    isin: Option<&'a str>,
+    wkn: Option<&'a str>,
+    ticker_symbol: Option<&'a str>,
+    security_name: Option<&'a str>,
+    note: Option<&'a str>,
+}
+
+impl PortfolioType {
+    const ALL: [PortfolioType; 6] = [
+        PortfolioType::Buy,
+        PortfolioType::Sell,
+        PortfolioType::DeliveryInbound,
+        PortfolioType::DeliveryOutbound,
+        PortfolioType::TransferInbound,
+        PortfolioType::TransferOutbound,
+    ];
+
+    /// The type with the given English or German name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        PortfolioType::ALL.into_iter().find(|t| {
+            [Language::English, Language::German]
+                .into_iter()
+                .any(|language| t.name(language).eq_ignore_ascii_case(name.trim()))
+        })
+    }
+
+    pub fn name(&self, language: Language) -> &'static str {
+        let (english, german) = match self {
+            PortfolioType::Buy => ("Buy", "Kauf"),
+            PortfolioType::Sell => ("Sell", "Verkauf"),
+            PortfolioType::DeliveryInbound => ("Delivery (Inbound)", "Einlieferung"),
+            PortfolioType::DeliveryOutbound => ("Delivery (Outbound)", "Auslieferung"),
+            PortfolioType::TransferInbound => ("Transfer (Inbound)", "Umbuchung (Eingang)"),
+            PortfolioType::TransferOutbound => ("Transfer (Outbound)", "Umbuchung (Ausgang)"),
+        };
+        match language {
+            Language::English => english,
+            Language::German => german,
+        }
+    }
+}
+
+impl AccountType {
+    const ALL: [AccountType; 13] = [
+        AccountType::Buy,
+        AccountType::Deposit,
+        AccountType::Dividend,
+        AccountType::Fees,
+        AccountType::FeesRefund,
+        AccountType::Interest,
+        AccountType::InterestCharge,
+        AccountType::Removal,
+        AccountType::Sell,
+        AccountType::TaxRefund,
+        AccountType::Taxes,
+        AccountType::TransferInbound,
+        AccountType::TransferOutbound,
+    ];
+
+    /// The type with the given English or German name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        AccountType::ALL.into_iter().find(|t| {
+            [Language::English, Language::German]
+                .into_iter()
+                .any(|language| t.name(language).eq_ignore_ascii_case(name.trim()))
+        })
+    }
+
+    pub fn name(&self, language: Language) -> &'static str {
+        let (english, german) = match self {
+            AccountType::Buy => ("Buy", "Kauf"),
+            AccountType::Deposit => ("Deposit", "Einlage"),
+            AccountType::Dividend => ("Dividend", "Dividende"),
+            AccountType::Fees => ("Fees", "Gebühren"),
+            AccountType::FeesRefund => ("Fees Refund", "Gebührenerstattung"),
+            AccountType::Interest => ("Interest", "Zinsen"),
+            AccountType::InterestCharge => ("Interest Charge", "Zinsbelastung"),
+            AccountType::Removal => ("Removal", "Entnahme"),
+            AccountType::Sell => ("Sell", "Verkauf"),
+            AccountType::TaxRefund => ("Tax Refund", "Steuerrückerstattung"),
+            AccountType::Taxes => ("Taxes", "Steuern"),
+            AccountType::TransferInbound => ("Transfer (Inbound)", "Umbuchung (Eingang)"),
+            AccountType::TransferOutbound => ("Transfer (Outbound)", "Umbuchung (Ausgang)"),
+        };
+        match language {
+            Language::English => english,
+            Language::German => german,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use super::*;
+    use crate::{pp::PortfolioTransaction, types::Currency};
+
+    #[test]
+    fn german_record() {
+        let transaction = Transaction::Portfolio(PortfolioTransaction {
+            date: "2024-03-01 09:30".to_owned(),
+            securities_account: Some("ISK".to_owned()),
+            cash_account: Some("ISK".to_owned()),
+            type_: PortfolioType::Buy,
+            value: dec!(1234.5),
+            transaction_currency: Currency::new("SEK"),
+            gross_amount: None,
+            currency_gross_amount: None,
+            exchange_rate: None,
+            fees: Some(dec!(1)),
+            taxes: None,
+            shares: Some(dec!(10)),
+            isin: Some("SE0000108656".to_owned()),
+            wkn: None,
+            ticker_symbol: None,
+            security_name: Some("Ericsson B".to_owned()),
+            note: None,
+        });
+        let format = CsvFormat {
+            time_column: true,
+            ..CsvProfile::German.into()
+        };
+        let columns = format.columns(PORTFOLIO_COLUMNS);
+        assert_eq!(
+            columns[..7]
+                .iter()
+                .map(|c| c.name(format.language))
+                .collect::<Vec<_>>(),
+            [
+                "Datum",
+                "Uhrzeit",
+                "Depot",
+                "Konto",
+                "Typ",
+                "Wert",
+                "Buchungswährung"
+            ]
+        );
+        let record = format.record(&transaction, &columns);
+        assert_eq!(
+            record[..7],
+            ["2024-03-01", "09:30", "ISK", "ISK", "Kauf", "1234,5", "SEK"]
+        );
+        assert_eq!(record[10], "1");
+    }
+}
@@ -0,0 +1,445 @@
+//! Reads portfolio and account transaction CSV files back into [`Transaction`]s: the files written by
+//! [`super::CsvWriter`] in any profile, and the CSV files that Portfolio Performance exports.
+
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use anyhow::Context;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use super::profile::{ACCOUNT_COLUMNS, Column, Language, PORTFOLIO_COLUMNS};
+use super::{
+    AccountTransaction, AccountType, CsvFormat, PortfolioTransaction, PortfolioType, Transaction,
+};
+use crate::types::Currency;
+
+/// Reads the transactions of a CSV file. The delimiter and the language of the columns are recognized
+/// from the header, the decimal separator from the numbers, and columns that are not known are ignored.
+pub struct CsvReader {
+    records: csv::StringRecordsIntoIter<std::io::Cursor<String>>,
+    /// The column of each field, if it is known
+    columns: Vec<Option<Column>>,
+    decimal_separator: char,
+}
+
+impl CsvReader {
+    pub fn open<T: AsRef<Path>>(path: T) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let text = text.trim_start_matches('\u{feff}').to_owned();
+        let delimiter = delimiter(&text);
+        let open = |text: String| {
+            csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .flexible(true)
+                .from_reader(std::io::Cursor::new(text))
+        };
+        let mut reader = open(text.clone());
+        let (columns, languages): (Vec<_>, Vec<_>) = reader
+            .headers()?
+            .iter()
+            .map(|name| Column::from_name(name).unzip())
+            .unzip();
+        anyhow::ensure!(
+            [Column::Date, Column::Type, Column::Value]
+                .iter()
+                .all(|c| columns.contains(&Some(*c))),
+            "{} is not a Portfolio Performance transactions file",
+            path.display()
+        );
+        let german = languages.contains(&Some(Language::German));
+        // When the numbers do not tell, e.g. as they are all whole, the profile is guessed
+        let guess = if german || delimiter == b';' {
+            ','
+        } else {
+            '.'
+        };
+        let decimal_separator = detect_decimal_separator(open(text), &columns).unwrap_or(guess);
+        Ok(CsvReader {
+            records: reader.into_records(),
+            columns,
+            decimal_separator,
+        })
+    }
+
+    fn parse(&self, record: &csv::StringRecord) -> anyhow::Result<Transaction> {
+        let field = |column: Column| {
+            self.columns
+                .iter()
+                .position(|c| *c == Some(column))
+                .and_then(|i| record.get(i))
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+        };
+        let text = |column: Column| field(column).map(str::to_owned);
+        let decimal = |column: Column| {
+            field(column)
+                .map(|v| parse_decimal(v, self.decimal_separator))
+                .transpose()
+        };
+
+        let date = parse_date(field(Column::Date).context("Date is missing")?);
+        let date = match field(Column::Time) {
+            Some(time) => format!("{date} {time}"),
+            None => date,
+        };
+        let type_name = field(Column::Type).context("Type is missing")?;
+        let value = decimal(Column::Value)?.context("Value is missing")?;
+        let transaction_currency = Currency::new(
+            field(Column::TransactionCurrency).context("Transaction Currency is missing")?,
+        );
+        let shares = decimal(Column::Shares)?;
+
+        let portfolio_type = PortfolioType::from_name(type_name);
+        let account_type = AccountType::from_name(type_name);
+        let portfolio_type = match (portfolio_type, account_type) {
+            (Some(t @ (PortfolioType::Buy | PortfolioType::Sell)), _) => Some(t),
+            // Transfers of securities have shares, transfers of money do not
+            (Some(t), Some(_)) => shares.is_some().then_some(t),
+            (Some(t), None) => Some(t),
+            (None, Some(_)) => None,
+            (None, None) => anyhow::bail!("Unknown transaction type {type_name}"),
+        };
+        Ok(match portfolio_type {
+            Some(type_) => Transaction::Portfolio(PortfolioTransaction {
+                date,
+                securities_account: text(Column::SecuritiesAccount),
+                cash_account: text(Column::CashAccount),
+                type_,
+                value,
+                transaction_currency,
+                gross_amount: decimal(Column::GrossAmount)?,
+                currency_gross_amount: field(Column::CurrencyGrossAmount).map(Currency::new),
+                exchange_rate: decimal(Column::ExchangeRate)?,
+                fees: decimal(Column::Fees)?,
+                taxes: decimal(Column::Taxes)?,
+                shares,
+                isin: text(Column::Isin),
+                wkn: text(Column::Wkn),
+                ticker_symbol: text(Column::TickerSymbol),
+                security_name: text(Column::SecurityName),
+                note: text(Column::Note),
+            }),
+            None => Transaction::Account(AccountTransaction {
+                date,
+                // Files exported for a single account do not name it
+                cash_account: text(Column::CashAccount).unwrap_or_default(),
+                securities_account: text(Column::SecuritiesAccount),
+                type_: AccountType::from_name(type_name).unwrap(),
+                value,
+                transaction_currency,
+                gross_amount: decimal(Column::GrossAmount)?,
+                currency_gross_amount: field(Column::CurrencyGrossAmount).map(Currency::new),
+                exchange_rate: decimal(Column::ExchangeRate)?,
+                fees: decimal(Column::Fees)?,
+                taxes: decimal(Column::Taxes)?,
+                shares,
+                isin: text(Column::Isin),
+                wkn: text(Column::Wkn),
+                ticker_symbol: text(Column::TickerSymbol),
+                security_name: text(Column::SecurityName),
+                note: text(Column::Note),
+            }),
+        })
+    }
+}
+
+impl Iterator for CsvReader {
+    type Item = anyhow::Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.records.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let line = record.position().map_or(0, |p| p.line());
+        Some(
+            self.parse(&record)
+                .with_context(|| format!("Failed to read line {line}")),
+        )
+    }
+}
+
+/// Reads the ticker symbols and names of the securities in a securities file written by
+/// [`super::SecurityCsvWriter`], in any profile. Securities without ticker are left out.
+pub fn read_security_tickers<T: AsRef<Path>>(
+    path: T,
+) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let path = path.as_ref();
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let text = text.trim_start_matches('\u{feff}').to_owned();
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter(&text))
+        .flexible(true)
+        .from_reader(std::io::Cursor::new(text));
+    let position = |headers: &csv::StringRecord, column: Column| {
+        headers
+            .iter()
+            .position(|name| Column::from_name(name).map(|(c, _)| c) == Some(column))
+    };
+    let headers = reader.headers()?.clone();
+    let ticker_column = position(&headers, Column::TickerSymbol)
+        .with_context(|| format!("{} has no ticker symbol column", path.display()))?;
+    let name_column = position(&headers, Column::SecurityName);
+    let mut tickers = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let field = |i: usize| record.get(i).map(str::trim).filter(|s| !s.is_empty());
+        if let Some(ticker) = field(ticker_column) {
+            tickers.push((
+                ticker.to_owned(),
+                name_column.and_then(field).map(str::to_owned),
+            ));
+        }
+    }
+    Ok(tickers)
+}
+
+/// The decimal separator that most numbers in the file tell, if any do.
+fn detect_decimal_separator(
+    mut reader: csv::Reader<std::io::Cursor<String>>,
+    columns: &[Option<Column>],
+) -> Option<char> {
+    let numeric: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| {
+            matches!(
+                column,
+                Some(
+                    Column::Value
+                        | Column::GrossAmount
+                        | Column::ExchangeRate
+                        | Column::Fees
+                        | Column::Taxes
+                        | Column::Shares
+                )
+            )
+        })
+        .map(|(i, _)| i)
+        .collect();
+    let (mut points, mut commas) = (0, 0);
+    for record in reader.records().flatten() {
+        for field in numeric.iter().filter_map(|i| record.get(*i)) {
+            match decimal_separator_of(field.trim()) {
+                Some('.') => points += 1,
+                Some(',') => commas += 1,
+                _ => (),
+            }
+        }
+    }
+    match points.cmp(&commas) {
+        std::cmp::Ordering::Greater => Some('.'),
+        std::cmp::Ordering::Less => Some(','),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+/// The decimal separator of a number, if it can be told. In `1,000` and `1.000` the separator could be
+/// a thousands separator, so they tell nothing.
+fn decimal_separator_of(number: &str) -> Option<char> {
+    let last = number.rfind(['.', ','])?;
+    let separator = number[last..].chars().next()?;
+    let other = if separator == '.' { ',' } else { '.' };
+    if number.contains(other) {
+        // The last separator is the decimal separator in both `1,234.5` and `1.234,5`
+        return Some(separator);
+    }
+    if number.matches(separator).count() > 1 {
+        return Some(other);
+    }
+    let decimals = number[last + 1..]
+        .chars()
+        .filter(char::is_ascii_digit)
+        .count();
+    (decimals != 3).then_some(separator)
+}
+
+/// The most common of the delimiters in the header line.
+fn delimiter(text: &str) -> u8 {
+    let header = text.lines().next().unwrap_or_default();
+    [b',', b';', b'\t']
+        .into_iter()
+        .max_by_key(|d| header.bytes().filter(|b| b == d).count())
+        .unwrap()
+}
+
+/// Both `1234.5` and `1.234,5` are read, depending on the decimal separator.
+fn parse_decimal(text: &str, decimal_separator: char) -> anyhow::Result<Decimal> {
+    let thousands_separator = if decimal_separator == ',' { '.' } else { ',' };
+    let number: String = text
+        .chars()
+        .filter(|c| *c != thousands_separator && !c.is_whitespace())
+        .map(|c| if c == decimal_separator { '.' } else { c })
+        .collect();
+    Decimal::from_str(&number).with_context(|| format!("{text} is not a number"))
+}
+
+/// German dates (`31.01.2024`) are changed to ISO dates, other dates are kept as they are.
+fn parse_date(date: &str) -> String {
+    NaiveDate::parse_from_str(date, "%d.%m.%Y")
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|_| date.to_owned())
+}
+
+/// Compares two lists of transactions. Returns the transactions that are only in `old`, prefixed with
+/// `-`, followed by the transactions that are only in `new`, prefixed with `+`.
+pub fn diff(old: &[Transaction], new: &[Transaction]) -> Vec<String> {
+    let old: Vec<_> = old.iter().map(describe).collect();
+    let new: Vec<_> = new.iter().map(describe).collect();
+    let only_in = |a: &[String], b: &[String], prefix: &str| {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for line in b {
+            *counts.entry(line).or_default() += 1;
+        }
+        a.iter()
+            .filter(|line| match counts.get_mut(line.as_str()) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    false
+                }
+                _ => true,
+            })
+            .map(|line| format!("{prefix} {line}"))
+            .collect::<Vec<_>>()
+    };
+    let mut lines = only_in(&old, &new, "-");
+    lines.extend(only_in(&new, &old, "+"));
+    lines
+}
+
+/// A transaction as a line, with the numbers normalized so that `1.50` and `1.5` are equal.
+fn describe(transaction: &Transaction) -> String {
+    let columns = match transaction {
+        Transaction::Portfolio(_) => PORTFOLIO_COLUMNS,
+        Transaction::Account(_) => ACCOUNT_COLUMNS,
+    };
+    CsvFormat::default()
+        .record(transaction, columns)
+        .into_iter()
+        .map(|field| match Decimal::from_str(&field) {
+            Ok(number) => number.normalize().to_string(),
+            Err(_) => field,
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use super::*;
+    use crate::pp::{CsvProfile, CsvWriter, OutputFiles, Writer};
+
+    #[test]
+    fn reads_written_files() {
+        let dir = std::env::temp_dir().join(format!("pp-reader-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let portfolio_path = dir.join("p.csv");
+        let account_path = dir.join("a.csv");
+        let transactions = [
+            Transaction::Portfolio(PortfolioTransaction {
+                date: "2024-03-01 09:30".to_owned(),
+                securities_account: Some("ISK".to_owned()),
+                cash_account: Some("ISK".to_owned()),
+                type_: PortfolioType::Buy,
+                value: dec!(1234.50),
+                transaction_currency: Currency::new("SEK"),
+                gross_amount: Some(dec!(120)),
+                currency_gross_amount: Some(Currency::new("USD")),
+                exchange_rate: Some(dec!(0.0971)),
+                fees: Some(dec!(1)),
+                taxes: None,
+                shares: Some(dec!(10)),
+                isin: Some("US0378331005".to_owned()),
+                wkn: None,
+                ticker_symbol: Some("AAPL".to_owned()),
+                security_name: Some("Apple; Inc.".to_owned()),
+                note: None,
+            }),
+            Transaction::Account(AccountTransaction {
+                date: "2024-03-02".to_owned(),
+                cash_account: "ISK".to_owned(),
+                securities_account: None,
+                type_: AccountType::TransferInbound,
+                value: dec!(1000),
+                transaction_currency: Currency::new("SEK"),
+                gross_amount: None,
+                currency_gross_amount: None,
+                exchange_rate: None,
+                fees: None,
+                taxes: None,
+                shares: None,
+                isin: None,
+                wkn: None,
+                ticker_symbol: None,
+                security_name: None,
+                note: Some("Överföring".to_owned()),
+            }),
+        ];
+        let formats = [
+            CsvProfile::English.into(),
+            CsvFormat {
+                time_column: true,
+                ..CsvProfile::German.into()
+            },
+            // Delimiters and decimal separators that do not go with the language
+            CsvFormat {
+                delimiter: b';',
+                decimal_separator: ',',
+                ..CsvProfile::English.into()
+            },
+            CsvFormat {
+                delimiter: b',',
+                decimal_separator: '.',
+                ..CsvProfile::German.into()
+            },
+            CsvFormat {
+                delimiter: b'\t',
+                decimal_separator: ',',
+                ..CsvProfile::English.into()
+            },
+        ];
+        for format in formats {
+            let files = OutputFiles::default();
+            let mut writer =
+                CsvWriter::new(&files, &portfolio_path, &account_path, format).unwrap();
+            for transaction in &transactions {
+                writer.write(transaction, None).unwrap();
+            }
+            writer.finish().unwrap();
+            files.commit().unwrap();
+
+            let read: Vec<_> = CsvReader::open(&portfolio_path)
+                .unwrap()
+                .chain(CsvReader::open(&account_path).unwrap())
+                .collect::<anyhow::Result<_>>()
+                .unwrap();
+            assert_eq!(
+                read.iter().map(describe).collect::<Vec<_>>(),
+                transactions.iter().map(describe).collect::<Vec<_>>()
+            );
+            assert!(matches!(
+                &read[1],
+                Transaction::Account(t) if matches!(t.type_, AccountType::TransferInbound)
+            ));
+            assert!(diff(&read, &transactions).is_empty());
+        }
+        assert_eq!(diff(&transactions[..1], &transactions[1..]).len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tells_decimal_separator() {
+        assert_eq!(decimal_separator_of("1234.5"), Some('.'));
+        assert_eq!(decimal_separator_of("1234,5"), Some(','));
+        assert_eq!(decimal_separator_of("1,234.56"), Some('.'));
+        assert_eq!(decimal_separator_of("1.234,56"), Some(','));
+        assert_eq!(decimal_separator_of("1.234.567"), Some(','));
+        assert_eq!(decimal_separator_of("1,000"), None);
+        assert_eq!(decimal_separator_of("100"), None);
+    }
+}
@@ -0,0 +1,822 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::{NaiveDate, NaiveDateTime};
+use rand::Rng;
+use rust_decimal::{Decimal, dec, prelude::ToPrimitive};
+
+use super::xstream::{Document, NodeId};
+use super::{
+    AccountTransaction, AccountType, OutputFiles, PortfolioTransaction, PortfolioType, Transaction,
+    Writer,
+};
+use crate::types::Currency;
+
+/// The client file version that the written files follow.
+const CLIENT_VERSION: &str = "57";
+
+/// Writes a complete Portfolio Performance client file (`.xml`), with securities, accounts and transactions.
+///
+/// Transactions are collected by [`Self::write()`] and the file is written by [`Self::finish()`].
+/// With [`Self::merge_with()`], the transactions are added to a copy of an existing client file instead.
+pub struct XmlWriter {
+    files: OutputFiles,
+    path: PathBuf,
+    existing: Option<PathBuf>,
+    transactions: Vec<Transaction>,
+    /// Transactions that were already in the existing file
+    skipped: usize,
+}
+
+impl XmlWriter {
+    pub fn new<T: AsRef<Path>>(files: &OutputFiles, path: T) -> Self {
+        XmlWriter {
+            files: files.clone(),
+            path: path.as_ref().to_owned(),
+            existing: None,
+            transactions: Vec::new(),
+            skipped: 0,
+        }
+    }
+
+    /// Starts from an existing client file. Securities are matched by ISIN or ticker, accounts by name,
+    /// and transactions that already are in the file are left out. The existing file is backed up and
+    /// never written to.
+    pub fn merge_with<T: AsRef<Path>>(mut self, existing: T) -> Self {
+        self.existing = Some(existing.as_ref().to_owned());
+        self
+    }
+}
+
+impl Writer for XmlWriter {
+    fn write(
+        &mut self,
+        transaction: &Transaction,
+        _source_line: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.transactions.push(transaction.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let mut client = match &self.existing {
+            Some(existing) => {
+                if fs::canonicalize(existing).ok() == fs::canonicalize(&self.path).ok() {
+                    anyhow::bail!("Will not overwrite {}", existing.display());
+                }
+                let text = fs::read_to_string(existing)
+                    .with_context(|| format!("Failed to read {}", existing.display()))?;
+                self.files.backup(existing);
+                let doc = Document::parse(&text).with_context(|| {
+                    format!(
+                        "{} is not an unencrypted Portfolio Performance XML file",
+                        existing.display()
+                    )
+                })?;
+                ClientBuilder::from_existing(doc)
+            }
+            None => ClientBuilder::new(base_currency(&self.transactions)),
+        };
+        for transaction in &self.transactions {
+            if !client.add(transaction)? {
+                self.skipped += 1;
+            }
+        }
+        let f = self
+            .files
+            .create(&self.path)
+            .with_context(|| format!("Failed to create {}", self.path.display()))?;
+        client
+            .doc
+            .write(&mut BufWriter::new(f))
+            .context("Failed to write client file")?;
+        Ok(())
+    }
+
+    fn summary(&self) -> Option<String> {
+        self.existing.as_ref().map(|existing| {
+            format!(
+                "{} transaktioner fanns redan i {} och hoppades över",
+                self.skipped,
+                existing.display()
+            )
+        })
+    }
+}
+
+/// The most common transaction currency.
+fn base_currency(transactions: &[Transaction]) -> Currency {
+    let mut counts: HashMap<&Currency, usize> = HashMap::new();
+    for t in transactions {
+        let currency = match t {
+            Transaction::Portfolio(t) => &t.transaction_currency,
+            Transaction::Account(t) => &t.transaction_currency,
+        };
+        *counts.entry(currency).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(currency, _)| currency.clone())
+        .unwrap_or_else(|| Currency::new("SEK"))
+}
+
+/// Builds the XML tree of a client. Securities and accounts are created as transactions refer to them.
+struct ClientBuilder {
+    doc: Document,
+    securities: NodeId,
+    accounts: NodeId,
+    portfolios: NodeId,
+    /// Securities by all their keys, see [`security_keys()`]
+    security_nodes: HashMap<String, NodeId>,
+    /// Cash accounts by name and currency
+    account_nodes: HashMap<(String, Currency), Node>,
+    portfolio_nodes: HashMap<String, Node>,
+    /// The number of transactions of each kind in the existing file
+    existing_account_transactions: HashMap<TransactionKey, usize>,
+    existing_portfolio_transactions: HashMap<TransactionKey, usize>,
+}
+
+/// Date, type, amount and security of a transaction, which are compared to find transactions that are
+/// already in the existing file.
+type TransactionKey = (String, String, String, Option<NodeId>);
+
+/// An account or portfolio, and the list of its transactions.
+#[derive(Clone, Copy)]
+struct Node {
+    node: NodeId,
+    transactions: NodeId,
+}
+
+impl ClientBuilder {
+    fn new(base_currency: Currency) -> Self {
+        let mut doc = Document::new("client");
+        let root = doc.root();
+        doc.text_element(root, "version", CLIENT_VERSION);
+        doc.text_element(root, "baseCurrency", base_currency.as_str());
+        let securities = doc.element(root, "securities");
+        doc.element(root, "watchlists");
+        let accounts = doc.element(root, "accounts");
+        let portfolios = doc.element(root, "portfolios");
+        doc.element(root, "plans");
+        doc.element(root, "taxonomies");
+        doc.element(root, "dashboards");
+        doc.element(root, "properties");
+        let settings = doc.element(root, "settings");
+        doc.element(settings, "bookmarks");
+        doc.element(settings, "attributeTypes");
+        doc.element(settings, "configurationSets");
+        ClientBuilder {
+            doc,
+            securities,
+            accounts,
+            portfolios,
+            security_nodes: HashMap::new(),
+            account_nodes: HashMap::new(),
+            portfolio_nodes: HashMap::new(),
+            existing_account_transactions: HashMap::new(),
+            existing_portfolio_transactions: HashMap::new(),
+        }
+    }
+
+    /// Continues building on a client file that was read from disk.
+    fn from_existing(mut doc: Document) -> Self {
+        let root = doc.root();
+        let mut list = |name| match doc.child(root, name) {
+            Some(node) => node,
+            None => doc.element(root, name),
+        };
+        let securities = list("securities");
+        let accounts = list("accounts");
+        let portfolios = list("portfolios");
+        let mut client = ClientBuilder {
+            doc,
+            securities,
+            accounts,
+            portfolios,
+            security_nodes: HashMap::new(),
+            account_nodes: HashMap::new(),
+            portfolio_nodes: HashMap::new(),
+            existing_account_transactions: HashMap::new(),
+            existing_portfolio_transactions: HashMap::new(),
+        };
+
+        let doc = &mut client.doc;
+        for security in doc.children(securities) {
+            let security = doc.resolve(security);
+            for key in security_keys(
+                doc.child_text(security, "isin"),
+                doc.child_text(security, "wkn"),
+                doc.child_text(security, "tickerSymbol"),
+                doc.child_text(security, "name"),
+            ) {
+                client.security_nodes.entry(key).or_insert(security);
+            }
+        }
+        for (list, is_account) in [(accounts, true), (portfolios, false)] {
+            for owner in doc.children(list) {
+                let owner = doc.resolve(owner);
+                let Some(name) = doc.child_text(owner, "name").map(str::to_owned) else {
+                    continue;
+                };
+                let transactions = match doc.child(owner, "transactions") {
+                    Some(transactions) => transactions,
+                    None => doc.element(owner, "transactions"),
+                };
+                for transaction in doc.children(transactions) {
+                    let transaction = doc.resolve(transaction);
+                    let key = (
+                        doc.child_text(transaction, "date")
+                            .unwrap_or_default()
+                            .chars()
+                            .take(10)
+                            .collect(),
+                        doc.child_text(transaction, "type")
+                            .unwrap_or_default()
+                            .to_owned(),
+                        doc.child_text(transaction, "amount")
+                            .unwrap_or_default()
+                            .to_owned(),
+                        doc.child(transaction, "security"),
+                    );
+                    let existing = if is_account {
+                        &mut client.existing_account_transactions
+                    } else {
+                        &mut client.existing_portfolio_transactions
+                    };
+                    *existing.entry(key).or_default() += 1;
+                }
+                let node = Node {
+                    node: owner,
+                    transactions,
+                };
+                if is_account {
+                    let currency = doc.child_text(owner, "currencyCode").unwrap_or("EUR");
+                    client
+                        .account_nodes
+                        .entry((name, Currency::new(currency)))
+                        .or_insert(node);
+                } else {
+                    client.portfolio_nodes.entry(name).or_insert(node);
+                }
+            }
+        }
+        client
+    }
+
+    /// Adds a transaction, unless it already was in the existing file. Returns whether it was added.
+    fn add(&mut self, transaction: &Transaction) -> anyhow::Result<bool> {
+        match transaction {
+            Transaction::Portfolio(t) => self.add_portfolio_transaction(t),
+            Transaction::Account(t) => self.add_account_transaction(t),
+        }
+    }
+
+    fn add_portfolio_transaction(&mut self, t: &PortfolioTransaction) -> anyhow::Result<bool> {
+        let security_currency = t
+            .currency_gross_amount
+            .clone()
+            .unwrap_or_else(|| t.transaction_currency.clone());
+        let security = self.security(
+            t.isin.as_deref(),
+            t.wkn.as_deref(),
+            t.ticker_symbol.as_deref(),
+            t.security_name.as_deref(),
+            &security_currency,
+        )?;
+        let portfolio_name = t
+            .securities_account
+            .as_deref()
+            .context("Transaction without securities account")?;
+        let cash_name = t.cash_account.as_deref().unwrap_or(portfolio_name);
+        let account = self.account(cash_name, &t.transaction_currency);
+        let portfolio = self.portfolio(portfolio_name, account.node);
+
+        let (type_, is_buy_sell) = match t.type_ {
+            PortfolioType::Buy => ("BUY", true),
+            PortfolioType::Sell => ("SELL", true),
+            // Transfers need a second securities account, so they are written as deliveries
+            PortfolioType::DeliveryInbound | PortfolioType::TransferInbound => {
+                ("DELIVERY_INBOUND", false)
+            }
+            PortfolioType::DeliveryOutbound | PortfolioType::TransferOutbound => {
+                ("DELIVERY_OUTBOUND", false)
+            }
+        };
+        let date = xml_date(&t.date)?;
+        let currency = t.transaction_currency.as_str();
+        let key = transaction_key(&date, type_, t.value, Some(security));
+        if take_existing(&mut self.existing_portfolio_transactions, &key) {
+            return Ok(false);
+        }
+
+        let doc = &mut self.doc;
+        let ptx = doc.element(portfolio.transactions, "portfolio-transaction");
+        doc.text_element(ptx, "uuid", &uuid());
+        doc.text_element(ptx, "date", &date);
+        doc.text_element(ptx, "currencyCode", currency);
+        doc.text_element(ptx, "amount", &amount(t.value));
+        doc.reference(ptx, "security", security);
+        let cross_entry = is_buy_sell.then(|| {
+            let cross_entry = doc.element(ptx, "crossEntry");
+            doc.set_attribute(cross_entry, "class", "buysell");
+            cross_entry
+        });
+        doc.text_element(ptx, "shares", &shares(t.shares.unwrap_or_default()));
+        if let Some(note) = &t.note {
+            doc.text_element(ptx, "note", note);
+        }
+        let units = doc.element(ptx, "units");
+        let fees = t.fees.unwrap_or_default();
+        let taxes = t.taxes.unwrap_or_default();
+        add_unit(doc, units, "FEE", fees, currency);
+        add_unit(doc, units, "TAX", taxes, currency);
+        if security_currency != t.transaction_currency
+            && let Some(rate) = t.exchange_rate.filter(|r| !r.is_zero())
+        {
+            // The CSV exchange rate is in units of the security currency per transaction currency,
+            // while the client file has it the other way around.
+            let gross = match t.type_ {
+                PortfolioType::Buy => t.value - fees - taxes,
+                _ => t.value + fees + taxes,
+            };
+            let unit = doc.element(units, "unit");
+            doc.set_attribute(unit, "type", "GROSS_VALUE");
+            let amount_node = doc.element(unit, "amount");
+            doc.set_attribute(amount_node, "currency", currency);
+            doc.set_attribute(amount_node, "amount", &amount(gross));
+            let forex = doc.element(unit, "forex");
+            doc.set_attribute(forex, "currency", security_currency.as_str());
+            doc.set_attribute(forex, "amount", &amount(gross * rate));
+            doc.text_element(
+                unit,
+                "exchangeRate",
+                &(dec!(1) / rate).round_dp(10).normalize().to_string(),
+            );
+        }
+        doc.text_element(ptx, "type", type_);
+
+        if let Some(cross_entry) = cross_entry {
+            let atx = doc.element(account.transactions, "account-transaction");
+            doc.text_element(atx, "uuid", &uuid());
+            doc.text_element(atx, "date", &date);
+            doc.text_element(atx, "currencyCode", currency);
+            doc.text_element(atx, "amount", &amount(t.value));
+            doc.reference(atx, "security", security);
+            let atx_cross_entry = doc.reference(atx, "crossEntry", cross_entry);
+            doc.set_attribute(atx_cross_entry, "class", "buysell");
+            doc.text_element(atx, "shares", "0");
+            doc.element(atx, "units");
+            doc.text_element(atx, "type", type_);
+
+            doc.reference(cross_entry, "portfolio", portfolio.node);
+            doc.reference(cross_entry, "portfolioTransaction", ptx);
+            doc.reference(cross_entry, "account", account.node);
+            doc.reference(cross_entry, "accountTransaction", atx);
+        }
+        Ok(true)
+    }
+
+    fn add_account_transaction(&mut self, t: &AccountTransaction) -> anyhow::Result<bool> {
+        let security = if t.isin.is_some() || t.wkn.is_some() || t.ticker_symbol.is_some() {
+            Some(self.security(
+                t.isin.as_deref(),
+                t.wkn.as_deref(),
+                t.ticker_symbol.as_deref(),
+                t.security_name.as_deref(),
+                &t.transaction_currency,
+            )?)
+        } else {
+            None
+        };
+        let account = self.account(&t.cash_account, &t.transaction_currency);
+        let type_ = match t.type_ {
+            AccountType::Deposit | AccountType::TransferInbound | AccountType::Sell => "DEPOSIT",
+            AccountType::Removal | AccountType::TransferOutbound | AccountType::Buy => "REMOVAL",
+            AccountType::Dividend => "DIVIDENDS",
+            AccountType::Fees => "FEES",
+            AccountType::FeesRefund => "FEES_REFUND",
+            AccountType::Interest => "INTEREST",
+            AccountType::InterestCharge => "INTEREST_CHARGE",
+            AccountType::Taxes => "TAXES",
+            AccountType::TaxRefund => "TAX_REFUND",
+        };
+        let currency = t.transaction_currency.as_str();
+        let date = xml_date(&t.date)?;
+        let key = transaction_key(&date, type_, t.value.abs(), security);
+        if take_existing(&mut self.existing_account_transactions, &key) {
+            return Ok(false);
+        }
+
+        let doc = &mut self.doc;
+        let atx = doc.element(account.transactions, "account-transaction");
+        doc.text_element(atx, "uuid", &uuid());
+        doc.text_element(atx, "date", &date);
+        doc.text_element(atx, "currencyCode", currency);
+        doc.text_element(atx, "amount", &amount(t.value.abs()));
+        if let Some(security) = security {
+            doc.reference(atx, "security", security);
+        }
+        doc.text_element(atx, "shares", &shares(t.shares.unwrap_or_default()));
+        if let Some(note) = &t.note {
+            doc.text_element(atx, "note", note);
+        }
+        let units = doc.element(atx, "units");
+        add_unit(doc, units, "FEE", t.fees.unwrap_or_default(), currency);
+        add_unit(doc, units, "TAX", t.taxes.unwrap_or_default(), currency);
+        doc.text_element(atx, "type", type_);
+        Ok(true)
+    }
+
+    fn security(
+        &mut self,
+        isin: Option<&str>,
+        wkn: Option<&str>,
+        ticker: Option<&str>,
+        name: Option<&str>,
+        currency: &Currency,
+    ) -> anyhow::Result<NodeId> {
+        let keys = security_keys(isin, wkn, ticker, name);
+        anyhow::ensure!(!keys.is_empty(), "Transaction without security");
+        if let Some(node) = keys.iter().find_map(|key| self.security_nodes.get(key)) {
+            return Ok(*node);
+        }
+        let doc = &mut self.doc;
+        let security = doc.element(self.securities, "security");
+        doc.text_element(security, "uuid", &uuid());
+        doc.text_element(
+            security,
+            "name",
+            name.or(ticker).or(isin).unwrap_or_default(),
+        );
+        doc.text_element(security, "currencyCode", currency.as_str());
+        if let Some(isin) = isin {
+            doc.text_element(security, "isin", isin);
+        }
+        if let Some(ticker) = ticker {
+            doc.text_element(security, "tickerSymbol", ticker);
+        }
+        if let Some(wkn) = wkn {
+            doc.text_element(security, "wkn", wkn);
+        }
+        doc.text_element(
+            security,
+            "feed",
+            if ticker.is_some() { "YAHOO" } else { "MANUAL" },
+        );
+        doc.element(security, "prices");
+        let attributes = doc.element(security, "attributes");
+        doc.element(attributes, "map");
+        doc.element(security, "events");
+        doc.element(security, "properties");
+        doc.text_element(security, "isRetired", "false");
+        for key in keys {
+            self.security_nodes.insert(key, security);
+        }
+        Ok(security)
+    }
+
+    /// Returns the cash account with the given name and currency. If an account with the name exists in
+    /// another currency, the currency is added to the name of the new account.
+    fn account(&mut self, name: &str, currency: &Currency) -> Node {
+        let key = (name.to_owned(), currency.clone());
+        if let Some(node) = self.account_nodes.get(&key) {
+            return *node;
+        }
+        let display_name = if self.account_nodes.keys().any(|(n, _)| n == name) {
+            format!("{name} ({})", currency.as_str())
+        } else {
+            name.to_owned()
+        };
+        let doc = &mut self.doc;
+        let account = doc.element(self.accounts, "account");
+        doc.text_element(account, "uuid", &uuid());
+        doc.text_element(account, "name", &display_name);
+        doc.text_element(account, "currencyCode", currency.as_str());
+        doc.text_element(account, "isRetired", "false");
+        let transactions = doc.element(account, "transactions");
+        let attributes = doc.element(account, "attributes");
+        doc.element(attributes, "map");
+        let node = Node {
+            node: account,
+            transactions,
+        };
+        self.account_nodes.insert(key, node);
+        node
+    }
+
+    fn portfolio(&mut self, name: &str, reference_account: NodeId) -> Node {
+        if let Some(node) = self.portfolio_nodes.get(name) {
+            return *node;
+        }
+        let doc = &mut self.doc;
+        let portfolio = doc.element(self.portfolios, "portfolio");
+        doc.text_element(portfolio, "uuid", &uuid());
+        doc.text_element(portfolio, "name", name);
+        doc.text_element(portfolio, "isRetired", "false");
+        doc.reference(portfolio, "referenceAccount", reference_account);
+        let transactions = doc.element(portfolio, "transactions");
+        let attributes = doc.element(portfolio, "attributes");
+        doc.element(attributes, "map");
+        let node = Node {
+            node: portfolio,
+            transactions,
+        };
+        self.portfolio_nodes.insert(name.to_owned(), node);
+        node
+    }
+}
+
+/// The keys that identify a security, in the order they are tried: the ISIN, the WKN, the ticker and
+/// the name.
+fn security_keys(
+    isin: Option<&str>,
+    wkn: Option<&str>,
+    ticker: Option<&str>,
+    name: Option<&str>,
+) -> Vec<String> {
+    let mut keys = Vec::new();
+    keys.extend(isin.map(|isin| format!("isin:{isin}")));
+    keys.extend(wkn.map(|wkn| format!("wkn:{wkn}")));
+    keys.extend(ticker.map(|ticker| format!("ticker:{ticker}")));
+    // The name is only used for securities that have neither
+    if keys.is_empty() {
+        keys.extend(name.map(|name| format!("name:{name}")));
+    }
+    keys
+}
+
+fn transaction_key(
+    date: &str,
+    type_: &str,
+    value: Decimal,
+    security: Option<NodeId>,
+) -> TransactionKey {
+    (
+        date.chars().take(10).collect(),
+        type_.to_owned(),
+        amount(value),
+        security,
+    )
+}
+
+/// Counts off a transaction that already is in the existing file. Returns false if there is none left.
+fn take_existing(existing: &mut HashMap<TransactionKey, usize>, key: &TransactionKey) -> bool {
+    match existing.get_mut(key) {
+        Some(count) if *count > 0 => {
+            *count -= 1;
+            true
+        }
+        _ => false,
+    }
+}
+
+fn add_unit(doc: &mut Document, units: NodeId, type_: &str, value: Decimal, currency: &str) {
+    if value.is_zero() {
+        return;
+    }
+    let unit = doc.element(units, "unit");
+    doc.set_attribute(unit, "type", type_);
+    let amount_node = doc.element(unit, "amount");
+    doc.set_attribute(amount_node, "currency", currency);
+    doc.set_attribute(amount_node, "amount", &amount(value.abs()));
+}
+
+/// Amounts are stored in hundredths.
+fn amount(value: Decimal) -> String {
+    (value * dec!(100))
+        .round()
+        .to_i64()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Shares are stored in hundred-millionths.
+fn shares(value: Decimal) -> String {
+    (value.abs() * dec!(100_000_000))
+        .round()
+        .to_i64()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn xml_date(date: &str) -> anyhow::Result<String> {
+    let date_time = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|d| d.and_time(chrono::NaiveTime::MIN))
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S"))
+        .with_context(|| format!("Bad date {date}"))?;
+    Ok(date_time.format("%Y-%m-%dT%H:%M").to_string())
+}
+
+/// A random (version 4) UUID.
+fn uuid() -> String {
+    let mut bytes: [u8; 16] = rand::rng().random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pp::Existing;
+    use crate::test_util;
+
+    fn buy(date: &str, isin: &str, value: Decimal) -> Transaction {
+        Transaction::Portfolio(PortfolioTransaction {
+            date: date.to_owned(),
+            securities_account: Some("ISK".to_owned()),
+            cash_account: Some("ISK".to_owned()),
+            type_: PortfolioType::Buy,
+            value,
+            transaction_currency: Currency::new("SEK"),
+            gross_amount: None,
+            currency_gross_amount: None,
+            exchange_rate: None,
+            fees: None,
+            taxes: None,
+            shares: Some(dec!(1)),
+            isin: Some(isin.to_owned()),
+            wkn: None,
+            ticker_symbol: None,
+            security_name: None,
+            note: None,
+        })
+    }
+
+    fn write(
+        files: &OutputFiles,
+        mut writer: XmlWriter,
+        transactions: &[Transaction],
+    ) -> XmlWriter {
+        for transaction in transactions {
+            writer.write(transaction, None).unwrap();
+        }
+        writer.finish().unwrap();
+        files.commit().unwrap();
+        writer
+    }
+
+    #[test]
+    fn writes_foreign_buy_and_dividend() {
+        let path = test_util::temp_path("client.xml");
+        let mut foreign_buy = buy("2024-01-02", "US0378331005", dec!(1010));
+        if let Transaction::Portfolio(t) = &mut foreign_buy {
+            t.currency_gross_amount = Some(Currency::new("USD"));
+            t.gross_amount = Some(dec!(100));
+            // USD per SEK
+            t.exchange_rate = Some(dec!(0.1));
+            t.fees = Some(dec!(10));
+        }
+        let dividend = Transaction::Account(AccountTransaction {
+            date: "2024-02-15".to_owned(),
+            cash_account: "ISK".to_owned(),
+            securities_account: Some("ISK".to_owned()),
+            type_: AccountType::Dividend,
+            value: dec!(5),
+            transaction_currency: Currency::new("USD"),
+            gross_amount: None,
+            currency_gross_amount: None,
+            exchange_rate: None,
+            fees: None,
+            taxes: Some(dec!(1)),
+            shares: Some(dec!(1)),
+            isin: Some("US0378331005".to_owned()),
+            wkn: None,
+            ticker_symbol: None,
+            security_name: None,
+            note: None,
+        });
+        let files = OutputFiles::default();
+        write(
+            &files,
+            XmlWriter::new(&files, &path),
+            &[foreign_buy, dividend],
+        );
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let doc = Document::parse(&text).unwrap();
+        let root = doc.root();
+        let securities = doc.children(doc.child(root, "securities").unwrap());
+        assert_eq!(securities.len(), 1);
+        assert_eq!(doc.child_text(securities[0], "currencyCode"), Some("USD"));
+
+        // The dividend is in dollars, so it gets an account of its own next to the one in kronor
+        let accounts = doc.children(doc.child(root, "accounts").unwrap());
+        let names: Vec<_> = accounts
+            .iter()
+            .map(|a| doc.child_text(*a, "name").unwrap())
+            .collect();
+        assert_eq!(names, ["ISK", "ISK (USD)"]);
+        let transactions = |owner| doc.children(doc.child(owner, "transactions").unwrap());
+        let portfolio = doc.resolve(doc.children(doc.child(root, "portfolios").unwrap())[0]);
+        assert_eq!(doc.child(portfolio, "referenceAccount"), Some(accounts[0]));
+
+        // The buy is linked to its payment from the account
+        let ptx = doc.resolve(transactions(portfolio)[0]);
+        let atx = doc.resolve(transactions(accounts[0])[0]);
+        assert_eq!(doc.child_text(ptx, "type"), Some("BUY"));
+        assert_eq!(doc.child_text(atx, "type"), Some("BUY"));
+        assert_eq!(doc.child_text(atx, "amount"), Some("101000"));
+        let cross_entry = doc.child(ptx, "crossEntry").unwrap();
+        assert_eq!(doc.child(atx, "crossEntry"), Some(cross_entry));
+        assert_eq!(doc.child(cross_entry, "portfolioTransaction"), Some(ptx));
+        assert_eq!(doc.child(cross_entry, "accountTransaction"), Some(atx));
+        assert_eq!(doc.child(cross_entry, "portfolio"), Some(portfolio));
+        assert_eq!(doc.child(cross_entry, "account"), Some(accounts[0]));
+
+        let dividend = doc.resolve(transactions(accounts[1])[0]);
+        assert_eq!(doc.child_text(dividend, "type"), Some("DIVIDENDS"));
+        assert_eq!(doc.child_text(dividend, "currencyCode"), Some("USD"));
+        assert_eq!(doc.child_text(dividend, "amount"), Some("500"));
+        assert_eq!(doc.child(dividend, "security"), Some(securities[0]));
+
+        // Attributes, which the document does not give access to
+        let xml = roxmltree::Document::parse(&text).unwrap();
+        let element = |name: &str| xml.descendants().find(|n| n.has_tag_name(name)).unwrap();
+        assert_eq!(element("crossEntry").attribute("class"), Some("buysell"));
+        let gross_value = xml
+            .descendants()
+            .find(|n| n.attribute("type") == Some("GROSS_VALUE"))
+            .unwrap();
+        let child = |name: &str| {
+            gross_value
+                .children()
+                .find(|n| n.has_tag_name(name))
+                .unwrap()
+        };
+        assert_eq!(child("amount").attribute("currency"), Some("SEK"));
+        assert_eq!(child("amount").attribute("amount"), Some("100000"));
+        assert_eq!(child("forex").attribute("currency"), Some("USD"));
+        assert_eq!(child("forex").attribute("amount"), Some("10000"));
+        // SEK per USD, the inverse of the rate in the transaction
+        assert_eq!(child("exchangeRate").text(), Some("10"));
+        let tax = xml
+            .descendants()
+            .find(|n| n.attribute("type") == Some("TAX"))
+            .unwrap();
+        let tax_amount = tax.first_element_child().unwrap();
+        assert_eq!(tax_amount.attribute("currency"), Some("USD"));
+        assert_eq!(tax_amount.attribute("amount"), Some("100"));
+    }
+
+    #[test]
+    fn merge_skips_existing_transactions() {
+        let existing = test_util::temp_path("client.xml");
+        let merged = test_util::temp_path("client.pp.xml");
+        let first = [
+            buy("2024-01-02", "SE0000108656", dec!(100)),
+            buy("2024-01-03", "SE0000108656", dec!(200)),
+        ];
+        let files = OutputFiles::default();
+        write(&files, XmlWriter::new(&files, &existing), &first);
+        let original = fs::read_to_string(&existing).unwrap();
+
+        let files = OutputFiles::new(Existing::Backup);
+        let mut all = first.to_vec();
+        all.push(buy("2024-01-04", "SE0000108656", dec!(300)));
+        let writer = write(
+            &files,
+            XmlWriter::new(&files, &merged).merge_with(&existing),
+            &all,
+        );
+        assert_eq!(writer.skipped, 2);
+        let text = fs::read_to_string(&merged).unwrap();
+        assert_eq!(text.matches("<portfolio-transaction>").count(), 3);
+        assert_eq!(text.matches("<security>").count(), 1);
+        assert_eq!(fs::read_to_string(&existing).unwrap(), original);
+
+        // Each merge keeps its own backup of the existing file
+        let mut backup = existing.clone().into_os_string();
+        backup.push(".~1~");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), original);
+        let files = OutputFiles::default();
+        write(
+            &files,
+            XmlWriter::new(&files, &merged).merge_with(&existing),
+            &all,
+        );
+        let mut second_backup = existing.clone().into_os_string();
+        second_backup.push(".~2~");
+        assert!(Path::new(&second_backup).exists());
+
+        for path in [existing, merged, backup.into(), second_backup.into()] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,481 @@
+//! An XML tree in the format that Portfolio Performance reads and writes with XStream.
+//!
+//! Objects that are shared, such as a security that is used by many transactions, are only written in
+//! full the first time they occur in the document. Later occurrences are elements with a `reference`
+//! attribute, holding the relative path to the first occurrence, e.g.
+//! `<security reference="../../../../../securities/security[2]"/>`.
+//!
+//! XStream can also number the objects instead, with an `id` attribute on each object and references
+//! such as `<security reference="12"/>`. Files in that mode are recognized by the `id` attribute of the
+//! root element, and are written back in the same mode.
+//!
+//! In the tree, such elements point directly at the node they refer to. The paths and numbers are
+//! calculated when writing, so nodes can be added anywhere without breaking existing references. When a
+//! file is read, the references are resolved the same way.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+pub type NodeId = usize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("Invalid XML")]
+    Xml(#[from] roxmltree::Error),
+    #[error("Reference to unknown element {0}")]
+    BadReference(String),
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    Children(Vec<Child>),
+    Reference(NodeId),
+}
+
+#[derive(Debug, Clone)]
+enum Child {
+    Element(NodeId),
+    Text(String),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    name: String,
+    attributes: Vec<(String, String)>,
+    content: Content,
+}
+
+/// How references are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReferenceMode {
+    /// Relative paths, such as `../../securities/security[2]`
+    RelativePath,
+    /// Numbers of objects, given in their `id` attributes
+    Id,
+}
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    nodes: Vec<Node>,
+    root: NodeId,
+    mode: ReferenceMode,
+}
+
+impl Document {
+    pub fn new(root_name: &str) -> Self {
+        Document {
+            nodes: vec![Node {
+                name: root_name.to_owned(),
+                attributes: Vec::new(),
+                content: Content::Children(Vec::new()),
+            }],
+            root: 0,
+            mode: ReferenceMode::RelativePath,
+        }
+    }
+
+    /// Reads a document that was written by XStream, such as a client file saved by Portfolio Performance.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let xml = roxmltree::Document::parse(text)?;
+        let root = xml.root_element();
+        let mut doc = Document::new(root.tag_name().name());
+        let mut state = ParseState {
+            paths: HashMap::new(),
+            ids: HashMap::new(),
+            references: Vec::new(),
+        };
+        if let Some(id) = root.attribute("id") {
+            doc.mode = ReferenceMode::Id;
+            state.ids.insert(id.to_owned(), doc.root);
+        }
+        let path = vec![root.tag_name().name().to_owned()];
+        doc.read_children(root, doc.root, &path, &mut state);
+        state.paths.insert(path, doc.root);
+
+        for (id, path, reference) in state.references {
+            let target = match doc.mode {
+                ReferenceMode::RelativePath => {
+                    absolute_path(&path, &reference).and_then(|p| state.paths.get(&p))
+                }
+                ReferenceMode::Id => state.ids.get(&reference),
+            };
+            let target = target.ok_or_else(|| ParseError::BadReference(reference.clone()))?;
+            doc.nodes[id].content = Content::Reference(*target);
+        }
+        Ok(doc)
+    }
+
+    fn read_children(
+        &mut self,
+        xml: roxmltree::Node,
+        parent: NodeId,
+        path: &[String],
+        state: &mut ParseState,
+    ) {
+        let elements: Vec<_> = xml.children().filter(|c| c.is_element()).collect();
+        if elements.is_empty() {
+            if let Some(text) = xml.text().filter(|t| !t.is_empty()) {
+                let Content::Children(children) = &mut self.nodes[parent].content else {
+                    unreachable!()
+                };
+                children.push(Child::Text(text.to_owned()));
+            }
+            return;
+        }
+        let mut sibling_counts: HashMap<&str, usize> = HashMap::new();
+        for element in elements {
+            let name = element.tag_name().name();
+            let count = sibling_counts.entry(name).or_insert(0);
+            *count += 1;
+            let mut element_path = path.to_vec();
+            element_path.push(path_step(name, *count));
+
+            let id = self.element(parent, name);
+            for attribute in element.attributes() {
+                match attribute.name() {
+                    "reference" => (),
+                    // The objects are numbered anew when written
+                    "id" if self.mode == ReferenceMode::Id => {
+                        state.ids.insert(attribute.value().to_owned(), id);
+                    }
+                    _ => self.set_attribute(id, attribute.name(), attribute.value()),
+                }
+            }
+            if let Some(reference) = element.attribute("reference") {
+                state
+                    .references
+                    .push((id, element_path, reference.to_owned()));
+            } else {
+                self.read_children(element, id, &element_path, state);
+                state.paths.insert(element_path, id);
+            }
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// The node that `id` stands for, which is the referred node if `id` is a reference.
+    pub fn resolve(&self, id: NodeId) -> NodeId {
+        match self.nodes[id].content {
+            Content::Reference(target) => target,
+            Content::Children(_) => id,
+        }
+    }
+
+    /// The child elements of the node that `id` stands for.
+    pub fn children(&self, id: NodeId) -> Vec<NodeId> {
+        match &self.nodes[self.resolve(id)].content {
+            Content::Children(children) => children
+                .iter()
+                .filter_map(|c| match c {
+                    Child::Element(id) => Some(*id),
+                    Child::Text(_) => None,
+                })
+                .collect(),
+            Content::Reference(_) => Vec::new(),
+        }
+    }
+
+    /// The first child element with the given name, resolved if it is a reference.
+    pub fn child(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        self.children(id)
+            .into_iter()
+            .find(|c| self.nodes[*c].name == name)
+            .map(|c| self.resolve(c))
+    }
+
+    /// The text of the child element with the given name.
+    pub fn child_text(&self, id: NodeId, name: &str) -> Option<&str> {
+        let child = self.child(id, name)?;
+        match &self.nodes[child].content {
+            Content::Children(children) => match children.as_slice() {
+                [Child::Text(text)] => Some(text),
+                _ => None,
+            },
+            Content::Reference(_) => None,
+        }
+    }
+
+    /// Appends an empty element to `parent`.
+    pub fn element(&mut self, parent: NodeId, name: &str) -> NodeId {
+        self.push(parent, name, Content::Children(Vec::new()))
+    }
+
+    /// Appends an element that only holds text to `parent`.
+    pub fn text_element(&mut self, parent: NodeId, name: &str, text: &str) -> NodeId {
+        self.push(
+            parent,
+            name,
+            Content::Children(vec![Child::Text(text.to_owned())]),
+        )
+    }
+
+    /// Appends an element to `parent` that stands for the object `target`.
+    pub fn reference(&mut self, parent: NodeId, name: &str, target: NodeId) -> NodeId {
+        self.push(parent, name, Content::Reference(target))
+    }
+
+    pub fn set_attribute(&mut self, node: NodeId, name: &str, value: &str) {
+        let attributes = &mut self.nodes[node].attributes;
+        match attributes.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_owned(),
+            None => attributes.push((name.to_owned(), value.to_owned())),
+        }
+    }
+
+    fn push(&mut self, parent: NodeId, name: &str, content: Content) -> NodeId {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            name: name.to_owned(),
+            attributes: Vec::new(),
+            content,
+        });
+        match &mut self.nodes[parent].content {
+            Content::Children(children) => children.push(Child::Element(id)),
+            Content::Reference(_) => panic!("Cannot add children to a reference"),
+        }
+        id
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        let mut state = WriteState {
+            written: HashMap::new(),
+            path: Vec::new(),
+            sibling_counts: vec![HashMap::new()],
+            ids: HashMap::new(),
+        };
+        self.write_node(writer, self.root, &mut state)?;
+        writeln!(writer)
+    }
+
+    fn write_node(
+        &self,
+        writer: &mut impl Write,
+        id: NodeId,
+        state: &mut WriteState,
+    ) -> io::Result<()> {
+        let node = &self.nodes[id];
+        let count = state
+            .sibling_counts
+            .last_mut()
+            .unwrap()
+            .entry(node.name.clone())
+            .or_insert(0);
+        *count += 1;
+        state.path.push(path_step(&node.name, *count));
+
+        let indent = "  ".repeat(state.path.len() - 1);
+        let target = match node.content {
+            Content::Reference(target) => target,
+            Content::Children(_) => id,
+        };
+        if let Some(target_path) = state.written.get(&target) {
+            let reference = match self.mode {
+                ReferenceMode::RelativePath => relative_path(&state.path, target_path),
+                ReferenceMode::Id => state.ids[&target].to_string(),
+            };
+            write!(writer, "{indent}<{}", node.name)?;
+            write_attributes(writer, &node.attributes)?;
+            write!(writer, r#" reference="{reference}"/>"#)?;
+        } else {
+            state.written.insert(target, state.path.clone());
+            let target_node = &self.nodes[target];
+            let Content::Children(children) = &target_node.content else {
+                panic!("Reference to a reference");
+            };
+            write!(writer, "{indent}<{}", node.name)?;
+            // Only objects are numbered, not text values such as strings and numbers
+            if self.mode == ReferenceMode::Id && !matches!(children.as_slice(), [Child::Text(_)]) {
+                let id = state.ids.len() + 1;
+                state.ids.insert(target, id);
+                write!(writer, r#" id="{id}""#)?;
+            }
+            write_attributes(writer, &target_node.attributes)?;
+            match children.as_slice() {
+                [] => write!(writer, "/>")?,
+                [Child::Text(text)] => write!(writer, ">{}</{}>", escape(text), node.name)?,
+                _ => {
+                    write!(writer, ">")?;
+                    state.sibling_counts.push(HashMap::new());
+                    for child in children {
+                        writeln!(writer)?;
+                        match child {
+                            Child::Element(child) => self.write_node(writer, *child, state)?,
+                            Child::Text(text) => write!(writer, "{}", escape(text))?,
+                        }
+                    }
+                    state.sibling_counts.pop();
+                    write!(writer, "\n{indent}</{}>", node.name)?;
+                }
+            }
+        }
+        state.path.pop();
+        Ok(())
+    }
+}
+
+struct WriteState {
+    /// The path to where each object was written in full
+    written: HashMap<NodeId, Vec<String>>,
+    path: Vec<String>,
+    sibling_counts: Vec<HashMap<String, usize>>,
+    /// The number of each object, in [`ReferenceMode::Id`]
+    ids: HashMap<NodeId, usize>,
+}
+
+struct ParseState {
+    /// The node at each path, for resolving references
+    paths: HashMap<Vec<String>, NodeId>,
+    /// The node with each `id` attribute, for resolving references in [`ReferenceMode::Id`]
+    ids: HashMap<String, NodeId>,
+    /// Reference elements, with their paths and the paths they refer to
+    references: Vec<(NodeId, Vec<String>, String)>,
+}
+
+/// Elements are numbered among siblings with the same name, where the first one has no number.
+fn path_step(name: &str, count: usize) -> String {
+    if count == 1 {
+        name.to_owned()
+    } else {
+        format!("{name}[{count}]")
+    }
+}
+
+/// Resolves a reference relative to the element at `from`.
+fn absolute_path(from: &[String], reference: &str) -> Option<Vec<String>> {
+    let mut path = from.to_vec();
+    for step in reference.split('/') {
+        match step {
+            "" | "." => (),
+            ".." => {
+                path.pop()?;
+            }
+            _ => path.push(step.strip_suffix("[1]").unwrap_or(step).to_owned()),
+        }
+    }
+    Some(path)
+}
+
+fn write_attributes(writer: &mut impl Write, attributes: &[(String, String)]) -> io::Result<()> {
+    for (name, value) in attributes {
+        write!(writer, r#" {name}="{}""#, escape(value))?;
+    }
+    Ok(())
+}
+
+fn relative_path(from: &[String], to: &[String]) -> String {
+    let common = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+    let mut steps = vec![".."; from.len() - common];
+    steps.extend(to[common..].iter().map(String::as_str));
+    steps.join("/")
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_references_as_relative_paths() {
+        let mut doc = Document::new("client");
+        let securities = doc.element(doc.root(), "securities");
+        doc.element(securities, "security");
+        let second = doc.element(securities, "security");
+        doc.text_element(second, "name", "A & B");
+        let accounts = doc.element(doc.root(), "accounts");
+        let account = doc.element(accounts, "account");
+        let transactions = doc.element(account, "transactions");
+        let transaction = doc.element(transactions, "account-transaction");
+        doc.reference(transaction, "security", second);
+        // The first occurrence of an object is written in full, even if it is a reference in the tree
+        let portfolios = doc.element(doc.root(), "portfolios");
+        let portfolio = doc.element(portfolios, "portfolio");
+        let inline = doc.element(portfolio, "note");
+        doc.text_element(inline, "text", "x");
+        doc.reference(account, "note", inline);
+
+        let mut out = Vec::new();
+        doc.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("<name>A &amp; B</name>"));
+        assert!(out.contains(r#"<security reference="../../../../../securities/security[2]"/>"#));
+        assert!(out.contains(r#"<note reference="../../../accounts/account/note"/>"#));
+    }
+
+    #[test]
+    fn parses_written_document() {
+        let mut doc = Document::new("client");
+        let securities = doc.element(doc.root(), "securities");
+        doc.element(securities, "security");
+        let second = doc.element(securities, "security");
+        doc.text_element(second, "isin", "SE0000108656");
+        let accounts = doc.element(doc.root(), "accounts");
+        let account = doc.element(accounts, "account");
+        let transaction = doc.element(account, "account-transaction");
+        doc.reference(transaction, "security", second);
+        let mut out = Vec::new();
+        doc.write(&mut out).unwrap();
+
+        let parsed = Document::parse(std::str::from_utf8(&out).unwrap()).unwrap();
+        let accounts = parsed.child(parsed.root(), "accounts").unwrap();
+        let account = parsed.child(accounts, "account").unwrap();
+        let transaction = parsed.child(account, "account-transaction").unwrap();
+        let security = parsed.child(transaction, "security").unwrap();
+        assert_eq!(parsed.child_text(security, "isin"), Some("SE0000108656"));
+
+        let mut rewritten = Vec::new();
+        parsed.write(&mut rewritten).unwrap();
+        assert_eq!(out, rewritten);
+    }
+
+    #[test]
+    fn parses_id_references() {
+        let text = r#"<client id="1">
+  <securities id="2">
+    <security id="3">
+      <isin>SE0000108656</isin>
+    </security>
+  </securities>
+  <accounts id="4">
+    <account id="5">
+      <account-transaction id="6">
+        <security reference="3"/>
+      </account-transaction>
+    </account>
+  </accounts>
+</client>
+"#;
+        let parsed = Document::parse(text).unwrap();
+        let accounts = parsed.child(parsed.root(), "accounts").unwrap();
+        let account = parsed.child(accounts, "account").unwrap();
+        let transaction = parsed.child(account, "account-transaction").unwrap();
+        let security = parsed.child(transaction, "security").unwrap();
+        assert_eq!(parsed.child_text(security, "isin"), Some("SE0000108656"));
+
+        let mut rewritten = Vec::new();
+        parsed.write(&mut rewritten).unwrap();
+        assert_eq!(
+            String::from_utf8(rewritten).unwrap(),
+            format!(r#"<?xml version="1.0" encoding="UTF-8"?>{}{text}"#, "\n")
+        );
+    }
+}
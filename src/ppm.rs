@@ -0,0 +1,290 @@
+//! Transaction history from Pensionsmyndigheten, for the premium pension (PPM).
+//!
+//! Fund switches ("Fondbyte") are listed with one row per fund, negative for the fund that is sold and
+//! positive for the fund that is bought. They become sell/buy pairs, which net to zero on the cash account.
+//! Fees are paid by selling fund shares, so a fee row becomes a sale followed by a fee.
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::fund_names::FundNames;
+use crate::pp;
+use crate::table::Table;
+use crate::types::{Currency, dec_from_swe_num_opt};
+use crate::{ProgressSender, symbols::ResolverChain};
+
+pub const DELIMITER: u8 = b';';
+
+/// Columns that identify the header row of a PPM export.
+pub const HEADER: &[&str] = &["Datum", "Transaktionstyp", "Fondnamn", "Fondnummer"];
+
+/// The name of both the securities account and the reference account.
+const ACCOUNT: &str = "PPM";
+
+#[derive(Debug, Deserialize)]
+struct PpmTransaction {
+    #[serde(rename = "Datum")]
+    date: String,
+    #[serde(rename = "Transaktionstyp")]
+    kind: String,
+    #[serde(rename = "Fondnamn")]
+    fund: Option<String>,
+    /// Pensionsmyndigheten's own fund number
+    #[serde(rename = "Fondnummer")]
+    fund_number: Option<String>,
+    #[serde(rename = "Antal andelar", deserialize_with = "dec_from_swe_num_opt")]
+    shares: Option<Decimal>,
+    #[serde(rename = "Kurs", deserialize_with = "dec_from_swe_num_opt")]
+    nav: Option<Decimal>,
+    #[serde(rename = "Belopp", deserialize_with = "dec_from_swe_num_opt")]
+    amount: Option<Decimal>,
+}
+
+#[derive(Debug, PartialEq)]
+enum PpmType {
+    Premium,
+    Buy,
+    Sell,
+    Switch,
+    Fee,
+    Other,
+}
+
+impl PpmType {
+    fn parse(kind: &str) -> Self {
+        let kind = kind.to_lowercase();
+        if kind.contains("fondbyte") {
+            PpmType::Switch
+        } else if kind.contains("avgift") {
+            PpmType::Fee
+        } else if kind.contains("premie") || kind.contains("pensionsrätt") {
+            PpmType::Premium
+        } else if kind.contains("sälj") || kind.contains("försäljning") {
+            PpmType::Sell
+        } else if kind.contains("köp") {
+            PpmType::Buy
+        } else {
+            PpmType::Other
+        }
+    }
+}
+
+pub async fn convert(
+    table: Table,
+    fund_names: &FundNames,
+    symbols: &ResolverChain<'_>,
+    writer: &mut pp::Output,
+    mut progress: ProgressSender,
+) -> anyhow::Result<()> {
+    let isins = table
+        .deserialize::<PpmTransaction>()
+        .filter_map(|line| fund_isin(&line.ok()?, fund_names));
+    symbols.prefetch(isins, &mut progress).await;
+    progress.total(table.len()).await;
+    progress.count(0).await;
+    let mut unknown_funds = Vec::new();
+    let mut read_records = 0;
+    for (i, line) in table.deserialize().enumerate() {
+        let line: PpmTransaction = line?;
+        writer.set_source_line(table.line(i));
+        read_records += 1;
+        progress.count(read_records).await;
+
+        let type_ = PpmType::parse(&line.kind);
+        let amount = line.amount.unwrap_or_default();
+        match type_ {
+            PpmType::Premium => {
+                writer.write(&account_transaction(
+                    &line,
+                    pp::AccountType::Deposit,
+                    amount.abs(),
+                ))?;
+                continue;
+            }
+            PpmType::Other => {
+                progress
+                    .log(format!(
+                        "Hoppar över {} {} {}",
+                        line.date,
+                        line.kind,
+                        line.fund.as_deref().unwrap_or_default()
+                    ))
+                    .await;
+                continue;
+            }
+            PpmType::Fee if line.shares.is_none() => {
+                writer.write(&account_transaction(
+                    &line,
+                    pp::AccountType::Fees,
+                    amount.abs(),
+                ))?;
+                continue;
+            }
+            PpmType::Buy | PpmType::Sell | PpmType::Switch | PpmType::Fee => (),
+        }
+
+        let Some(fund) = line.fund.clone() else {
+            anyhow::bail!("Fondnamn saknas för {} {}", line.date, line.kind);
+        };
+        let isin = fund_isin(&line, fund_names);
+        if isin.is_none() && !unknown_funds.contains(&fund) {
+            unknown_funds.push(fund.clone());
+        }
+        let mut y_symbol = None;
+        if let Some(isin) = &isin
+            && let Some(security) = symbols.resolve(isin, ACCOUNT, Some("SEK")).await?
+        {
+            y_symbol = Some(security.symbol);
+        }
+
+        let Some(shares) = line.shares else {
+            anyhow::bail!("Antal andelar saknas för {} {}", line.date, fund);
+        };
+        let is_sale = match type_ {
+            PpmType::Buy => false,
+            PpmType::Sell | PpmType::Fee => true,
+            _ => shares.is_sign_negative() || amount.is_sign_negative(),
+        };
+        let value = if line.amount.is_some() {
+            amount.abs()
+        } else if let Some(nav) = line.nav {
+            (shares * nav).abs().round_dp(2)
+        } else {
+            anyhow::bail!("Belopp saknas för {} {}", line.date, fund);
+        };
+        writer.write(&pp::Transaction::Portfolio(pp::PortfolioTransaction {
+            date: line.date.clone(),
+            securities_account: Some(ACCOUNT.to_owned()),
+            cash_account: Some(ACCOUNT.to_owned()),
+            type_: if is_sale {
+                pp::PortfolioType::Sell
+            } else {
+                pp::PortfolioType::Buy
+            },
+            value,
+            transaction_currency: Currency::new("SEK"),
+            gross_amount: None,
+            currency_gross_amount: Some(Currency::new("SEK")),
+            exchange_rate: None,
+            fees: None,
+            taxes: None,
+            shares: Some(shares.abs()),
+            isin,
+            wkn: None,
+            ticker_symbol: y_symbol,
+            security_name: Some(fund),
+            note: Some(match &line.fund_number {
+                Some(number) => format!("{} (fond {number})", line.kind),
+                None => line.kind.clone(),
+            }),
+        }))?;
+        if type_ == PpmType::Fee {
+            writer.write(&account_transaction(&line, pp::AccountType::Fees, value))?;
+        }
+    }
+
+    fund_names.log_unknown(&unknown_funds, &mut progress).await;
+    Ok(())
+}
+
+/// Looks up the ISIN of the fund in the fund name table. The fund number is also accepted in the table,
+/// as it is stable across renames.
+fn fund_isin(line: &PpmTransaction, fund_names: &FundNames) -> Option<String> {
+    fund_names
+        .isin(line.fund.as_deref()?)
+        .or_else(|| line.fund_number.as_deref().and_then(|n| fund_names.isin(n)))
+        .map(str::to_owned)
+}
+
+fn account_transaction(
+    line: &PpmTransaction,
+    type_: pp::AccountType,
+    value: Decimal,
+) -> pp::Transaction {
+    pp::Transaction::Account(pp::AccountTransaction {
+        date: line.date.clone(),
+        cash_account: ACCOUNT.to_owned(),
+        securities_account: None,
+        type_,
+        value,
+        transaction_currency: Currency::new("SEK"),
+        gross_amount: None,
+        currency_gross_amount: None,
+        exchange_rate: None,
+        fees: None,
+        taxes: None,
+        shares: None,
+        isin: None,
+        wkn: None,
+        ticker_symbol: None,
+        security_name: None,
+        note: Some(match &line.fund {
+            Some(fund) => format!("{} {fund}", line.kind),
+            None => line.kind.clone(),
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    async fn convert_rows(contents: &str) -> anyhow::Result<Vec<String>> {
+        let table = test_util::table(contents, DELIMITER, HEADER);
+        let (mut output, transactions) = test_util::output();
+        convert(
+            table,
+            &FundNames::default(),
+            &test_util::no_symbols(),
+            &mut output,
+            test_util::progress(),
+        )
+        .await?;
+        let transactions = transactions.lock().unwrap();
+        Ok(transactions.iter().map(test_util::describe).collect())
+    }
+
+    const COLUMNS: &str = "Datum;Transaktionstyp;Fondnamn;Fondnummer;Antal andelar;Kurs;Belopp\n";
+
+    #[tokio::test]
+    async fn classifies_rows() {
+        let rows = convert_rows(&format!(
+            "{COLUMNS}\
+             2024-01-10;Inbetald pensionsrätt;;;;;1200\n\
+             2024-01-12;Köp;AP7 Aktiefond;123456;4;300;1200\n\
+             2024-02-01;Fondbyte;AP7 Aktiefond;123456;-4;310;-1240\n\
+             2024-02-01;Fondbyte;AP7 Räntefond;234567;10;124;1240\n\
+             2024-03-01;Förvaltningsavgift;AP7 Räntefond;234567;-0,1;125;-12,5\n\
+             2024-03-02;Avgift;;;;;-3\n\
+             2024-03-03;Information;;;;;\n"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            rows,
+            [
+                "2024-01-10 Deposit 1200 ",
+                "2024-01-12 Buy 1200 4 AP7 Aktiefond",
+                "2024-02-01 Sell 1240 4 AP7 Aktiefond",
+                "2024-02-01 Buy 1240 10 AP7 Räntefond",
+                "2024-03-01 Sell 12.5 0.1 AP7 Räntefond",
+                "2024-03-01 Fees 12.5 ",
+                "2024-03-02 Fees 3 ",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn requires_shares_for_trades() {
+        let error = convert_rows(&format!(
+            "{COLUMNS}2024-01-12;Köp;AP7 Aktiefond;123456;;300;1200\n"
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Antal andelar saknas för 2024-01-12 AP7 Aktiefond"
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Trading account statements from Revolut.
+//!
+//! Revolut only gives the ticker, not the ISIN. The optional columns `Fees` and `Withholding Tax` are
+//! read when the statement has them.
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::ProgressSender;
+use crate::pp;
+use crate::table::Table;
+use crate::types::{Currency, dec_from_num_opt};
+
+pub const DELIMITER: u8 = b',';
+
+/// Columns that identify the header row of a Revolut trading statement.
+pub const HEADER: &[&str] = &["Date", "Ticker", "Type", "Total Amount"];
+
+const ACCOUNT: &str = "Revolut";
+
+#[derive(Debug, Deserialize)]
+struct RevolutTransaction {
+    #[serde(rename = "Date")]
+    date: String,
+    #[serde(rename = "Ticker")]
+    ticker: Option<String>,
+    #[serde(rename = "Type")]
+    kind: String,
+    #[serde(rename = "Quantity", deserialize_with = "dec_from_num_opt")]
+    quantity: Option<Decimal>,
+    /// Given with a currency code or sign, e.g. `USD 12.34` or `$12.34`
+    #[serde(rename = "Total Amount", deserialize_with = "dec_from_num_opt")]
+    total: Option<Decimal>,
+    #[serde(rename = "Currency")]
+    currency: Currency,
+    #[serde(rename = "Fees", deserialize_with = "dec_from_num_opt", default)]
+    fees: Option<Decimal>,
+    #[serde(
+        rename = "Withholding Tax",
+        deserialize_with = "dec_from_num_opt",
+        default
+    )]
+    withholding_tax: Option<Decimal>,
+}
+
+pub async fn convert(
+    table: Table,
+    writer: &mut pp::Output,
+    mut progress: ProgressSender,
+) -> anyhow::Result<()> {
+    progress.total(table.len()).await;
+    progress.count(0).await;
+    let mut read_records = 0;
+    for (i, line) in table.deserialize().enumerate() {
+        let line: RevolutTransaction = line?;
+        writer.set_source_line(table.line(i));
+        read_records += 1;
+        progress.count(read_records).await;
+
+        let date = line.date.get(..10).unwrap_or(&line.date).to_owned();
+        let kind = line.kind.to_uppercase();
+        let total = line.total.unwrap_or_default().abs();
+        let fees = line.fees.map(|f| f.abs()).filter(|f| !f.is_zero());
+        let ticker = line.ticker.filter(|t| !t.is_empty());
+
+        let transaction = if kind.starts_with("BUY") || kind.starts_with("SELL") {
+            pp::Transaction::Portfolio(pp::PortfolioTransaction {
+                date,
+                securities_account: Some(ACCOUNT.to_owned()),
+                cash_account: Some(ACCOUNT.to_owned()),
+                type_: if kind.starts_with("BUY") {
+                    pp::PortfolioType::Buy
+                } else {
+                    pp::PortfolioType::Sell
+                },
+                value: total,
+                transaction_currency: line.currency,
+                gross_amount: None,
+                currency_gross_amount: None,
+                exchange_rate: None,
+                fees,
+                taxes: None,
+                shares: line.quantity.map(|q| q.abs()),
+                isin: None,
+                wkn: None,
+                security_name: ticker.clone(),
+                ticker_symbol: ticker,
+                note: Some(line.kind),
+            })
+        } else {
+            let type_ = match kind.as_str() {
+                "DIVIDEND" => pp::AccountType::Dividend,
+                "CASH TOP-UP" => pp::AccountType::Deposit,
+                "CASH WITHDRAWAL" => pp::AccountType::Removal,
+                "CUSTODY FEE" => pp::AccountType::Fees,
+                _ => {
+                    progress
+                        .log(format!(
+                            "Hoppar över {} {} {}",
+                            date,
+                            line.kind,
+                            ticker.unwrap_or_default()
+                        ))
+                        .await;
+                    continue;
+                }
+            };
+            let is_dividend = matches!(type_, pp::AccountType::Dividend);
+            pp::Transaction::Account(pp::AccountTransaction {
+                date,
+                cash_account: ACCOUNT.to_owned(),
+                securities_account: is_dividend.then(|| ACCOUNT.to_owned()),
+                type_,
+                value: total,
+                transaction_currency: line.currency,
+                gross_amount: None,
+                currency_gross_amount: None,
+                exchange_rate: None,
+                fees,
+                taxes: line
+                    .withholding_tax
+                    .map(|t| t.abs())
+                    .filter(|t| !t.is_zero()),
+                shares: None,
+                isin: None,
+                wkn: None,
+                security_name: ticker.clone().filter(|_| is_dividend),
+                ticker_symbol: ticker.filter(|_| is_dividend),
+                note: Some(line.kind),
+            })
+        };
+        writer.write(&transaction)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use rust_decimal::dec;
+
+    #[tokio::test]
+    async fn classifies_rows() {
+        let table = test_util::table(
+            "Date,Ticker,Type,Quantity,Price per share,Total Amount,Currency,FX Rate,Fees,Withholding Tax\n\
+             2024-01-02T10:00:00.000Z,,CASH TOP-UP,,,USD 500,USD,1.00,,\n\
+             2024-01-03T15:30:00.000Z,TSLA,BUY - MARKET,2,$240,$480,USD,1.00,$1.99,\n\
+             2024-02-01T12:00:00.000Z,TSLA,DIVIDEND,,,$1.20,USD,1.00,,-$0.21\n\
+             2024-02-02T12:00:00.000Z,,CUSTODY FEE,,,-$0.50,USD,1.00,,\n\
+             2024-03-01T15:30:00.000Z,TSLA,SELL - STOP,1,\"$1,000\",\"$1,000\",USD,1.00,$0,\n\
+             2024-03-02T10:00:00.000Z,TSLA,STOCK SPLIT,4,,,USD,1.00,,\n",
+            DELIMITER,
+            HEADER,
+        );
+        let (mut output, transactions) = test_util::output();
+        convert(table, &mut output, test_util::progress())
+            .await
+            .unwrap();
+        let transactions = transactions.lock().unwrap();
+        assert_eq!(
+            transactions
+                .iter()
+                .map(test_util::describe)
+                .collect::<Vec<_>>(),
+            [
+                "2024-01-02 Deposit 500 ",
+                "2024-01-03 Buy 480 2 TSLA",
+                "2024-02-01 Dividend 1.20 TSLA",
+                "2024-02-02 Fees 0.50 ",
+                "2024-03-01 Sell 1000 1 TSLA",
+            ]
+        );
+        let pp::Transaction::Portfolio(buy) = &transactions[1] else {
+            panic!("{:?}", transactions[1]);
+        };
+        assert_eq!(buy.fees, Some(dec!(1.99)));
+        let pp::Transaction::Account(dividend) = &transactions[2] else {
+            panic!("{:?}", transactions[2]);
+        };
+        assert_eq!(dividend.taxes, Some(dec!(0.21)));
+        assert_eq!(dividend.fees, None);
+        // A zero fee is left out
+        let pp::Transaction::Portfolio(sell) = &transactions[4] else {
+            panic!("{:?}", transactions[4]);
+        };
+        assert_eq!(sell.fees, None);
+    }
+}
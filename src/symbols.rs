@@ -0,0 +1,967 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::LazyLock,
+};
+
+use anyhow::Context;
+use iced::futures::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    ProgressSender,
+    lookup::{LookupError, Security},
+    pp,
+    types::Currency,
+};
+
+static DEFAULT_FILENAME: LazyLock<PathBuf> = LazyLock::new(|| config_path("symbols.toml"));
+/// The number of ISINs that are looked up at the same time. The services' rate limiters decide how fast
+/// the requests are actually made.
+const CONCURRENT_LOOKUPS: usize = 8;
+/// How alike the name of a listing must be to a searched name to be used, see [`name_similarity()`].
+const MIN_NAME_SIMILARITY: f64 = 0.8;
+
+static DEFAULT_EXCHANGES_FILENAME: LazyLock<PathBuf> =
+    LazyLock::new(|| config_path("exchanges.toml"));
+
+fn config_path(filename: &str) -> PathBuf {
+    dirs::config_dir()
+        .unwrap()
+        .join("portfolio-performance-conv")
+        .join(filename)
+}
+
+pub(crate) type ListingsFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<Security>, LookupError>> + Send + 'a>>;
+pub(crate) type PrefetchFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+/// An ISIN, and whether a resolver found it.
+type FoundFuture<'a> = Pin<Box<dyn Future<Output = (String, bool)> + Send + 'a>>;
+
+/// Finds the ticker symbols of a security.
+pub(crate) trait SymbolResolver: Send + Sync {
+    /// Looks up the listings of the given ISIN, one per exchange. The returned vec is empty if the
+    /// resolver does not know the ISIN.
+    fn listings<'a>(&'a self, isin: &'a str) -> ListingsFuture<'a>;
+
+    /// Searches for listings by the name of the security, for securities without ISIN. The results
+    /// need not match the name closely, as the caller picks among them. The default finds nothing.
+    fn search<'a>(&'a self, _name: &'a str) -> ListingsFuture<'a> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    /// Looks up many ISINs at once ahead of [`Self::listings()`], for services that take several ISINs
+    /// per request. Failures are left to the lookups of each ISIN, which report them. The default does
+    /// nothing.
+    fn prefetch<'a>(&'a self, _isins: &'a [String]) -> PrefetchFuture<'a> {
+        Box::pin(async {})
+    }
+}
+
+/// Asks each resolver in turn, and picks a listing from the first answer according to the exchange
+/// preference.
+///
+/// A resolver that fails to look up an ISIN is skipped for it, with a warning, so that the security is
+/// converted without a ticker rather than failing the conversion.
+pub(crate) struct ResolverChain<'r> {
+    resolvers: Vec<&'r dyn SymbolResolver>,
+    exchanges: ExchangePreference,
+    progress: Option<Mutex<ProgressSender>>,
+    /// Where choices among equally preferred listings are remembered, if the user is asked
+    picks: Option<&'r SymbolOverrides>,
+    /// The resolvers, by index, and ISINs whose lookup has failed
+    failed: Mutex<HashSet<(usize, String)>>,
+    /// ISINs and names that no resolver found, while some resolver failed to look them up
+    unresolved: Mutex<BTreeSet<String>>,
+    /// Securities found by name, by [`name_key()`]
+    names: Mutex<HashMap<String, Option<Security>>>,
+    /// Where the picked listings are told about, for the securities file
+    resolved: Option<pp::ResolvedSecurities>,
+}
+
+impl<'r> ResolverChain<'r> {
+    pub fn new(exchanges: ExchangePreference) -> Self {
+        ResolverChain {
+            resolvers: Vec::new(),
+            exchanges,
+            progress: None,
+            picks: None,
+            failed: Mutex::new(HashSet::new()),
+            unresolved: Mutex::new(BTreeSet::new()),
+            names: Mutex::new(HashMap::new()),
+            resolved: None,
+        }
+    }
+
+    pub fn with_progress(mut self, progress: ProgressSender) -> Self {
+        self.progress = Some(Mutex::new(progress));
+        self
+    }
+
+    /// Asks the user to choose among equally preferred listings, instead of taking the first one. The
+    /// choices are remembered in `overrides`, so that each is only asked for once.
+    pub fn with_picker(mut self, overrides: &'r SymbolOverrides) -> Self {
+        self.picks = Some(overrides);
+        self
+    }
+
+    /// Tells `resolved` about the name, kind and currency of each listing that is picked.
+    pub fn with_resolved(mut self, resolved: &pp::ResolvedSecurities) -> Self {
+        self.resolved = Some(resolved.clone());
+        self
+    }
+
+    pub fn push(&mut self, resolver: &'r dyn SymbolResolver) {
+        self.resolvers.push(resolver);
+    }
+
+    /// Looks up the given ISINs, so that the resolvers have them cached when the transactions are
+    /// converted. Each resolver is asked in turn for the ISINs that the ones before it did not find,
+    /// first all at once, see [`SymbolResolver::prefetch()`], and then concurrently one by one. Each
+    /// ISIN is only looked up once.
+    pub async fn prefetch(
+        &self,
+        isins: impl IntoIterator<Item = String>,
+        progress: &mut ProgressSender,
+    ) {
+        let mut seen = HashSet::new();
+        let mut remaining: Vec<String> = isins
+            .into_iter()
+            .filter(|isin| seen.insert(isin.clone()))
+            .collect();
+        progress.phase("Slår upp värdepapper...").await;
+        progress.total(remaining.len()).await;
+        progress.count(0).await;
+        let mut done = 0;
+        for (i, resolver) in self.resolvers.iter().enumerate() {
+            resolver.prefetch(&remaining).await;
+            // Boxed, as the futures must be Send for any lifetime of the ISINs
+            let lookups: Vec<FoundFuture> = remaining
+                .iter()
+                .map(|isin| {
+                    Box::pin(async move {
+                        let found = self.listings_from(i, *resolver, isin).await.is_some();
+                        (isin.clone(), found)
+                    }) as _
+                })
+                .collect();
+            let mut lookups = stream::iter(lookups).buffer_unordered(CONCURRENT_LOOKUPS);
+            let mut found = HashSet::new();
+            while let Some((isin, is_found)) = lookups.next().await {
+                if is_found {
+                    found.insert(isin);
+                    done += 1;
+                    progress.count(done).await;
+                }
+            }
+            drop(lookups);
+            remaining.retain(|isin| !found.contains(isin));
+        }
+        progress.count(seen.len()).await;
+        progress.phase("Konverterar...").await;
+    }
+
+    /// Looks up the security to use for the given ISIN, when it is held in `account` and traded in
+    /// `currency`.
+    pub async fn resolve(
+        &self,
+        isin: &str,
+        account: &str,
+        currency: Option<&str>,
+    ) -> anyhow::Result<Option<Security>> {
+        let mut listings = self.listings(isin).await;
+        let rank = |s: &Security| self.exchanges.rank(s, account, currency);
+        // A stable sort keeps the lookup's order among equally preferred listings
+        listings.sort_by_key(rank);
+        if let Some(overrides) = self.picks
+            && listings.len() > 1
+            && rank(&listings[0]) == rank(&listings[1])
+        {
+            let security = self.pick(isin, listings, overrides).await?;
+            self.record(&security);
+            return Ok(Some(security));
+        }
+        let security = listings.into_iter().next();
+        security.iter().for_each(|s| self.record(s));
+        Ok(security)
+    }
+
+    fn record(&self, security: &Security) {
+        if let Some(resolved) = &self.resolved {
+            resolved.insert(
+                &security.symbol,
+                pp::SecurityDetails {
+                    name: security.name.clone(),
+                    security_type: security
+                        .type_display
+                        .clone()
+                        .or(security.security_type.clone()),
+                    currency: listing_currency(security).map(Currency::new),
+                },
+            );
+        }
+    }
+
+    /// Looks up the security to use for a security without ISIN, by its name. Only listings whose name
+    /// is alike the given name are used, the most alike first and then according to the exchange
+    /// preference.
+    pub async fn resolve_name(
+        &self,
+        name: &str,
+        account: &str,
+        currency: Option<&str>,
+    ) -> Option<Security> {
+        let key = name_key(name);
+        if let Some(found) = self.names.lock().await.get(&key) {
+            return found.clone();
+        }
+        let found = self.search(name, account, currency).await;
+        match &found {
+            Some(security) => {
+                self.log(format!(
+                    "Använder {} för {name}, som saknar ISIN",
+                    security.symbol
+                ))
+                .await
+            }
+            None => {
+                self.log(format!(
+                    "Hittade ingen ticker för {name}, som saknar ISIN. Värdepapperet har WKN {}",
+                    synthetic_id(name)
+                ))
+                .await
+            }
+        }
+        found.iter().for_each(|s| self.record(s));
+        self.names.lock().await.insert(key, found.clone());
+        found
+    }
+
+    /// The best matching listing from the first resolver that finds one. If a resolver fails to search
+    /// and none finds the name, it is listed with [`Self::unresolved()`].
+    async fn search(&self, name: &str, account: &str, currency: Option<&str>) -> Option<Security> {
+        let rank = |s: &Security| self.exchanges.rank(s, account, currency);
+        let mut failed = false;
+        for resolver in &self.resolvers {
+            let listings = match resolver.search(name).await {
+                Ok(listings) => listings,
+                Err(LookupError::Offline) => {
+                    failed = true;
+                    continue;
+                }
+                Err(e) => {
+                    failed = true;
+                    self.log(format!("Varning: Kunde inte söka efter {name}: {e}"))
+                        .await;
+                    continue;
+                }
+            };
+            let mut matching: Vec<(f64, Security)> = listings
+                .into_iter()
+                .filter_map(|security| {
+                    let similarity = [Some(&security.name), security.long_name.as_ref()]
+                        .into_iter()
+                        .flatten()
+                        .map(|listed| name_similarity(name, listed))
+                        .fold(0.0, f64::max);
+                    (similarity >= MIN_NAME_SIMILARITY).then_some((similarity, security))
+                })
+                .collect();
+            matching.sort_by(|(a, s), (b, t)| b.total_cmp(a).then_with(|| rank(s).cmp(&rank(t))));
+            if let Some((_, security)) = matching.into_iter().next() {
+                return Some(security);
+            }
+        }
+        if failed {
+            self.unresolved.lock().await.insert(name.to_owned());
+        }
+        None
+    }
+
+    /// The ISINs, and the names of securities without ISIN, that could not be looked up, e.g. as the
+    /// lookups were offline.
+    pub async fn unresolved(&self) -> Vec<String> {
+        self.unresolved.lock().await.iter().cloned().collect()
+    }
+
+    /// The listings from the first resolver that knows the ISIN.
+    async fn listings(&self, isin: &str) -> Vec<Security> {
+        for (i, resolver) in self.resolvers.iter().enumerate() {
+            if let Some(listings) = self.listings_from(i, *resolver, isin).await {
+                return listings;
+            }
+        }
+        let failed = self.failed.lock().await;
+        if (0..self.resolvers.len()).any(|i| failed.contains(&(i, isin.to_owned()))) {
+            self.unresolved.lock().await.insert(isin.to_owned());
+        }
+        Vec::new()
+    }
+
+    /// The listings of the ISIN from the resolver with index `i`, unless it does not know the ISIN or
+    /// has failed to look it up.
+    async fn listings_from(
+        &self,
+        i: usize,
+        resolver: &dyn SymbolResolver,
+        isin: &str,
+    ) -> Option<Vec<Security>> {
+        let key = (i, isin.to_owned());
+        if self.failed.lock().await.contains(&key) {
+            return None;
+        }
+        match resolver.listings(isin).await {
+            Ok(listings) if !listings.is_empty() => return Some(listings),
+            Ok(_) => (),
+            // Offline lookups are listed after the conversion instead
+            Err(LookupError::Offline) => {
+                self.failed.lock().await.insert(key);
+            }
+            Err(e) => {
+                self.failed.lock().await.insert(key);
+                self.log(format!(
+                    "Varning: Kunde inte slå upp {isin}: {e}. Värdepapperet får ingen ticker om det inte hittas på annat sätt."
+                ))
+                .await;
+            }
+        }
+        None
+    }
+
+    async fn pick(
+        &self,
+        isin: &str,
+        listings: Vec<Security>,
+        overrides: &SymbolOverrides,
+    ) -> anyhow::Result<Security> {
+        let choice = match &self.progress {
+            Some(progress) => progress.lock().await.choose(isin, listings.clone()).await,
+            None => None,
+        };
+        let Some(security) = choice.and_then(|i| listings.get(i)) else {
+            anyhow::bail!(
+                "Flera noteringar hittades för {isin}: {}. Ange vilken som ska användas i {}",
+                listings
+                    .iter()
+                    .map(|s| format!("{} ({})", s.symbol, s.exchange))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                overrides.path.display()
+            );
+        };
+        overrides.remember(isin, security).await?;
+        Ok(security.clone())
+    }
+
+    async fn log(&self, msg: impl Into<String>) {
+        if let Some(progress) = &self.progress {
+            progress.lock().await.log(msg).await;
+        }
+    }
+}
+
+/// Words that say little about which security a name is, such as company forms.
+const NOISE_WORDS: &[&str] = &[
+    "ab", "publ", "ser", "series", "class", "the", "inc", "ltd", "plc", "asa", "oyj", "as", "nv",
+    "sa", "ag",
+];
+
+/// The words of a security name that identify it: lowercased, without diacritics and punctuation,
+/// and without [`NOISE_WORDS`].
+fn name_words(name: &str) -> Vec<String> {
+    fold(name)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !NOISE_WORDS.contains(word))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The form of a name that is the same for all spellings of it.
+pub(crate) fn name_key(name: &str) -> String {
+    name_words(name).join(" ")
+}
+
+/// How alike two security names are, from 0 to 1, as the share of words they have in common.
+///
+/// Short words, such as share classes (`A`, `B`) and currencies, must all be the same, as they tell
+/// otherwise equal names apart.
+fn name_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (name_words(a), name_words(b));
+    let short = |words: &[String]| -> BTreeSet<String> {
+        words
+            .iter()
+            .filter(|word| word.chars().count() <= 3)
+            .cloned()
+            .collect()
+    };
+    if a.is_empty() || short(&a) != short(&b) {
+        return 0.0;
+    }
+    let common = a.iter().filter(|word| b.contains(word)).count();
+    2.0 * common as f64 / (a.len() + b.len()) as f64
+}
+
+/// A stable identifier for a security that has neither ISIN nor ticker, made from its name, so that
+/// the same name always gives the same security in Portfolio Performance. It is used as the WKN.
+pub(crate) fn synthetic_id(name: &str) -> String {
+    // FNV-1a, as the standard library's hashers may change between Rust versions
+    let hash = name_key(name)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("PPC{:09X}", hash >> 28)
+}
+
+/// Lowercases and removes the diacritics that Yahoo tends to drop, as in å, ä and ö. The number of
+/// characters is kept.
+pub(crate) fn fold(text: &str) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'å' | 'ä' | 'á' | 'à' => 'a',
+            'ö' | 'ø' | 'ó' => 'o',
+            'é' | 'è' | 'ë' => 'e',
+            'ü' | 'ú' => 'u',
+            c => c,
+        })
+        .collect()
+}
+
+/// The order in which exchanges are preferred when a security is listed on several of them.
+///
+/// Listings in the currency that the security is traded in are always preferred. Among them, the
+/// exchanges are tried in the order of the list for the account, the list for the currency or the
+/// default list, and then in the order that the lookup gave them. The lists are read from a TOML file:
+///
+/// ```toml
+/// default = ["STO", "HEL", "CPH", "OSL", "NMS", "NYQ"]
+///
+/// [currency]
+/// EUR = ["HEL", "PAR", "AMS", "GER"]
+///
+/// [account]
+/// "Avanza KF" = ["STO"]
+/// ```
+///
+/// The exchanges are Yahoo Finance's exchange codes, as shown in the log when a symbol is looked up.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ExchangePreference {
+    #[serde(default = "default_exchanges")]
+    default: Vec<String>,
+    #[serde(default)]
+    currency: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    account: HashMap<String, Vec<String>>,
+}
+
+fn default_exchanges() -> Vec<String> {
+    ["STO", "HEL", "CPH", "OSL", "NMS", "NYQ"]
+        .map(str::to_owned)
+        .to_vec()
+}
+
+impl Default for ExchangePreference {
+    fn default() -> Self {
+        ExchangePreference {
+            default: default_exchanges(),
+            currency: HashMap::new(),
+            account: HashMap::new(),
+        }
+    }
+}
+
+impl ExchangePreference {
+    /// Loads the preference from the given file, or from the default location in the config directory.
+    /// A missing file gives the default preference.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = path.unwrap_or(&DEFAULT_EXCHANGES_FILENAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read exchange file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("Bad exchange file {}", path.display()))
+    }
+
+    /// Orders listings from the most to the least preferred.
+    fn rank(&self, security: &Security, account: &str, currency: Option<&str>) -> (bool, usize) {
+        let order = self
+            .account
+            .get(account)
+            .or_else(|| currency.and_then(|c| self.currency.get(c)))
+            .unwrap_or(&self.default);
+        let other_currency = currency.is_some() && listing_currency(security) != currency;
+        let position = order
+            .iter()
+            .position(|e| *e == security.exchange)
+            .unwrap_or(order.len());
+        (other_currency, position)
+    }
+}
+
+/// The currency of a listing, as given by the lookup or by the exchange.
+fn listing_currency(security: &Security) -> Option<&str> {
+    if let Some(currency) = &security.currency {
+        return Some(currency);
+    }
+    Some(match security.exchange.as_str() {
+        "STO" | "NGM" => "SEK",
+        "HEL" | "GER" | "FRA" | "STU" | "MUN" | "BER" | "DUS" | "HAM" | "PAR" | "AMS" | "BRU"
+        | "MIL" | "MCE" | "VIE" | "LIS" | "ISE" => "EUR",
+        "CPH" => "DKK",
+        "OSL" => "NOK",
+        "NMS" | "NYQ" | "NGM_US" | "NCM" | "ASE" | "PCX" | "BTS" | "PNK" => "USD",
+        "TOR" | "VAN" => "CAD",
+        "LSE" => "GBP",
+        "EBS" => "CHF",
+        _ => return None,
+    })
+}
+
+/// A user-maintained file that maps ISINs to ticker symbols, for when the lookup gives the wrong
+/// symbol, e.g. another share class of a fund. The overrides are always used before any lookup.
+///
+/// The file is a TOML file with a table per ISIN:
+///
+/// ```toml
+/// [SE0000984189]
+/// ticker = "0P00000LST.ST"
+/// name = "SEB Sverigefond"
+/// currency = "SEK"
+/// ```
+///
+/// `name` and `currency` can be left out.
+pub(crate) struct SymbolOverrides {
+    path: PathBuf,
+    symbols: Mutex<HashMap<String, SymbolOverride>>,
+    progress: Option<Mutex<ProgressSender>>,
+    /// ISINs whose override has been logged
+    reported: Mutex<HashSet<String>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct SymbolOverride {
+    ticker: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<String>,
+}
+
+impl SymbolOverrides {
+    /// Loads the overrides from the given file, or from the default location in the config directory.
+    /// A missing file gives no overrides.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = path.unwrap_or(&DEFAULT_FILENAME).to_owned();
+        let symbols = if path.exists() {
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read symbol file {}", path.display()))?;
+            toml::from_str(&contents)
+                .with_context(|| format!("Bad symbol file {}", path.display()))?
+        } else {
+            HashMap::new()
+        };
+        Ok(SymbolOverrides {
+            path,
+            symbols: Mutex::new(symbols),
+            progress: None,
+            reported: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn with_progress(mut self, progress: ProgressSender) -> Self {
+        self.progress = Some(Mutex::new(progress));
+        self
+    }
+
+    /// Adds an override for the ISIN, and appends it to the file so that it is used in later
+    /// conversions too.
+    pub async fn remember(&self, isin: &str, security: &Security) -> anyhow::Result<()> {
+        let symbol = SymbolOverride {
+            ticker: security.symbol.clone(),
+            name: Some(security.name.clone()).filter(|n| !n.is_empty()),
+            currency: listing_currency(security).map(str::to_owned),
+        };
+        let table = toml::to_string(&HashMap::from([(isin, &symbol)]))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| write!(f, "\n{table}"))
+            .with_context(|| format!("Failed to write symbol file {}", self.path.display()))?;
+        self.symbols.lock().await.insert(isin.to_owned(), symbol);
+        self.reported.lock().await.insert(isin.to_owned());
+        if let Some(progress) = &self.progress {
+            progress
+                .lock()
+                .await
+                .log(format!(
+                    "Sparade {} för {isin} i {}",
+                    security.symbol,
+                    self.path.display()
+                ))
+                .await;
+        }
+        Ok(())
+    }
+}
+
+impl SymbolResolver for SymbolOverrides {
+    fn listings<'a>(&'a self, isin: &'a str) -> ListingsFuture<'a> {
+        Box::pin(async move {
+            let Some(symbol) = self.symbols.lock().await.get(isin).cloned() else {
+                return Ok(Vec::new());
+            };
+            if self.reported.lock().await.insert(isin.to_owned())
+                && let Some(progress) = &self.progress
+            {
+                progress
+                    .lock()
+                    .await
+                    .log(format!(
+                        "Använder {} för {isin} från {}{}",
+                        symbol.ticker,
+                        self.path.display(),
+                        symbol
+                            .currency
+                            .as_ref()
+                            .map(|c| format!(" (valuta {c})"))
+                            .unwrap_or_default()
+                    ))
+                    .await;
+            }
+            Ok(vec![Security {
+                symbol: symbol.ticker,
+                exchange: String::new(),
+                name: symbol.name.unwrap_or_default(),
+                currency: symbol.currency,
+                ..Default::default()
+            }])
+        })
+    }
+
+    /// All overrides that have a name, which the chain matches against the searched name.
+    fn search<'a>(&'a self, _name: &'a str) -> ListingsFuture<'a> {
+        Box::pin(async move {
+            Ok(self
+                .symbols
+                .lock()
+                .await
+                .values()
+                .filter_map(|symbol| {
+                    Some(Security {
+                        symbol: symbol.ticker.clone(),
+                        exchange: String::new(),
+                        name: symbol.name.clone()?,
+                        currency: symbol.currency.clone(),
+                        ..Default::default()
+                    })
+                })
+                .collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConversionProgress, test_util};
+
+    struct Fixed(Vec<(&'static str, &'static str)>);
+
+    impl SymbolResolver for Fixed {
+        fn listings<'a>(&'a self, _isin: &'a str) -> ListingsFuture<'a> {
+            Box::pin(async move {
+                Ok(self
+                    .0
+                    .iter()
+                    .map(|(symbol, exchange)| Security {
+                        symbol: symbol.to_string(),
+                        exchange: exchange.to_string(),
+                        ..Default::default()
+                    })
+                    .collect())
+            })
+        }
+    }
+
+    struct Offline;
+
+    impl SymbolResolver for Offline {
+        fn listings<'a>(&'a self, _isin: &'a str) -> ListingsFuture<'a> {
+            Box::pin(async { Err(LookupError::Offline) })
+        }
+    }
+
+    /// Knows the ISINs in `known`, and keeps the ISINs it is asked for.
+    struct Counting {
+        known: &'static [&'static str],
+        looked_up: std::sync::Mutex<Vec<String>>,
+        prefetched: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl Counting {
+        fn new(known: &'static [&'static str]) -> Self {
+            Counting {
+                known,
+                looked_up: Default::default(),
+                prefetched: Default::default(),
+            }
+        }
+    }
+
+    impl SymbolResolver for Counting {
+        fn listings<'a>(&'a self, isin: &'a str) -> ListingsFuture<'a> {
+            self.looked_up.lock().unwrap().push(isin.to_owned());
+            let mut listings = Vec::new();
+            if self.known.contains(&isin) {
+                listings.push(Security {
+                    symbol: format!("{isin}.ST"),
+                    exchange: "STO".to_owned(),
+                    ..Default::default()
+                });
+            }
+            Box::pin(async { Ok(listings) })
+        }
+
+        fn prefetch<'a>(&'a self, isins: &'a [String]) -> PrefetchFuture<'a> {
+            self.prefetched.lock().unwrap().extend_from_slice(isins);
+            Box::pin(async {})
+        }
+    }
+
+    #[tokio::test]
+    async fn prefetches_each_isin_once_from_each_resolver() {
+        let first = Counting::new(&["SE0000108656"]);
+        let second = Counting::new(&["SE0000108656", "SE0000148884"]);
+        let mut chain = ResolverChain::new(ExchangePreference::default());
+        chain.push(&first);
+        chain.push(&second);
+        let (mut progress, mut messages) = test_util::recording_progress(20);
+        let isins = [
+            "SE0000108656",
+            "SE0000148884",
+            "SE0000108656",
+            "SE0000000000",
+        ];
+        chain
+            .prefetch(isins.map(str::to_owned), &mut progress)
+            .await;
+        drop(progress);
+
+        let sorted = |isins: &std::sync::Mutex<Vec<String>>| {
+            let mut isins = isins.lock().unwrap().clone();
+            isins.sort();
+            isins
+        };
+        let all = ["SE0000000000", "SE0000108656", "SE0000148884"];
+        assert_eq!(sorted(&first.prefetched), all);
+        assert_eq!(sorted(&first.looked_up), all);
+        // What the first resolver found is not asked of the second
+        let rest = ["SE0000000000", "SE0000148884"];
+        assert_eq!(sorted(&second.prefetched), rest);
+        assert_eq!(sorted(&second.looked_up), rest);
+
+        let mut counts = Vec::new();
+        let mut total = None;
+        while let Some(message) = messages.next().await {
+            match message {
+                ConversionProgress::Count(count) => counts.push(count),
+                ConversionProgress::Total(value) => total = Some(value),
+                _ => (),
+            }
+        }
+        assert_eq!(total, Some(3));
+        assert_eq!(counts, [0, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn lists_isins_that_could_not_be_looked_up() {
+        let known = Fixed(vec![]);
+        let mut chain = ResolverChain::new(ExchangePreference::default());
+        chain.push(&Offline);
+        chain.push(&known);
+        assert!(
+            chain
+                .resolve("SE0000108656", "ISK", None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(chain.unresolved().await, ["SE0000108656"]);
+    }
+
+    struct Fund;
+
+    impl SymbolResolver for Fund {
+        fn listings<'a>(&'a self, _isin: &'a str) -> ListingsFuture<'a> {
+            Box::pin(async {
+                Ok(vec![Security {
+                    symbol: "0P0000J1IR.ST".to_owned(),
+                    exchange: "STO".to_owned(),
+                    name: "Länsförsäkringar Global Indexnära".to_owned(),
+                    long_name: Some("Lansforsakringar Global Indexnara".to_owned()),
+                    ..Default::default()
+                }])
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn records_cleaned_name() {
+        let resolved = pp::ResolvedSecurities::default();
+        let mut chain = ResolverChain::new(ExchangePreference::default()).with_resolved(&resolved);
+        chain.push(&Fund);
+        chain.resolve("SE0001732728", "ISK", None).await.unwrap();
+        let details = resolved.get("0P0000J1IR.ST").unwrap();
+        assert_eq!(details.name, "Länsförsäkringar Global Indexnära");
+        assert_eq!(details.currency, Some(Currency::new("SEK")));
+    }
+
+    #[tokio::test]
+    async fn overrides_win() {
+        let path = std::env::temp_dir().join(format!("pp-symbols-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[SE0000984189]\nticker = \"0P00000LST.ST\"\nname = \"SEB Sverigefond\"\n",
+        )
+        .unwrap();
+        let overrides = SymbolOverrides::load(Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let fallback = Fixed(vec![("SEB-A.ST", "STO")]);
+        let mut chain = ResolverChain::new(ExchangePreference::default());
+        chain.push(&overrides);
+        chain.push(&fallback);
+
+        let security = chain
+            .resolve("SE0000984189", "ISK", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(security.symbol, "0P00000LST.ST");
+        assert_eq!(security.name, "SEB Sverigefond");
+        let security = chain
+            .resolve("SE0000148884", "ISK", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(security.symbol, "SEB-A.ST");
+    }
+
+    #[tokio::test]
+    async fn prefers_listing_in_trading_currency() {
+        let nokia = Fixed(vec![
+            ("NOKIA.SG", "STU"),
+            ("NOKIA.HE", "HEL"),
+            ("NOK", "NYQ"),
+        ]);
+        let exchanges: ExchangePreference =
+            toml::from_str("default = [\"STU\"]\n[account]\nKF = [\"NYQ\"]\n").unwrap();
+        let mut chain = ResolverChain::new(exchanges);
+        chain.push(&nokia);
+        let symbol = |account, currency| {
+            let chain = &chain;
+            async move {
+                chain
+                    .resolve("FI0009000681", account, currency)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .symbol
+            }
+        };
+        assert_eq!(symbol("ISK", None).await, "NOKIA.SG");
+        assert_eq!(symbol("ISK", Some("EUR")).await, "NOKIA.SG");
+        assert_eq!(symbol("ISK", Some("USD")).await, "NOK");
+        assert_eq!(symbol("KF", Some("EUR")).await, "NOKIA.SG");
+        assert_eq!(symbol("KF", None).await, "NOK");
+        // No listing in SEK, so the preference decides
+        assert_eq!(symbol("KF", Some("SEK")).await, "NOK");
+
+        let mut chain = ResolverChain::new(ExchangePreference::default());
+        chain.push(&nokia);
+        let security = chain
+            .resolve("FI0009000681", "ISK", Some("EUR"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(security.symbol, "NOKIA.HE");
+    }
+
+    #[tokio::test]
+    async fn remembers_picked_listing() {
+        use iced::futures::{StreamExt, channel::mpsc};
+
+        let path = std::env::temp_dir().join(format!("pp-picked-{}.toml", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let overrides = SymbolOverrides::load(Some(&path)).unwrap();
+        let (sender, mut receiver) = mpsc::channel(1);
+        let listings = Fixed(vec![("ABC.DE", "GER"), ("ABC.PA", "PAR")]);
+        let mut chain = ResolverChain::new(ExchangePreference::default())
+            .with_progress(ProgressSender { sender })
+            .with_picker(&overrides);
+        chain.push(&overrides);
+        chain.push(&listings);
+
+        let answer = async {
+            while let Some(progress) = receiver.next().await {
+                if let crate::ConversionProgress::Choose(choice) = progress {
+                    assert_eq!(choice.candidates.len(), 2);
+                    choice.answer(1);
+                }
+            }
+        };
+        let resolve = async {
+            let first = chain.resolve("FR0000000001", "ISK", Some("EUR")).await;
+            // The second lookup is answered by the override, without asking
+            let second = chain.resolve("FR0000000001", "ISK", Some("EUR")).await;
+            drop(chain);
+            (first, second)
+        };
+        let ((first, second), ()) = tokio::join!(resolve, answer);
+        assert_eq!(first.unwrap().unwrap().symbol, "ABC.PA");
+        assert_eq!(second.unwrap().unwrap().symbol, "ABC.PA");
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(saved.contains("[FR0000000001]\nticker = \"ABC.PA\"\ncurrency = \"EUR\"\n"));
+    }
+
+    #[tokio::test]
+    async fn resolves_names_without_isin() {
+        let path = std::env::temp_dir().join(format!("pp-names-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[SE0000107419]\nticker = \"INVE-B.ST\"\nname = \"Investor AB ser. B\"\n\n\
+             [SE0000709123]\nticker = \"0P00000ALF.ST\"\nname = \"Swedbank Robur Ny Teknik A\"\n",
+        )
+        .unwrap();
+        let overrides = SymbolOverrides::load(Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut chain = ResolverChain::new(ExchangePreference::default());
+        chain.push(&overrides);
+
+        let found = chain.resolve_name("INVESTOR B", "ISK", Some("SEK")).await;
+        assert_eq!(found.unwrap().symbol, "INVE-B.ST");
+        // Another share class is another security
+        let found = chain
+            .resolve_name("Swedbank Robur Ny Teknik C", "ISK", Some("SEK"))
+            .await;
+        assert!(found.is_none());
+        assert_eq!(
+            synthetic_id("Swedbank Robur Ny Teknik C"),
+            synthetic_id("SWEDBANK ROBUR NY TEKNIK C")
+        );
+        assert_ne!(
+            synthetic_id("Swedbank Robur Ny Teknik C"),
+            synthetic_id("Swedbank Robur Ny Teknik A")
+        );
+    }
+}
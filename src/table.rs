@@ -0,0 +1,366 @@
+use std::path::Path;
+
+use calamine::{Data, Reader};
+use csv::{Position, StringRecord};
+use serde::de::DeserializeOwned;
+
+/// File extensions that are read as spreadsheets instead of CSV.
+pub const SPREADSHEET_EXTENSIONS: &[&str] = &["xlsx", "xlsm", "xls", "ods"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum TableError {
+    #[error("Failed to read CSV file")]
+    Csv(#[from] csv::Error),
+    #[error("Failed to read spreadsheet")]
+    Spreadsheet(#[from] calamine::Error),
+    #[error("The sheet \"{0}\" does not exist")]
+    NoSuchSheet(String),
+    #[error("Could not find a header row with the columns {0:?}")]
+    NoHeader(Vec<String>),
+}
+
+/// Rows read from a CSV file or a spreadsheet, together with the header row.
+///
+/// Each row keeps the line number it had in the input file, for error messages.
+pub struct Table {
+    headers: StringRecord,
+    rows: Vec<StringRecord>,
+}
+
+/// Builds a [`Table`] from a file, in the manner of [`csv::ReaderBuilder`].
+pub struct TableBuilder<'a> {
+    delimiter: u8,
+    sheet: Option<&'a str>,
+    header: &'a [&'a str],
+}
+
+impl Default for TableBuilder<'_> {
+    fn default() -> Self {
+        TableBuilder {
+            delimiter: b',',
+            sheet: None,
+            header: &[],
+        }
+    }
+}
+
+impl<'a> TableBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The field delimiter, for CSV files.
+    pub fn delimiter(&mut self, delimiter: u8) -> &mut Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// The sheet to read, for spreadsheets. If not given, the first sheet with a header row is used.
+    pub fn sheet(&mut self, sheet: Option<&'a str>) -> &mut Self {
+        self.sheet = sheet;
+        self
+    }
+
+    /// Column names that identify the header row. Rows before the header row are skipped,
+    /// as exports often start with a title or the account details.
+    ///
+    /// If empty, the first non-empty row is the header row.
+    pub fn header(&mut self, header: &'a [&'a str]) -> &mut Self {
+        self.header = header;
+        self
+    }
+
+    pub fn read(&self, path: &Path) -> Result<Table, TableError> {
+        if is_spreadsheet(path) {
+            self.read_spreadsheet(path)
+        } else {
+            self.read_csv(path)
+        }
+    }
+
+    fn read_csv(&self, path: &Path) -> Result<Table, TableError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(false)
+            .flexible(true)
+            .from_path(path)?;
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        self.find_header(records)
+            .ok_or_else(|| TableError::NoHeader(self.header_names()))
+    }
+
+    fn read_spreadsheet(&self, path: &Path) -> Result<Table, TableError> {
+        let mut workbook = calamine::open_workbook_auto(path)?;
+        let sheet_names = match self.sheet {
+            Some(sheet) => {
+                if !workbook.sheet_names().iter().any(|s| s == sheet) {
+                    return Err(TableError::NoSuchSheet(sheet.to_owned()));
+                }
+                vec![sheet.to_owned()]
+            }
+            None => workbook.sheet_names(),
+        };
+        for sheet_name in sheet_names {
+            let range = workbook.worksheet_range(&sheet_name)?;
+            // Line numbers are counted from the top of the sheet, as shown in the spreadsheet program.
+            let first_line = range.start().map(|(row, _)| row as u64 + 1).unwrap_or(1);
+            let records = range
+                .rows()
+                .enumerate()
+                .map(|(i, row)| {
+                    let mut record: StringRecord = row.iter().map(cell_to_string).collect();
+                    let mut pos = Position::new();
+                    pos.set_line(first_line + i as u64);
+                    record.set_position(Some(pos));
+                    record
+                })
+                .collect();
+            if let Some(table) = self.find_header(records) {
+                return Ok(table);
+            }
+        }
+        Err(TableError::NoHeader(self.header_names()))
+    }
+
+    fn find_header(&self, records: Vec<StringRecord>) -> Option<Table> {
+        let mut records = records.into_iter();
+        let headers = records.by_ref().find(|record| {
+            if self.header.is_empty() {
+                record.iter().any(|field| !field.trim().is_empty())
+            } else {
+                self.header
+                    .iter()
+                    .all(|name| record.iter().any(|field| clean_header(field) == *name))
+            }
+        })?;
+        let headers = headers.iter().map(clean_header).collect();
+        let rows = records
+            .filter(|record| record.iter().any(|field| !field.trim().is_empty()))
+            .collect();
+        Some(Table { headers, rows })
+    }
+
+    fn header_names(&self) -> Vec<String> {
+        self.header.iter().map(|s| s.to_string()).collect()
+    }
+}
+
+impl Table {
+    /// Returns the line in the file of the data row with the given index, counted from 1.
+    pub fn line(&self, index: usize) -> u64 {
+        self.rows[index]
+            .position()
+            .map(|p| p.line())
+            .unwrap_or(index as u64 + 2)
+    }
+
+    /// Returns the number of data rows, excluding the header row.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Deserializes each row into `T`, using the header row for the field names.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> impl Iterator<Item = Result<T, csv::Error>> {
+        self.rows
+            .iter()
+            .map(|record| record.deserialize(Some(&self.headers)))
+    }
+}
+
+pub fn is_spreadsheet(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            SPREADSHEET_EXTENSIONS
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(ext))
+        })
+}
+
+fn clean_header(field: &str) -> &str {
+    field.trim_start_matches('\u{feff}').trim()
+}
+
+/// Formats a cell the way it would appear in a CSV export, so that rows from sheets can be
+/// deserialized with the same types as rows from CSV files.
+fn cell_to_string(cell: &Data) -> String {
+    match cell {
+        Data::Int(i) => i.to_string(),
+        Data::Float(f) => f.to_string(),
+        Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => s.trim().to_owned(),
+        Data::Bool(b) => b.to_string(),
+        Data::DateTime(dt) => match dt.as_datetime() {
+            Some(dt) if dt.time() == chrono::NaiveTime::MIN => dt.format("%Y-%m-%d").to_string(),
+            Some(dt) => dt.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => String::new(),
+        },
+        Data::Error(_) | Data::Empty => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use calamine::{ExcelDateTime, ExcelDateTimeType};
+
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn finds_header_after_preamble() {
+        let path = test_util::temp_path("table.csv");
+        std::fs::write(
+            &path,
+            "Transaktioner;2024\n\n\u{feff}Datum;Belopp\n2024-01-02;12,5\n;\n2024-01-03;-1\n",
+        )
+        .unwrap();
+        let table = TableBuilder::new()
+            .delimiter(b';')
+            .header(&["Datum", "Belopp"])
+            .read(&path)
+            .unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(table.headers, vec!["Datum", "Belopp"]);
+        assert_eq!(table.len(), 2);
+
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Row {
+            datum: String,
+            belopp: String,
+        }
+        let rows: Vec<Row> = table.deserialize().collect::<Result<_, _>>().unwrap();
+        assert_eq!(rows[0].datum, "2024-01-02");
+        assert_eq!(rows[1].belopp, "-1");
+    }
+
+    #[test]
+    fn formats_cells_like_csv() {
+        let date = |value| {
+            Data::DateTime(ExcelDateTime::new(
+                value,
+                ExcelDateTimeType::DateTime,
+                false,
+            ))
+        };
+        assert_eq!(cell_to_string(&date(45293.0)), "2024-01-02");
+        assert_eq!(cell_to_string(&date(45293.5)), "2024-01-02 12:00:00");
+        assert_eq!(cell_to_string(&Data::Float(3.0)), "3");
+        assert_eq!(cell_to_string(&Data::Float(-12.5)), "-12.5");
+        assert_eq!(cell_to_string(&Data::Int(7)), "7");
+        assert_eq!(cell_to_string(&Data::String(" Köp ".to_owned())), "Köp");
+        assert_eq!(cell_to_string(&Data::Empty), "");
+    }
+
+    /// Writes an xlsx workbook with the given sheets, whose rows are inline strings or, for cells that
+    /// start with `=`, numbers. Numbers that start with `=d` get a date format.
+    fn write_workbook(path: &Path, sheets: &[(&str, &[&[&str]])]) {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        let mut add = |name: &str, contents: String| {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        };
+        const MAIN: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+        const RELATIONSHIPS: &str =
+            "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+        add(
+            "[Content_Types].xml",
+            r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/></Types>"#.to_owned(),
+        );
+        add(
+            "_rels/.rels",
+            format!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="{RELATIONSHIPS}/officeDocument" Target="xl/workbook.xml"/></Relationships>"#
+            ),
+        );
+        let mut workbook = String::new();
+        let mut relationships = format!(
+            r#"<Relationship Id="rIdStyles" Type="{RELATIONSHIPS}/styles" Target="styles.xml"/>"#
+        );
+        for (i, (name, rows)) in sheets.iter().enumerate() {
+            let n = i + 1;
+            workbook.push_str(&format!(
+                r#"<sheet name="{name}" sheetId="{n}" r:id="rId{n}"/>"#
+            ));
+            relationships.push_str(&format!(
+                r#"<Relationship Id="rId{n}" Type="{RELATIONSHIPS}/worksheet" Target="worksheets/sheet{n}.xml"/>"#
+            ));
+            let mut data = String::new();
+            for (r, row) in rows.iter().enumerate() {
+                data.push_str(&format!(r#"<row r="{}">"#, r + 1));
+                for (c, cell) in row.iter().enumerate() {
+                    let reference = format!("{}{}", char::from(b'A' + c as u8), r + 1);
+                    data.push_str(&match (cell.strip_prefix("=d"), cell.strip_prefix('=')) {
+                        (Some(date), _) => format!(r#"<c r="{reference}" s="1"><v>{date}</v></c>"#),
+                        (None, Some(number)) => {
+                            format!(r#"<c r="{reference}"><v>{number}</v></c>"#)
+                        }
+                        _ => format!(
+                            r#"<c r="{reference}" t="inlineStr"><is><t>{cell}</t></is></c>"#
+                        ),
+                    });
+                }
+                data.push_str("</row>");
+            }
+            add(
+                &format!("xl/worksheets/sheet{n}.xml"),
+                format!(r#"<worksheet xmlns="{MAIN}"><sheetData>{data}</sheetData></worksheet>"#),
+            );
+        }
+        add(
+            "xl/workbook.xml",
+            format!(
+                r#"<workbook xmlns="{MAIN}" xmlns:r="{RELATIONSHIPS}"><sheets>{workbook}</sheets></workbook>"#
+            ),
+        );
+        add(
+            "xl/_rels/workbook.xml.rels",
+            format!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{relationships}</Relationships>"#
+            ),
+        );
+        add(
+            "xl/styles.xml",
+            format!(
+                r#"<styleSheet xmlns="{MAIN}"><cellXfs count="2"><xf numFmtId="0"/><xf numFmtId="14" applyNumberFormat="1"/></cellXfs></styleSheet>"#
+            ),
+        );
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn reads_spreadsheet_sheets() {
+        let path = test_util::temp_path("table.xlsx");
+        write_workbook(
+            &path,
+            &[
+                ("Info", &[&["Konto", "=123"]]),
+                (
+                    "Transaktioner",
+                    &[
+                        &["Transaktioner 2024"],
+                        &["Datum", "Antal", "Kurs", "Typ"],
+                        &["=d45293", "=3", "=12.5", " Köp "],
+                    ],
+                ),
+            ],
+        );
+        let header = ["Datum", "Antal", "Kurs", "Typ"];
+        let read = |sheet| TableBuilder::new().sheet(sheet).header(&header).read(&path);
+        // Without a sheet, the first sheet with the header row is read
+        let table = read(None).unwrap();
+        assert_eq!(table.line(0), 3);
+        let rows: Vec<Vec<String>> = table.deserialize().collect::<Result<_, _>>().unwrap();
+        assert_eq!(rows, [["2024-01-02", "3", "12.5", "Köp"]]);
+        assert_eq!(read(Some("Transaktioner")).unwrap().len(), 1);
+        assert!(matches!(read(Some("Info")), Err(TableError::NoHeader(_))));
+        assert!(matches!(
+            read(Some("Saknas")),
+            Err(TableError::NoSuchSheet(sheet)) if sheet == "Saknas"
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+//! Helpers for the tests of the importers and the lookups.
+
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use iced::futures::{StreamExt, channel::mpsc};
+
+use crate::{
+    ConversionProgress, ProgressSender,
+    pp::{self, Transaction},
+    symbols::{ExchangePreference, ResolverChain},
+    table::{Table, TableBuilder},
+};
+
+/// A progress sender whose messages are dropped. Must be used within a Tokio runtime.
+pub fn progress() -> ProgressSender {
+    let (sender, mut receiver) = mpsc::channel(1);
+    tokio::spawn(async move { while receiver.next().await.is_some() {} });
+    ProgressSender { sender }
+}
+
+/// A progress sender whose messages are kept in the returned receiver. At most `buffer` messages can
+/// be sent before the receiver is read.
+pub fn recording_progress(buffer: usize) -> (ProgressSender, mpsc::Receiver<ConversionProgress>) {
+    let (sender, receiver) = mpsc::channel(buffer);
+    (ProgressSender { sender }, receiver)
+}
+
+/// A resolver chain without resolvers, so that no security gets a ticker.
+pub fn no_symbols() -> ResolverChain<'static> {
+    ResolverChain::new(ExchangePreference::default())
+}
+
+/// Reads `contents` as a CSV file with the given delimiter and header columns.
+pub fn table(contents: &str, delimiter: u8, header: &[&str]) -> Table {
+    let path = temp_path("table.csv");
+    std::fs::write(&path, contents).unwrap();
+    let table = TableBuilder::new()
+        .delimiter(delimiter)
+        .header(header)
+        .read(&path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    table
+}
+
+/// A unique path in the temp directory, for a file named `name`.
+pub fn temp_path(name: &str) -> PathBuf {
+    static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    std::env::temp_dir().join(format!("pp-test-{}-{n}-{name}", std::process::id()))
+}
+
+/// An output that collects the written transactions.
+pub fn output() -> (pp::Output, Arc<Mutex<Vec<Transaction>>>) {
+    let transactions = Arc::new(Mutex::new(Vec::new()));
+    let mut output = pp::Output::default();
+    output.add(Collect(transactions.clone()));
+    (output, transactions)
+}
+
+struct Collect(Arc<Mutex<Vec<Transaction>>>);
+
+impl pp::Writer for Collect {
+    fn write(
+        &mut self,
+        transaction: &Transaction,
+        _source_line: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.0.lock().unwrap().push(transaction.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// A short description of a transaction, for comparisons: the date, the type, the value, the shares
+/// and the security.
+pub fn describe(transaction: &Transaction) -> String {
+    match transaction {
+        Transaction::Portfolio(t) => format!(
+            "{} {:?} {} {} {}",
+            t.date,
+            t.type_,
+            t.value,
+            t.shares.unwrap_or_default(),
+            t.security_name
+                .as_deref()
+                .or(t.isin.as_deref())
+                .unwrap_or_default()
+        ),
+        Transaction::Account(t) => format!(
+            "{} {:?} {} {}",
+            t.date,
+            t.type_,
+            t.value,
+            t.security_name.as_deref().unwrap_or_default()
+        ),
+    }
+}
+
+/// Serves one request per response, in order, and returns the base URL. Each request, with its
+/// headers and body, is passed to `check` before it is answered.
+pub fn stub_server(responses: Vec<String>, check: impl Fn(&str) + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            let complete = |request: &[u8]| {
+                let text = String::from_utf8_lossy(request);
+                let Some((headers, body)) = text.split_once("\r\n\r\n") else {
+                    return false;
+                };
+                let length = headers
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse().ok())?
+                    })
+                    .unwrap_or(0);
+                body.len() >= length
+            };
+            while !complete(&request) {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            check(&String::from_utf8_lossy(&request));
+            write!(stream, "{response}").unwrap();
+        }
+    });
+    format!("http://{address}")
+}
+
+/// A successful response with a JSON body, for [`stub_server()`].
+pub fn json_response(body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
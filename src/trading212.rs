@@ -0,0 +1,349 @@
+//! History exports from Trading 212.
+//!
+//! Amounts are in the account currency. For securities in another currency, the export gives the
+//! exchange rate as price currency per account currency, which is used for the gross amount in the
+//! security's currency and for converting the withholding tax on dividends.
+
+use anyhow::Context;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::pp;
+use crate::table::Table;
+use crate::types::{Currency, dec_from_num_opt};
+use crate::{ProgressSender, symbols::ResolverChain};
+
+pub const DELIMITER: u8 = b',';
+
+/// Columns that identify the header row of a Trading 212 export.
+pub const HEADER: &[&str] = &["Action", "Time", "ISIN", "No. of shares"];
+
+const ACCOUNT: &str = "Trading 212";
+
+#[derive(Debug, Deserialize)]
+struct Trading212Transaction {
+    #[serde(rename = "Action")]
+    action: String,
+    #[serde(rename = "Time")]
+    time: String,
+    #[serde(rename = "ISIN")]
+    isin: Option<String>,
+    #[serde(rename = "Ticker")]
+    ticker: Option<String>,
+    #[serde(rename = "Name")]
+    name: Option<String>,
+    #[serde(rename = "No. of shares", deserialize_with = "dec_from_num_opt")]
+    shares: Option<Decimal>,
+    #[serde(rename = "Currency (Price / share)")]
+    price_currency: Option<Currency>,
+    /// Units of the price currency per unit of the account currency
+    #[serde(rename = "Exchange rate", deserialize_with = "dec_from_num_opt")]
+    exchange_rate: Option<Decimal>,
+    #[serde(rename = "Total", deserialize_with = "dec_from_num_opt")]
+    total: Option<Decimal>,
+    #[serde(rename = "Currency (Total)")]
+    total_currency: Currency,
+    #[serde(
+        rename = "Withholding tax",
+        deserialize_with = "dec_from_num_opt",
+        default
+    )]
+    withholding_tax: Option<Decimal>,
+    #[serde(rename = "Currency (Withholding tax)", default)]
+    withholding_tax_currency: Option<Currency>,
+    #[serde(
+        rename = "Currency conversion fee",
+        deserialize_with = "dec_from_num_opt",
+        default
+    )]
+    fx_fee: Option<Decimal>,
+    #[serde(
+        rename = "Stamp duty reserve tax",
+        deserialize_with = "dec_from_num_opt",
+        default
+    )]
+    stamp_duty: Option<Decimal>,
+    #[serde(
+        rename = "French transaction tax",
+        deserialize_with = "dec_from_num_opt",
+        default
+    )]
+    french_tax: Option<Decimal>,
+    #[serde(rename = "Notes", default)]
+    notes: Option<String>,
+}
+
+pub async fn convert(
+    table: Table,
+    symbols: &ResolverChain<'_>,
+    writer: &mut pp::Output,
+    mut progress: ProgressSender,
+) -> anyhow::Result<()> {
+    let isins = table
+        .deserialize::<Trading212Transaction>()
+        .filter_map(|line| line.ok()?.isin)
+        .filter(|isin| !isin.is_empty());
+    symbols.prefetch(isins, &mut progress).await;
+    progress.total(table.len()).await;
+    progress.count(0).await;
+    let mut read_records = 0;
+    for (i, line) in table.deserialize().enumerate() {
+        let line: Trading212Transaction = line?;
+        writer.set_source_line(table.line(i));
+        read_records += 1;
+        progress.count(read_records).await;
+
+        let date = line.time.get(..10).unwrap_or(&line.time).to_owned();
+        let action = line.action.to_lowercase();
+        let total = line.total.unwrap_or_default().abs();
+        let fees = line.fx_fee.map(|f| f.abs()).filter(|f| !f.is_zero());
+        let taxes = [line.stamp_duty, line.french_tax]
+            .into_iter()
+            .flatten()
+            .map(|t| t.abs())
+            .reduce(|a, b| a + b)
+            .filter(|t| !t.is_zero());
+
+        let mut ticker_symbol = line.ticker.clone().filter(|t| !t.is_empty());
+        let mut security_name = line.name.clone().filter(|n| !n.is_empty());
+        let isin = line.isin.clone().filter(|i| !i.is_empty());
+        if let Some(isin) = &isin
+            && let Some(security) = symbols
+                .resolve(
+                    isin,
+                    ACCOUNT,
+                    line.price_currency.as_ref().map(Currency::as_str),
+                )
+                .await?
+        {
+            ticker_symbol = Some(security.symbol);
+            if security_name.is_none() {
+                security_name = Some(security.name);
+            }
+        }
+
+        // Trading 212 gives the rate as price currency per account currency, which is the same way
+        // round as Portfolio Performance wants it.
+        let foreign_rate = match (&line.price_currency, line.exchange_rate) {
+            (Some(price_currency), Some(rate))
+                if *price_currency != line.total_currency && !rate.is_zero() =>
+            {
+                Some((price_currency.clone(), rate))
+            }
+            _ => None,
+        };
+
+        let transaction = if action.contains("buy") || action.contains("sell") {
+            let is_buy = action.contains("buy");
+            // The gross amount is the value of the shares, without fees and taxes
+            let costs = fees.unwrap_or_default() + taxes.unwrap_or_default();
+            let gross = if is_buy { total - costs } else { total + costs };
+            let (gross_amount, currency_gross_amount, exchange_rate) = match foreign_rate {
+                Some((currency, rate)) => {
+                    (Some((gross * rate).round_dp(2)), Some(currency), Some(rate))
+                }
+                None => (None, None, None),
+            };
+            pp::Transaction::Portfolio(pp::PortfolioTransaction {
+                date,
+                securities_account: Some(ACCOUNT.to_owned()),
+                cash_account: Some(ACCOUNT.to_owned()),
+                type_: if is_buy {
+                    pp::PortfolioType::Buy
+                } else {
+                    pp::PortfolioType::Sell
+                },
+                value: total,
+                transaction_currency: line.total_currency,
+                gross_amount,
+                currency_gross_amount,
+                exchange_rate,
+                fees,
+                taxes,
+                shares: line.shares.map(|s| s.abs()),
+                isin,
+                wkn: None,
+                ticker_symbol,
+                security_name,
+                note: line.notes.filter(|n| !n.is_empty()),
+            })
+        } else if action.starts_with("dividend") {
+            // The withholding tax is often in the currency of the security, not of the account
+            let foreign_tax = match (line.withholding_tax, &line.withholding_tax_currency) {
+                (Some(tax), Some(tax_currency))
+                    if *tax_currency != line.total_currency && !tax.is_zero() =>
+                {
+                    let rate = line
+                        .exchange_rate
+                        .filter(|r| !r.is_zero())
+                        .with_context(|| {
+                            format!(
+                                "Växelkurs saknas för källskatten {} {}",
+                                date,
+                                security_name.as_deref().unwrap_or_default()
+                            )
+                        })?;
+                    Some((tax.abs(), tax_currency.clone(), rate))
+                }
+                _ => None,
+            };
+            let withholding_tax = match &foreign_tax {
+                Some((tax, _, rate)) => Some((tax / rate).round_dp(2)),
+                None => line.withholding_tax.map(|t| t.abs()),
+            }
+            .filter(|t| !t.is_zero());
+            // The gross dividend in the security's currency is given when the tax is converted
+            let (gross_amount, currency_gross_amount, exchange_rate) = match foreign_tax {
+                Some((tax, currency, rate)) => (
+                    Some((total * rate + tax).round_dp(2)),
+                    Some(currency),
+                    Some(rate),
+                ),
+                None => (None, None, None),
+            };
+            pp::Transaction::Account(pp::AccountTransaction {
+                date,
+                cash_account: ACCOUNT.to_owned(),
+                securities_account: Some(ACCOUNT.to_owned()),
+                type_: pp::AccountType::Dividend,
+                value: total,
+                transaction_currency: line.total_currency,
+                gross_amount,
+                currency_gross_amount,
+                exchange_rate,
+                fees,
+                taxes: withholding_tax,
+                shares: line.shares.map(|s| s.abs()),
+                isin,
+                wkn: None,
+                ticker_symbol,
+                security_name,
+                note: Some(line.action),
+            })
+        } else {
+            let type_ = match action.as_str() {
+                "deposit" => pp::AccountType::Deposit,
+                "withdrawal" => pp::AccountType::Removal,
+                "interest on cash" | "lending interest" => pp::AccountType::Interest,
+                _ => {
+                    progress
+                        .log(format!(
+                            "Hoppar över {} {} {}",
+                            date,
+                            line.action,
+                            line.name.unwrap_or_default()
+                        ))
+                        .await;
+                    continue;
+                }
+            };
+            pp::Transaction::Account(pp::AccountTransaction {
+                date,
+                cash_account: ACCOUNT.to_owned(),
+                securities_account: None,
+                type_,
+                value: total,
+                transaction_currency: line.total_currency,
+                gross_amount: None,
+                currency_gross_amount: None,
+                exchange_rate: None,
+                fees: None,
+                taxes: None,
+                shares: None,
+                isin: None,
+                wkn: None,
+                ticker_symbol: None,
+                security_name: None,
+                note: line.notes.filter(|n| !n.is_empty()),
+            })
+        };
+        writer.write(&transaction)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use super::*;
+    use crate::test_util;
+
+    const COLUMNS: &str = "Action,Time,ISIN,Ticker,Name,No. of shares,Price / share,Currency (Price / share),Exchange rate,Total,Currency (Total),Withholding tax,Currency (Withholding tax),Currency conversion fee,Notes\n";
+
+    async fn convert_rows(contents: &str) -> anyhow::Result<Vec<pp::Transaction>> {
+        let table = test_util::table(&format!("{COLUMNS}{contents}"), DELIMITER, HEADER);
+        let (mut output, transactions) = test_util::output();
+        convert(
+            table,
+            &test_util::no_symbols(),
+            &mut output,
+            test_util::progress(),
+        )
+        .await?;
+        Ok(transactions.lock().unwrap().clone())
+    }
+
+    #[tokio::test]
+    async fn converts_trades_and_dividends() {
+        let transactions = convert_rows(
+            "Deposit,2024-01-01 10:00:00,,,,,,,,1000.00,EUR,,,,\n\
+             Market buy,2024-01-02 15:30:00,US0378331005,AAPL,Apple,2,180.00,USD,1.08,334.83,EUR,,,0.50,\n\
+             Dividend (Dividend),2024-02-15 12:00:00,US0378331005,AAPL,Apple,2,0.24,USD,1.08,0.38,EUR,0.07,USD,,\n\
+             Market sell,2024-03-01 15:30:00,IE00B4L5Y983,IWDA,iShares Core MSCI World,1,90.00,EUR,1.00,90.00,EUR,,,,\n\
+             Spending cashback,2024-03-02 10:00:00,,,,,,,,1.00,EUR,,,,\n",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            transactions
+                .iter()
+                .map(test_util::describe)
+                .collect::<Vec<_>>(),
+            [
+                "2024-01-01 Deposit 1000.00 ",
+                "2024-01-02 Buy 334.83 2 Apple",
+                "2024-02-15 Dividend 0.38 Apple",
+                "2024-03-01 Sell 90.00 1 iShares Core MSCI World",
+            ]
+        );
+        let pp::Transaction::Portfolio(buy) = &transactions[1] else {
+            panic!("Not a buy");
+        };
+        assert_eq!(buy.gross_amount, Some(dec!(361.08)));
+        assert_eq!(buy.currency_gross_amount, Some(Currency::new("USD")));
+        assert_eq!(buy.exchange_rate, Some(dec!(1.08)));
+        assert_eq!(buy.fees, Some(dec!(0.50)));
+        let pp::Transaction::Account(dividend) = &transactions[2] else {
+            panic!("Not a dividend");
+        };
+        assert_eq!(dividend.taxes, Some(dec!(0.06)));
+        assert_eq!(dividend.gross_amount, Some(dec!(0.48)));
+        assert_eq!(dividend.currency_gross_amount, Some(Currency::new("USD")));
+        // Same currency: no gross amount at all
+        let pp::Transaction::Portfolio(sell) = &transactions[3] else {
+            panic!("Not a sale");
+        };
+        assert_eq!(
+            (
+                sell.gross_amount,
+                &sell.currency_gross_amount,
+                sell.exchange_rate
+            ),
+            (None, &None, None)
+        );
+    }
+
+    #[tokio::test]
+    async fn requires_rate_for_foreign_withholding_tax() {
+        let error = convert_rows(
+            "Dividend (Dividend),2024-02-15 12:00:00,US0378331005,AAPL,Apple,2,0.24,USD,Not available,0.38,EUR,0.07,USD,,\n",
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Växelkurs saknas för källskatten 2024-02-15 Apple"
+        );
+    }
+}
@@ -1,12 +1,23 @@
 use std::ops::Deref;
 
+use chrono::{NaiveDate, NaiveDateTime};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Deserializer, Serialize};
 
 /// The name of a currency
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Currency(String);
 
+impl Currency {
+    pub fn new(name: impl Into<String>) -> Self {
+        Currency(name.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
 #[derive(Debug)]
 pub struct CommaDec(Decimal);
 
@@ -44,7 +55,8 @@ where
     if s.is_empty() {
         return Ok(None);
     }
-    let s = s.replacen(",", ".", 1);
+    // Thousands separators are spaces, often non-breaking
+    let s = s.replace([' ', '\u{a0}'], "").replacen(",", ".", 1);
     Ok(Some(
         Decimal::from_str_exact(&s).map_err(serde::de::Error::custom)?,
     ))
@@ -58,3 +70,119 @@ where
     dec_from_swe_num_opt(deserializer)
         .and_then(|r| r.ok_or(serde::de::Error::custom("Empty number")))
 }
+
+/// Parses a number with `.` as the decimal separator, as in English exports. Currency signs, currency
+/// codes and thousands separators (`,`) are ignored, e.g. `-€1,234.50`. Exponents are accepted, as in
+/// `1.5e-7`.
+pub fn dec_from_num_opt<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    // The sign can come before the currency sign
+    let negative = s.trim_start().starts_with('-');
+    let number: String = s
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit() && *c != '.')
+        .take_while(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | 'e' | 'E' | '-' | '+'))
+        .filter(|c| *c != ',')
+        .collect();
+    // An `E` can also be the start of a currency code, as in `1.5 EUR`
+    let number = number.trim_end_matches(['e', 'E']);
+    if number.is_empty() {
+        return Ok(None);
+    }
+    let value = if number.contains(['e', 'E']) {
+        Decimal::from_scientific(number)
+    } else {
+        Decimal::from_str_exact(number)
+    }
+    .map_err(serde::de::Error::custom)?;
+    Ok(Some(if negative { -value } else { value }))
+}
+
+/// Whether `isin` is a well-formed ISIN: two letters for the country, nine letters or digits and a check
+/// digit that matches.
+pub fn is_isin(isin: &str) -> bool {
+    let bytes = isin.as_bytes();
+    if bytes.len() != 12
+        || !bytes[..2].iter().all(u8::is_ascii_uppercase)
+        || !bytes[2..11]
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        || !bytes[11].is_ascii_digit()
+    {
+        return false;
+    }
+    // Letters count as two digits, A = 10 to Z = 35, and the digits are checked with the Luhn algorithm
+    let digits: Vec<u32> = isin
+        .chars()
+        .flat_map(|c| {
+            let value = c.to_digit(36).unwrap();
+            if value < 10 {
+                vec![value]
+            } else {
+                vec![value / 10, value % 10]
+            }
+        })
+        .collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| {
+            if i % 2 == 1 {
+                d * 2 / 10 + d * 2 % 10
+            } else {
+                d
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Changes a date in any of the formats that Swedish banks export, e.g. `2024-01-31`, `20240131` or
+/// `31.01.2024`, possibly followed by a time, to an ISO date. Returns `None` for other formats.
+pub fn iso_date(date: &str) -> Option<String> {
+    let date = date.trim();
+    ["%Y-%m-%d", "%Y%m%d", "%d.%m.%Y", "%d/%m/%Y", "%Y/%m/%d"]
+        .iter()
+        .find_map(|format| {
+            NaiveDate::parse_from_str(date, format).ok().or_else(|| {
+                ["%H:%M", "%H:%M:%S"].iter().find_map(|time| {
+                    NaiveDateTime::parse_from_str(date, &format!("{format} {time}"))
+                        .ok()
+                        .map(|dt| dt.date())
+                })
+            })
+        })
+        .map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_isins() {
+        assert!(is_isin("SE0000108656"));
+        assert!(is_isin("US0378331005"));
+        assert!(is_isin("IE00B3RBWM25"));
+        assert!(!is_isin("SE0000108657"));
+        assert!(!is_isin("se0000108656"));
+        assert!(!is_isin("SE000010865"));
+        assert!(!is_isin("ERIC-B.ST"));
+    }
+
+    #[test]
+    fn parses_english_numbers() {
+        let parse = |s: &str| {
+            dec_from_num_opt(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(s))
+                .unwrap()
+        };
+        assert_eq!(parse("-€1,234.50"), Some(Decimal::new(-123450, 2)));
+        assert_eq!(parse("1.5e-7"), Some(Decimal::new(15, 8)));
+        assert_eq!(parse("2.5 EUR"), Some(Decimal::new(25, 1)));
+        assert_eq!(parse(""), None);
+    }
+}
@@ -0,0 +1,358 @@
+//! Historical prices from the chart endpoint of Yahoo Finance, written as CSV files that Portfolio
+//! Performance can import as historical quotes. Meant for securities that lack a working quote feed in
+//! Portfolio Performance.
+
+use anyhow::{Context, anyhow};
+use chrono::{DateTime, NaiveDate, Utc};
+use rand::Rng;
+use rust_decimal::{Decimal, prelude::FromPrimitive};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::time::{Duration, Instant, sleep};
+
+use crate::{
+    ProgressSender,
+    lookup::{self, Cache, CacheEntry, CacheSettings},
+    pp,
+};
+
+pub const DEFAULT_BASE_URL: &str = "https://query2.finance.yahoo.com";
+
+/// Downloads price history for tickers, with a cache of its own next to the symbol cache.
+///
+/// [`Self::save_cache()`] must be called to keep the downloaded prices.
+pub(crate) struct YahooPrices {
+    base_url: String,
+    cache_path: PathBuf,
+    cache: Cache<PriceHistory>,
+    recovered: Option<lookup::Recovered>,
+    /// For rate limiting
+    last_fetch: Option<Instant>,
+}
+
+/// The prices of a ticker, as cached.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct PriceHistory {
+    quotes: Vec<Quote>,
+    updated_at: DateTime<Utc>,
+}
+
+impl CacheEntry for PriceHistory {
+    fn updated_at(&self) -> DateTime<Utc> {
+        self.updated_at
+    }
+
+    fn describe(&self) -> String {
+        format!("{} kurser", self.quotes.len())
+    }
+}
+
+/// A day in the price history. The field names are the column names that Portfolio Performance expects.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct Quote {
+    #[serde(rename = "Date")]
+    pub(crate) date: NaiveDate,
+    #[serde(rename = "Close")]
+    pub(crate) close: Decimal,
+    #[serde(rename = "High")]
+    pub(crate) high: Option<Decimal>,
+    #[serde(rename = "Low")]
+    pub(crate) low: Option<Decimal>,
+    #[serde(rename = "Volume")]
+    pub(crate) volume: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChartResponse {
+    chart: Chart,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chart {
+    result: Option<Vec<ChartResult>>,
+    error: Option<ChartError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChartError {
+    description: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChartResult {
+    meta: ChartMeta,
+    /// Missing when there is no history
+    #[serde(default)]
+    timestamp: Vec<i64>,
+    indicators: Indicators,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChartMeta {
+    /// Offset of the exchange time zone, in seconds
+    #[serde(default)]
+    gmtoffset: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Indicators {
+    quote: Vec<QuoteIndicator>,
+}
+
+/// Values by day, with `null` for days without trading
+#[derive(Debug, Deserialize)]
+struct QuoteIndicator {
+    #[serde(default)]
+    close: Vec<Option<f64>>,
+    #[serde(default)]
+    high: Vec<Option<f64>>,
+    #[serde(default)]
+    low: Vec<Option<f64>>,
+    #[serde(default)]
+    volume: Vec<Option<f64>>,
+}
+
+impl YahooPrices {
+    /// Reads the cache like [`lookup::LookupCache::load()`], so a cache file that is not valid is moved
+    /// aside, see [`Self::recovered()`].
+    pub fn new(base_url: &str, cache: &CacheSettings) -> anyhow::Result<Self> {
+        Self::with_cache_path(base_url, cache.path(lookup::PRICE_CACHE))
+    }
+
+    fn with_cache_path(base_url: &str, cache_path: PathBuf) -> anyhow::Result<Self> {
+        let (cache, recovered) = lookup::load_cache(&cache_path)?;
+        Ok(YahooPrices {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            cache_path,
+            cache,
+            recovered,
+            last_fetch: None,
+        })
+    }
+
+    /// Where the cache file was moved, and why, if it could not be read when loading.
+    pub fn recovered(&self) -> Option<(&Path, &Path, &str)> {
+        self.recovered
+            .as_ref()
+            .map(|(aside, reason)| (self.cache_path.as_path(), aside.as_path(), reason.as_str()))
+    }
+
+    /// Returns the daily prices of the ticker. Prices that were downloaded today are taken from the cache.
+    pub async fn history(&mut self, ticker: &str) -> anyhow::Result<&[Quote]> {
+        let is_fresh = self
+            .cache
+            .entries
+            .get(ticker)
+            .is_some_and(|h| h.updated_at.date_naive() == Utc::now().date_naive());
+        if !is_fresh {
+            if let Some(last) = self.last_fetch {
+                sleep(Duration::from_millis(500).saturating_sub(last.elapsed())).await;
+            }
+            self.last_fetch = Some(Instant::now());
+            let quotes = self.fetch(ticker).await?;
+            self.cache.entries.insert(
+                ticker.to_owned(),
+                PriceHistory {
+                    quotes,
+                    updated_at: Utc::now(),
+                },
+            );
+        }
+        // unwrap: Inserted above if missing
+        Ok(&self.cache.entries.get(ticker).unwrap().quotes)
+    }
+
+    async fn fetch(&self, ticker: &str) -> anyhow::Result<Vec<Quote>> {
+        let mut url = reqwest::Url::parse(&self.base_url).context("Bad Yahoo Finance URL")?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Bad Yahoo Finance URL"))?
+            .pop_if_empty()
+            .extend(["v8", "finance", "chart", ticker]);
+        url.query_pairs_mut()
+            .append_pair("period1", "0")
+            .append_pair("period2", &Utc::now().timestamp().to_string())
+            .append_pair("interval", "1d")
+            .append_pair("events", "history");
+
+        let r = rand::rng().random_range(100000..=999999);
+        let client = reqwest::ClientBuilder::new()
+            .user_agent(format!("Mozilla/5.0 ({r})"))
+            .build()?;
+        let resp = client.get(url).send().await?.text().await?;
+        let resp: ChartResponse =
+            serde_json::from_str(&resp).context("Unexpected answer from Yahoo Finance")?;
+        if let Some(error) = resp.chart.error {
+            anyhow::bail!(error.description);
+        }
+        let result = resp
+            .chart
+            .result
+            .and_then(|r| r.into_iter().next())
+            .context("No prices in answer from Yahoo Finance")?;
+        let Some(indicator) = result.indicators.quote.into_iter().next() else {
+            return Ok(Vec::new());
+        };
+
+        let value = |values: &[Option<f64>], i: usize| {
+            values
+                .get(i)
+                .copied()
+                .flatten()
+                .and_then(Decimal::from_f64)
+                .map(|v| v.round_dp(6).normalize())
+        };
+        let mut quotes = Vec::new();
+        for (i, timestamp) in result.timestamp.iter().enumerate() {
+            let Some(close) = value(&indicator.close, i) else {
+                continue;
+            };
+            let Some(date) = DateTime::from_timestamp(timestamp + result.meta.gmtoffset, 0) else {
+                continue;
+            };
+            quotes.push(Quote {
+                date: date.date_naive(),
+                close,
+                high: value(&indicator.high, i),
+                low: value(&indicator.low, i),
+                volume: indicator.volume.get(i).copied().flatten().map(|v| v as u64),
+            });
+        }
+        // The current day can occur twice, while it is still trading
+        quotes.dedup_by(|b, a| a.date == b.date);
+        Ok(quotes)
+    }
+
+    pub fn save_cache(&self) -> anyhow::Result<()> {
+        lookup::write_cache(&self.cache_path, &self.cache)
+    }
+}
+
+/// Downloads the prices of every security with a ticker in a securities file (`.pp-securities.csv`) and
+/// writes them to one CSV file per security, next to the securities file.
+pub async fn export(
+    securities_path: &Path,
+    base_url: &str,
+    cache: &CacheSettings,
+    mut progress: ProgressSender,
+) -> anyhow::Result<()> {
+    let mut tickers = pp::read_security_tickers(securities_path)?;
+    tickers.dedup_by(|a, b| a.0 == b.0);
+
+    let file_name = securities_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let base_name = file_name
+        .strip_suffix(".pp-securities.csv")
+        .or_else(|| file_name.strip_suffix(".csv"))
+        .unwrap_or(&file_name)
+        .to_owned();
+
+    let mut yahoo = YahooPrices::new(base_url, cache)?;
+    if let Some((path, aside, reason)) = yahoo.recovered() {
+        progress
+            .log(lookup::recovered_warning(path, aside, reason))
+            .await;
+    }
+    progress.total(tickers.len()).await;
+    progress.count(0).await;
+    for (i, (ticker, name)) in tickers.iter().enumerate() {
+        let name = name.as_deref().unwrap_or(ticker);
+        match yahoo.history(ticker).await {
+            Ok(quotes) => {
+                let path = securities_path.with_file_name(format!(
+                    "{base_name}.pp-prices-{}.csv",
+                    file_name_safe(ticker)
+                ));
+                write_quotes(&path, quotes)?;
+                progress
+                    .log(format!(
+                        "{name} ({ticker}): {} kurser -> {}",
+                        quotes.len(),
+                        path.display()
+                    ))
+                    .await;
+            }
+            Err(e) => {
+                progress
+                    .log(format!(
+                        "Kunde inte hämta kurser för {name} ({ticker}): {e:#}"
+                    ))
+                    .await;
+            }
+        }
+        progress.count(i + 1).await;
+    }
+    yahoo.save_cache()
+}
+
+fn write_quotes(path: &Path, quotes: &[Quote]) -> anyhow::Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b',')
+        .from_path(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    for quote in quotes {
+        writer.serialize(quote)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Tickers can contain characters such as `^` and `=` that are troublesome in file names.
+fn file_name_safe(ticker: &str) -> String {
+    ticker
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[tokio::test]
+    async fn reads_chart_and_caches_it() {
+        let body = r#"{"chart":{"result":[{"meta":{"gmtoffset":3600},
+            "timestamp":[1704182400,1704268800,1704355200],
+            "indicators":{"quote":[{"close":[310.5,null,312.25],"high":[311.0,null,313.0],
+            "low":[309.0,null,311.5],"volume":[1200,null,900]}]}}],"error":null}}"#;
+        let base_url = test_util::stub_server(vec![test_util::json_response(body)], |request| {
+            assert!(request.starts_with("GET /v8/finance/chart/ABB.ST?"))
+        });
+        let cache_path = std::env::temp_dir().join(format!(
+            "pp-conv-test-prices-{}.json",
+            rand::rng().random::<u32>()
+        ));
+        let mut yahoo = YahooPrices::with_cache_path(&base_url, cache_path.clone()).unwrap();
+        let quotes = yahoo.history("ABB.ST").await.unwrap().to_vec();
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(quotes[0].date, NaiveDate::from_ymd_opt(2024, 1, 2).unwrap());
+        assert_eq!(quotes[1].close, Decimal::new(31225, 2));
+        assert_eq!(quotes[1].volume, Some(900));
+
+        // The stub server only answers once, so this comes from the cache
+        yahoo.save_cache().unwrap();
+        let mut yahoo = YahooPrices::with_cache_path(&base_url, cache_path.clone()).unwrap();
+        assert_eq!(yahoo.history("ABB.ST").await.unwrap(), quotes.as_slice());
+        std::fs::remove_file(cache_path).ok();
+    }
+
+    #[test]
+    fn corrupt_cache_is_moved_aside() {
+        let cache_path = test_util::temp_path("yahoo_prices_cache.json");
+        std::fs::write(&cache_path, "{not json").unwrap();
+        let yahoo = YahooPrices::with_cache_path(DEFAULT_BASE_URL, cache_path.clone()).unwrap();
+        let (_, aside, reason) = yahoo.recovered().unwrap();
+        assert!(reason.starts_with("Bad cache file"));
+        assert_eq!(std::fs::read_to_string(aside).unwrap(), "{not json");
+        std::fs::remove_file(aside).unwrap();
+        assert!(!cache_path.exists());
+    }
+}
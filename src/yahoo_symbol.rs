@@ -1,77 +1,26 @@
-use anyhow::Context;
-use chrono::Utc;
+use std::sync::Arc;
+
 use rand::Rng;
-use serde::{Deserialize, Serialize};
-use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufReader, BufWriter},
-    ops::Deref,
-    path::PathBuf,
-    sync::{
-        LazyLock,
-        atomic::{self, AtomicBool},
+use serde::Deserialize;
+use tokio::time::Duration;
+
+use crate::{
+    ProgressSender,
+    lookup::{
+        CacheSettings, CachedLookups, LookupCache, LookupError, RateLimiter, Security,
+        SecurityEntry, fetch_with_retry,
     },
+    symbols::{ListingsFuture, SymbolResolver, fold, name_key},
 };
-use tokio::{
-    sync::{Mutex, RwLock, RwLockReadGuard},
-    time::{Duration, Instant, sleep},
-};
-
-use crate::ProgressSender;
-
-// TODO: Store dir path in main
-static CACHE_FILENAME: LazyLock<PathBuf> = LazyLock::new(|| {
-    dirs::cache_dir()
-        .unwrap()
-        .join("portfolio-performance-conv")
-        .join("yahoo_cache.json")
-});
 
 /// Performs lookups towards Yahoo Finance.
 ///
 /// Results are cached for a number of days, to avoid unnecessary calls to Yahoo.
 /// [`Self::save_cache()`] **must be called** before dropping [`Yahoo`], to save the cache.
 pub(crate) struct Yahoo {
-    cache: RwLock<Cache>,
-    cache_is_dirty: AtomicBool,
-    /// For rate limiting
-    last_fetch: Mutex<Option<Instant>>,
-    progress: Option<Mutex<ProgressSender>>,
-}
-
-#[derive(Debug, Deserialize, Serialize, Default)]
-struct Cache {
-    entries: HashMap<String, IsinLookup>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub(crate) struct Security {
-    pub(crate) symbol: String,
-    pub(crate) exchange: String,
-    /// The name is often messy. Only use it as a fallback.
-    pub(crate) name: String,
-}
-
-#[derive(Debug)]
-pub(crate) struct SecurityEntry<'c> {
-    isin: &'c str,
-    rcache: RwLockReadGuard<'c, Cache>,
-}
-
-impl<'c> Deref for SecurityEntry<'c> {
-    type Target = Vec<Security>;
-
-    fn deref(&self) -> &Self::Target {
-        // unwrap: Value was found to exist in the cache and cannot be removed as Self is holding a lock.
-        &self.rcache.entries.get(self.isin).unwrap().securities
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct IsinLookup {
-    securities: Vec<Security>,
-    updated_at: chrono::DateTime<Utc>,
+    lookups: CachedLookups,
+    /// Searches by name, which are kept apart from the lookups by ISIN
+    name_lookups: CachedLookups,
 }
 
 #[derive(Debug, Deserialize)]
@@ -83,151 +32,162 @@ struct YahooResponse {
 #[derive(Debug, Deserialize)]
 struct YahooQuote {
     /// Often cut off or has `"` at the end
-    shortname: String,
-    // /// Tend to miss umlaut characters
-    // longname: Option<String>,
+    shortname: Option<String>,
+    /// Tend to miss umlaut characters
+    longname: Option<String>,
     exchange: String,
     symbol: String,
+    #[serde(rename = "quoteType")]
+    quote_type: Option<String>,
+    #[serde(rename = "typeDisp")]
+    type_display: Option<String>,
+    #[serde(rename = "exchDisp")]
+    exchange_display: Option<String>,
 }
 
 impl Yahoo {
-    pub fn new() -> Self {
-        let cache = RwLock::new(read_cache());
-        Yahoo {
-            last_fetch: Mutex::new(None),
-            cache,
-            cache_is_dirty: AtomicBool::new(false),
-            progress: None,
-        }
+    pub fn new(cache: &CacheSettings) -> anyhow::Result<Self> {
+        let rate_limiter = Arc::new(RateLimiter::new(4, Duration::from_millis(500)));
+        Ok(Yahoo {
+            lookups: CachedLookups::new(
+                "Yahoo Finance",
+                LookupCache::load(cache.path("yahoo"), cache.ttl)?,
+                rate_limiter.clone(),
+            ),
+            name_lookups: CachedLookups::new(
+                "Yahoo Finance",
+                LookupCache::load(cache.path("yahoo_names"), cache.ttl)?,
+                rate_limiter,
+            ),
+        })
+    }
+
+    pub fn new_with_progress(
+        cache: &CacheSettings,
+        progress: ProgressSender,
+    ) -> anyhow::Result<Self> {
+        let mut y = Self::new(cache)?;
+        y.lookups.set_progress(progress.clone());
+        y.name_lookups.set_progress(progress);
+        Ok(y)
     }
 
-    pub fn new_with_progress(progress: ProgressSender) -> Self {
-        let mut y = Self::new();
-        y.progress = Some(Mutex::new(progress));
-        y
+    pub fn caches(&self) -> [&LookupCache; 2] {
+        [self.lookups.cache(), self.name_lookups.cache()]
+    }
+
+    /// See [`CachedLookups::set_offline()`].
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.lookups.set_offline(offline);
+        self.name_lookups.set_offline(offline);
+        self
     }
 }
 
 impl Yahoo {
     /// Looks up the Yahoo ticker symbol(s) for the given ISIN. Multiple symbols can be returned if the security is
     /// available at multiple exchanges. The returned vec is empty if no symbols are found.
-    pub async fn isin_to_symbols<'c>(&'c self, isin: &'c str) -> anyhow::Result<SecurityEntry<'c>> {
-        {
-            let rcache = self.cache.read().await;
-            let lookup = rcache.entries.get(isin);
-            if let Some(lookup) = lookup {
-                if Utc::now() - lookup.updated_at < chrono::TimeDelta::days(5) {
-                    return Ok(SecurityEntry { isin, rcache });
-                }
-            }
-        }
-
-        self.log(format!("Hämtar symbol for {isin} från Yahoo Finance:"))
-            .await;
-
-        let lookup = self.rate_limit_fetch(isin).await?;
-
-        if lookup.securities.is_empty() {
-            self.log("Ingen träff").await;
-        } else {
-            self.log(format!(
-                "{}",
-                lookup
-                    .securities
-                    .iter()
-                    .map(|s| s.symbol.as_str())
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            ))
-            .await;
-        }
-
-        let mut wcache = self.cache.write().await;
-        wcache.entries.insert(isin.to_owned(), lookup);
-        self.cache_is_dirty.store(true, atomic::Ordering::Relaxed);
-
-        let rcache = wcache.downgrade();
-        Ok(SecurityEntry {
-            isin: isin,
-            rcache: rcache,
-        })
+    pub async fn isin_to_symbols<'c>(
+        &'c self,
+        isin: &'c str,
+    ) -> Result<SecurityEntry<'c>, LookupError> {
+        self.lookups.get(isin, isin, fetch_securities(isin)).await
     }
 
-    async fn log(&self, msg: impl Into<String>) {
-        if let Some(ref progress) = self.progress {
-            progress.lock().await.log(msg).await;
-        }
-    }
-
-    async fn rate_limit_fetch(&self, isin: &str) -> anyhow::Result<IsinLookup> {
-        // Download rate limit
-        let now = Instant::now();
-        let mut last_fetch = self.last_fetch.lock().await;
-        if let Some(last) = *last_fetch {
-            let passed = last - now;
-            let wait = Duration::from_millis(500) - passed;
-            sleep(wait).await;
-        }
-        *last_fetch = Some(now);
-        fetch_securities(isin).await
+    /// Searches for the Yahoo ticker symbol(s) of a security by its name. The search is cached under
+    /// the name as given by [`name_key()`], so that all spellings of the name share it.
+    pub async fn name_to_symbols(&self, name: &str) -> Result<Vec<Security>, LookupError> {
+        let key = name_key(name);
+        Ok(self
+            .name_lookups
+            .get(&key, name, fetch_securities(name))
+            .await?
+            .to_vec())
     }
 
     /// Saves the internal cache.
     ///
     /// A separate function is required since async drop is not supported.
     pub async fn save_cache(&self) -> anyhow::Result<()> {
-        let rcache = self.cache.read().await;
-        write_cache(&rcache)?;
-        self.cache_is_dirty.store(false, atomic::Ordering::Relaxed);
-        Ok(())
+        self.lookups.save_cache().await?;
+        self.name_lookups.save_cache().await
     }
 }
 
-impl Drop for Yahoo {
-    fn drop(&mut self) {
-        if self.cache_is_dirty.load(atomic::Ordering::Relaxed) {
-            println!("Implementation Error: Yahoo finance cache was not saved! Call save_cache().");
-        }
+impl SymbolResolver for Yahoo {
+    fn listings<'a>(&'a self, isin: &'a str) -> ListingsFuture<'a> {
+        Box::pin(async move { Ok(self.isin_to_symbols(isin).await?.to_vec()) })
+    }
+
+    fn search<'a>(&'a self, name: &'a str) -> ListingsFuture<'a> {
+        Box::pin(self.name_to_symbols(name))
     }
 }
 
-async fn fetch_securities(isin: &str) -> anyhow::Result<IsinLookup> {
+async fn fetch_securities(query: &str) -> Result<Vec<Security>, LookupError> {
     let r = rand::rng().random_range(100000..=999999);
     let user_agent = format!("Mozilla/5.0 ({r})");
     let client = reqwest::ClientBuilder::new()
         .user_agent(user_agent)
         .build()?;
-    let resp = client.get(format!("https://query2.finance.yahoo.com/v1/finance/search?q={isin}&lang=en-US&region=US&quotesCount=6&newsCount=3&listsCount=2&enableFuzzyQuery=false&quotesQueryId=tss_match_phrase_query&multiQuoteQueryId=multi_quote_single_token_query&newsQueryId=news_cie_vespa&enableCb=false&enableNavLinks=true&enableEnhancedTrivialQuery=true&enableResearchReports=true&enableCulturalAssets=true&enableLogoUrl=true&enableLists=false&recommendCount=5&enablePrivateCompany=true")).send().await?.text().await.unwrap();
+    let resp = fetch_with_retry(client.get("https://query2.finance.yahoo.com/v1/finance/search?lang=en-US&region=US&quotesCount=6&newsCount=3&listsCount=2&enableFuzzyQuery=false&quotesQueryId=tss_match_phrase_query&multiQuoteQueryId=multi_quote_single_token_query&newsQueryId=news_cie_vespa&enableCb=false&enableNavLinks=true&enableEnhancedTrivialQuery=true&enableResearchReports=true&enableCulturalAssets=true&enableLogoUrl=true&enableLists=false&recommendCount=5&enablePrivateCompany=true").query(&[("q", query)])).await?;
     let resp: YahooResponse = serde_json::from_str(&resp)?;
-    let now = chrono::Utc::now();
-    let securities: Vec<_> = resp
+    Ok(resp
         .quotes
         .into_iter()
         .map(|q| Security {
+            name: clean_name(q.shortname.as_deref(), q.longname.as_deref()),
             symbol: q.symbol,
             exchange: q.exchange,
-            name: q.shortname,
+            quote_type: q.quote_type,
+            long_name: q.longname,
+            type_display: q.type_display,
+            exchange_display: q.exchange_display,
+            ..Default::default()
         })
-        .collect();
-    Ok(IsinLookup {
-        securities,
-        updated_at: now,
-    })
+        .collect())
 }
 
-fn read_cache() -> Cache {
-    File::open(&*CACHE_FILENAME)
-        .map(|f| serde_json::from_reader(BufReader::new(f)).expect("Bad cache format"))
-        .unwrap_or_default()
+/// Makes a name from Yahoo's short and long names. The short name is often cut off and can end with a
+/// stray `"`, while the long name is complete but tends to miss umlauts. The long name is used, with
+/// the words that the short name spells with umlauts taken from the short name.
+///
+/// `Lansforsakringar Global Indexnara` and `Länsförsäkringar Global Indexnä"` give
+/// `Länsförsäkringar Global Indexnära`.
+fn clean_name(short_name: Option<&str>, long_name: Option<&str>) -> String {
+    let short_words = words(short_name.unwrap_or_default());
+    let Some(long_name) = long_name.filter(|n| !n.trim().is_empty()) else {
+        return short_words.join(" ");
+    };
+    let long_words = words(long_name);
+    long_words
+        .iter()
+        .enumerate()
+        .map(|(i, long_word)| {
+            let Some(short_word) = short_words.get(i) else {
+                return long_word.to_string();
+            };
+            let (short_folded, long_folded) = (fold(short_word), fold(long_word));
+            if short_folded == long_folded {
+                short_word.to_string()
+            } else if i == short_words.len() - 1 && long_folded.starts_with(&short_folded) {
+                // The short name is cut off in this word. fold() keeps the number of characters.
+                let rest: String = long_word.chars().skip(short_word.chars().count()).collect();
+                format!("{short_word}{rest}")
+            } else {
+                long_word.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
-fn write_cache(cache: &Cache) -> anyhow::Result<()> {
-    std::fs::create_dir_all(&*CACHE_FILENAME.parent().unwrap())
-        .context("Failed to create cache directory")?;
-    let f = File::create(&*CACHE_FILENAME).context("Failed to create Yahoo cache file")?;
-    let writer = BufWriter::new(f);
-    serde_json::to_writer_pretty(writer, &cache).unwrap();
-    Ok(())
+/// The words of a name, without stray quotes.
+fn words(name: &str) -> Vec<&str> {
+    name.split_whitespace()
+        .map(|word| word.trim_matches('"'))
+        .filter(|word| !word.is_empty())
+        .collect()
 }
 
 #[cfg(test)]
@@ -236,7 +196,7 @@ mod tests {
 
     #[tokio::test]
     async fn isin_to_symbol() {
-        let y = Yahoo::new();
+        let y = Yahoo::new(&CacheSettings::default()).unwrap();
         let sec = y.isin_to_symbols("NO0010827280").await.unwrap();
         assert_eq!(sec[0].symbol, "0P0001Q6FC.ST");
         assert_eq!(sec[0].exchange, "STO");
@@ -251,12 +211,35 @@ mod tests {
 
         // Make sure to make at least on non-cached look-up
         let mut y = y;
-        y.cache.get_mut().entries.clear();
+        y.lookups.cache_mut().clear();
         let sec = y.isin_to_symbols("SE0000671919").await.unwrap();
         assert_eq!(sec[0].symbol, "0P00000LST.ST");
         assert_eq!(sec[0].exchange, "STO");
         drop(sec);
     }
 
+    #[test]
+    fn cleans_names() {
+        assert_eq!(
+            clean_name(
+                Some("Länsförsäkringar Global Indexnä\""),
+                Some("Lansforsakringar Global Indexnara")
+            ),
+            "Länsförsäkringar Global Indexnära"
+        );
+        assert_eq!(
+            clean_name(
+                Some("Handelsbanken Sverige Index Cri"),
+                Some("Handelsbanken Sverige Index Criteria A1 SEK")
+            ),
+            "Handelsbanken Sverige Index Criteria A1 SEK"
+        );
+        assert_eq!(clean_name(Some("ERICSSON B\""), None), "ERICSSON B");
+        assert_eq!(
+            clean_name(None, Some("Telefonaktiebolaget LM Ericsson (publ)")),
+            "Telefonaktiebolaget LM Ericsson (publ)"
+        );
+    }
+
     // TODO: Test cache file save and load
 }